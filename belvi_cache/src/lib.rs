@@ -1,45 +1,359 @@
 // SPDX-License-Identifier: Apache-2.0
-use log::trace;
-use redis_async::{client::paired, resp_array};
-use std::fmt;
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use log::{trace, warn};
+use memory::MemoryCache;
+use redis_async::{client::paired, error::Error as RedisError, resp_array};
+use std::{
+    env, fmt,
+    io::{Read, Write},
+    time::Duration,
+};
+
+pub mod memory;
 
 pub struct Connection {
-    inner: paired::PairedConnection,
+    inner: Backend,
+    /// How long certs are kept in the cache for, in seconds. `None` means forever.
+    cert_ttl: Option<u64>,
+    /// Whether new certs are compressed before being added to the cache.
+    compression: bool,
+}
+
+enum Backend {
+    Redis(paired::PairedConnection),
+    /// Used by [`Connection::in_memory`].
+    Memory(MemoryCache),
 }
 
 impl fmt::Debug for Connection {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Connection")
-            .field("inner", &"[redis connection]".to_string())
+            .field(
+                "inner",
+                &match &self.inner {
+                    Backend::Redis(_) => "[redis connection]".to_string(),
+                    Backend::Memory(cache) => format!("{:?}", cache),
+                },
+            )
+            .field("cert_ttl", &self.cert_ttl)
+            .field("compression", &self.compression)
             .finish()
     }
 }
 
 const OBJECT_PREFIX: &[u8] = b"o:";
+/// Prefix for the `extra_data` of log entries, keyed by its hash. Entries with the same chain
+/// share the same `extra_data`.
+const EXTRA_DATA_PREFIX: &[u8] = b"e:";
+/// Stored values starting with this byte are deflate-compressed.
+const COMPRESSED_MARKER: u8 = 0x01;
+/// Stored `extra_data` starting with this byte is uncompressed.
+const RAW_MARKER: u8 = 0x00;
+
+/// How a kind of value is stored, so compressed values can be told apart from uncompressed ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    /// Uncompressed values are stored as they are. This is used for certs, which are DER and so
+    /// always start with 0x30 (SEQUENCE), so they can't be confused with [`COMPRESSED_MARKER`].
+    Der,
+    /// Every value starts with [`RAW_MARKER`] or [`COMPRESSED_MARKER`]. This is used for
+    /// `extra_data`, which starts with a TLS length prefix and so can start with any byte.
+    Marked,
+}
+
+fn encode_value(content: &[u8], compression: bool, framing: Framing) -> Vec<u8> {
+    if compression {
+        let mut encoder = DeflateEncoder::new(vec![COMPRESSED_MARKER], Compression::default());
+        encoder.write_all(content).unwrap();
+        return encoder.finish().unwrap();
+    }
+    match framing {
+        Framing::Der => content.to_vec(),
+        Framing::Marked => [&[RAW_MARKER], content].concat(),
+    }
+}
+
+fn decode_value(mut stored: Vec<u8>, framing: Framing) -> Option<Vec<u8>> {
+    match (stored.first(), framing) {
+        (Some(&COMPRESSED_MARKER), _) => {
+            let mut content = Vec::new();
+            match DeflateDecoder::new(&stored[1..]).read_to_end(&mut content) {
+                Ok(_) => Some(content),
+                Err(e) => {
+                    warn!("failed to decompress cached value: {:?}", e);
+                    None
+                }
+            }
+        }
+        (_, Framing::Der) => Some(stored),
+        (Some(&RAW_MARKER), Framing::Marked) => {
+            stored.remove(0);
+            Some(stored)
+        }
+        (_, Framing::Marked) => {
+            warn!("cached value has an unknown marker");
+            None
+        }
+    }
+}
 
 impl Connection {
     pub async fn new() -> Self {
-        let client = paired::paired_connect("127.0.0.1:6379").await.unwrap();
-        Self { inner: client }
+        Self::try_new().await.unwrap()
+    }
+
+    /// Connects to Redis, returning an error if it isn't running.
+    pub async fn try_new() -> Result<Self, RedisError> {
+        let client = paired::paired_connect("127.0.0.1:6379").await?;
+        Ok(Self::with_backend(Backend::Redis(client)))
+    }
+
+    /// A cache that keeps up to `capacity` items in memory instead of using Redis, evicting the
+    /// least recently used ones. It isn't shared with other processes.
+    #[must_use]
+    pub fn in_memory(capacity: usize) -> Self {
+        Self::with_backend(Backend::Memory(MemoryCache::new(capacity)))
+    }
+
+    fn with_backend(inner: Backend) -> Self {
+        let cert_ttl = env::var("BELVI_CACHE_TTL")
+            .ok()
+            .map(|ttl| ttl.parse().expect("invalid BELVI_CACHE_TTL"));
+        let compression = env::var("BELVI_CACHE_COMPRESS").is_ok();
+        Self {
+            inner,
+            cert_ttl,
+            compression,
+        }
+    }
+
+    /// Is this an in-memory cache, rather than a connection to Redis?
+    #[must_use]
+    pub fn is_in_memory(&self) -> bool {
+        matches!(self.inner, Backend::Memory(_))
+    }
+
+    #[must_use]
+    pub fn cert_ttl(&self) -> Option<u64> {
+        self.cert_ttl
+    }
+
+    #[must_use]
+    pub fn compression(&self) -> bool {
+        self.compression
+    }
+
+    /// Sets whether certs added after this are compressed. Certs can be read regardless of
+    /// whether they were compressed.
+    pub fn set_compression(&mut self, compression: bool) {
+        self.compression = compression;
+    }
+
+    async fn get(&mut self, key: Vec<u8>, framing: Framing) -> Option<Vec<u8>> {
+        let stored: Option<Vec<u8>> = match &mut self.inner {
+            Backend::Redis(redis) => redis.send(resp_array!["GET", key]).await.unwrap(),
+            Backend::Memory(cache) => cache.get(&key),
+        };
+        stored.and_then(|stored| decode_value(stored, framing))
+    }
+
+    fn set(&mut self, key: Vec<u8>, content: &[u8], framing: Framing) {
+        let stored = encode_value(content, self.compression, framing);
+        match (&mut self.inner, self.cert_ttl) {
+            (Backend::Redis(redis), Some(ttl)) => {
+                redis.send_and_forget(resp_array!["SET", key, stored, "EX", ttl.to_string()])
+            }
+            (Backend::Redis(redis), None) => redis.send_and_forget(resp_array!["SET", key, stored]),
+            (Backend::Memory(cache), ttl) => cache.set(key, stored, ttl.map(Duration::from_secs)),
+        }
+    }
+
+    /// Sets many keys with the same prefix. Without a TTL, this is a single `MSET`. With a TTL,
+    /// each key needs its own `SET`, but they are pipelined rather than waiting for each reply.
+    fn set_many<I: AsRef<[u8]>, C: AsRef<[u8]>>(
+        &mut self,
+        prefix: &[u8],
+        items: &[(I, C)],
+        framing: Framing,
+    ) {
+        if items.is_empty() {
+            // MSET needs at least one key
+            return;
+        }
+        if self.cert_ttl.is_some() || self.is_in_memory() {
+            for (id, content) in items {
+                self.set([prefix, id.as_ref()].concat(), content.as_ref(), framing);
+            }
+            return;
+        }
+        let mut args = Vec::with_capacity(items.len() * 2);
+        for (id, content) in items {
+            args.push([prefix, id.as_ref()].concat());
+            args.push(encode_value(content.as_ref(), self.compression, framing));
+        }
+        if let Backend::Redis(redis) = &mut self.inner {
+            redis.send_and_forget(resp_array!["MSET"].append(args));
+        }
     }
 
     pub async fn get_cert(&mut self, id: &[u8]) -> Option<Vec<u8>> {
-        self.inner
-            .send(resp_array!["GET", [OBJECT_PREFIX, id].concat()])
-            .await
-            .unwrap()
+        self.get([OBJECT_PREFIX, id].concat(), Framing::Der).await
+    }
+
+    /// Gets many certs in one round trip. The output is in the same order as `ids`.
+    pub async fn get_certs(&mut self, ids: &[Vec<u8>]) -> Vec<Option<Vec<u8>>> {
+        if ids.is_empty() {
+            // MGET needs at least one key
+            return Vec::new();
+        }
+        let keys: Vec<Vec<u8>> = ids.iter().map(|id| [OBJECT_PREFIX, id].concat()).collect();
+        let stored: Vec<Option<Vec<u8>>> = match &mut self.inner {
+            Backend::Redis(redis) => redis.send(resp_array!["MGET"].append(keys)).await.unwrap(),
+            Backend::Memory(cache) => keys.iter().map(|key| cache.get(key)).collect(),
+        };
+        stored
+            .into_iter()
+            .map(|cert| cert.and_then(|cert| decode_value(cert, Framing::Der)))
+            .collect()
     }
 
     pub fn new_cert(&mut self, id: &[u8], content: &[u8]) {
         trace!("adding cert to Redis: {:?}, {} bytes", id, content.len());
-        self.inner
-            .send_and_forget(resp_array!["SET", [OBJECT_PREFIX, id].concat(), content]);
+        self.set([OBJECT_PREFIX, id].concat(), content, Framing::Der);
         trace!("added cert to Redis: {:?}, {} bytes", id, content.len());
     }
 
-    /// Lists the keys for all certificates in the database.
-    /// Should be used for testing only, this is not fast.
+    /// Adds many certs at once, as `(id, content)` pairs.
+    pub fn new_cert_batch<I: AsRef<[u8]>, C: AsRef<[u8]>>(&mut self, certs: &[(I, C)]) {
+        trace!("adding {} certs to Redis", certs.len());
+        self.set_many(OBJECT_PREFIX, certs, Framing::Der);
+    }
+
+    /// Gets the `extra_data` of a log entry by its hash (`extra_hash` in the database).
+    pub async fn get_extra_data(&mut self, extra_hash: &[u8]) -> Option<Vec<u8>> {
+        self.get([EXTRA_DATA_PREFIX, extra_hash].concat(), Framing::Marked)
+            .await
+    }
+
+    /// Adds the `extra_data` of a log entry. This uses the same TTL and compression as certs.
+    pub fn new_extra_data(&mut self, extra_hash: &[u8], content: &[u8]) {
+        trace!(
+            "adding extra data to Redis: {:?}, {} bytes",
+            extra_hash,
+            content.len()
+        );
+        self.set(
+            [EXTRA_DATA_PREFIX, extra_hash].concat(),
+            content,
+            Framing::Marked,
+        );
+    }
+
+    /// Checks whether Redis is reachable. An in-memory cache is always reachable.
+    pub async fn ping(&mut self) -> bool {
+        match &mut self.inner {
+            Backend::Redis(redis) => redis.send::<String>(resp_array!["PING"]).await.is_ok(),
+            Backend::Memory(_) => true,
+        }
+    }
+
+    /// Adds the `extra_data` of many log entries at once, as `(extra_hash, content)` pairs.
+    pub fn new_extra_data_batch<I: AsRef<[u8]>, C: AsRef<[u8]>>(&mut self, items: &[(I, C)]) {
+        trace!("adding {} extra data items to Redis", items.len());
+        self.set_many(EXTRA_DATA_PREFIX, items, Framing::Marked);
+    }
+
+    /// Lists the keys for all certificates in the database, including the object prefix.
+    /// Should be used for testing only, this is not fast. This uses `SCAN` rather than `KEYS` so
+    /// Redis isn't blocked while listing a large cache.
     pub async fn cached_cert_key_list(&mut self) -> Vec<Vec<u8>> {
-        self.inner.send(resp_array!["KEYS", "*"]).await.unwrap()
+        let redis = match &mut self.inner {
+            Backend::Redis(redis) => redis,
+            Backend::Memory(cache) => return cache.keys_with_prefix(OBJECT_PREFIX),
+        };
+        let pattern = [OBJECT_PREFIX, b"*"].concat();
+        let mut keys = Vec::new();
+        let mut cursor = "0".to_string();
+        loop {
+            let (next_cursor, mut batch): (String, Vec<Vec<u8>>) = redis
+                .send(resp_array![
+                    "SCAN",
+                    cursor,
+                    "MATCH",
+                    pattern.clone(),
+                    "COUNT",
+                    "1000"
+                ])
+                .await
+                .unwrap();
+            keys.append(&mut batch);
+            if next_cursor == "0" {
+                break;
+            }
+            cursor = next_cursor;
+        }
+        // SCAN can return the same key more than once
+        keys.sort_unstable();
+        keys.dedup();
+        keys
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compression_round_trip() {
+        let cert = include_bytes!("../../test_certs/ttw.der");
+        let compressed = encode_value(cert, true, Framing::Der);
+        assert_eq!(compressed[0], COMPRESSED_MARKER);
+        assert!(compressed.len() < cert.len());
+        assert_eq!(decode_value(compressed, Framing::Der).unwrap(), cert);
+    }
+
+    #[tokio::test]
+    async fn in_memory() {
+        let cert = include_bytes!("../../test_certs/ttw.der");
+        let mut conn = Connection::in_memory(10);
+        assert!(conn.is_in_memory());
+        assert!(conn.ping().await);
+        conn.set_compression(true);
+        conn.new_cert(b"a", cert);
+        conn.set_compression(false);
+        conn.new_cert_batch(&[(b"b", &cert[..]), (b"c", &cert[..])]);
+        conn.new_extra_data(b"a", b"chain");
+        conn.new_extra_data(b"b", b"\x01\x00\x00");
+        assert_eq!(conn.get_cert(b"a").await.unwrap(), cert);
+        assert_eq!(
+            conn.get_certs(&[b"c".to_vec(), b"d".to_vec()]).await,
+            [Some(cert.to_vec()), None]
+        );
+        assert_eq!(conn.get_extra_data(b"a").await.unwrap(), b"chain");
+        assert_eq!(conn.get_extra_data(b"b").await.unwrap(), b"\x01\x00\x00");
+        assert_eq!(
+            conn.cached_cert_key_list().await,
+            [b"o:a".to_vec(), b"o:b".to_vec(), b"o:c".to_vec()]
+        );
+    }
+
+    #[test]
+    fn uncompressed_still_decodes() {
+        let cert = include_bytes!("../../test_certs/ttw.der");
+        assert_eq!(
+            decode_value(encode_value(cert, false, Framing::Der), Framing::Der).unwrap(),
+            cert
+        );
+    }
+
+    #[test]
+    fn extra_data_starting_with_marker() {
+        // a chain whose length is 65536-131071 bytes starts with 0x01, like compressed values
+        let extra_data = [&[0x01, 0x00, 0x03][..], b"abc"].concat();
+        for compression in [false, true] {
+            let stored = encode_value(&extra_data, compression, Framing::Marked);
+            assert_eq!(decode_value(stored, Framing::Marked).unwrap(), extra_data);
+        }
+        assert_eq!(decode_value(vec![0x30], Framing::Marked), None);
     }
 }
@@ -0,0 +1,158 @@
+// SPDX-License-Identifier: Apache-2.0
+//! A cache kept in memory, for when Redis isn't available.
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+/// Keeps up to `capacity` values, evicting the least recently used one when it is full.
+#[derive(Debug)]
+pub struct MemoryCache {
+    capacity: usize,
+    /// Values with when they were last used and when they expire.
+    entries: HashMap<Vec<u8>, Entry>,
+    /// Keys by when they were last used, least recent first.
+    order: BTreeMap<u64, Vec<u8>>,
+    /// Incremented every time a key is used.
+    tick: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: Vec<u8>,
+    last_used: u64,
+    expires: Option<Instant>,
+}
+
+impl MemoryCache {
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn touch(&mut self, key: &[u8]) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.order.remove(&entry.last_used);
+            self.tick += 1;
+            entry.last_used = self.tick;
+            self.order.insert(self.tick, key.to_vec());
+        }
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.get_at(key, Instant::now())
+    }
+
+    fn get_at(&mut self, key: &[u8], now: Instant) -> Option<Vec<u8>> {
+        let expired = self
+            .entries
+            .get(key)?
+            .expires
+            .is_some_and(|expires| expires <= now);
+        if expired {
+            self.remove(key);
+            return None;
+        }
+        self.touch(key);
+        Some(self.entries[key].value.clone())
+    }
+
+    /// Adds a value that is removed after `ttl`, or kept until it is evicted if that is `None`.
+    pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>, ttl: Option<Duration>) {
+        self.set_at(key, value, ttl, Instant::now());
+    }
+
+    fn set_at(&mut self, key: Vec<u8>, value: Vec<u8>, ttl: Option<Duration>, now: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        while self.entries.len() >= self.capacity {
+            match self.order.keys().next().copied() {
+                Some(oldest) => {
+                    let oldest_key = self.order.remove(&oldest).unwrap();
+                    self.entries.remove(&oldest_key);
+                }
+                None => break,
+            }
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                last_used: self.tick,
+                expires: ttl.map(|ttl| now + ttl),
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.last_used);
+        }
+    }
+
+    /// All keys starting with `prefix`, sorted.
+    #[must_use]
+    pub fn keys_with_prefix(&self, prefix: &[u8]) -> Vec<Vec<u8>> {
+        let mut keys: Vec<_> = self
+            .entries
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect();
+        keys.sort_unstable();
+        keys
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = MemoryCache::new(2);
+        cache.set(vec![1], vec![10], None);
+        cache.set(vec![2], vec![20], None);
+        // 1 is now more recently used than 2
+        assert_eq!(cache.get(&[1]), Some(vec![10]));
+        cache.set(vec![3], vec![30], None);
+        assert_eq!(cache.get(&[1]), Some(vec![10]));
+        assert_eq!(cache.get(&[2]), None);
+        assert_eq!(cache.get(&[3]), Some(vec![30]));
+        // replacing a value doesn't evict anything
+        cache.set(vec![3], vec![31], None);
+        assert_eq!(cache.get(&[1]), Some(vec![10]));
+        assert_eq!(cache.get(&[3]), Some(vec![31]));
+        assert_eq!(cache.entries.len(), cache.order.len());
+    }
+
+    #[test]
+    fn expires() {
+        let mut cache = MemoryCache::new(2);
+        let now = Instant::now();
+        cache.set_at(vec![1], vec![10], Some(Duration::from_secs(5)), now);
+        assert_eq!(
+            cache.get_at(&[1], now + Duration::from_secs(4)),
+            Some(vec![10])
+        );
+        assert_eq!(cache.get_at(&[1], now + Duration::from_secs(5)), None);
+        assert!(cache.entries.is_empty());
+        assert!(cache.order.is_empty());
+    }
+
+    #[test]
+    fn zero_capacity() {
+        let mut cache = MemoryCache::new(0);
+        cache.set(vec![1], vec![10], None);
+        assert_eq!(cache.get(&[1]), None);
+    }
+}
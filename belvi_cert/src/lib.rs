@@ -1,49 +1,102 @@
 // SPDX-License-Identifier: Apache-2.0
 use bcder::{
     decode::{self, Constructed, Content},
-    Tag,
+    encode::Values,
+    OctetString, Oid, Tag,
 };
 use log::warn;
-use x509_certificate::rfc5280::TbsCertificate;
+use std::net::IpAddr;
+use x509_certificate::{rfc3280::Name, rfc5280::TbsCertificate};
 
-pub fn get_cert_domains(cert: &TbsCertificate) -> Vec<Vec<u8>> {
-    let mut domains = Vec::new();
+/// Gets the names in the subject's commonName attributes, in order.
+fn get_cert_cns(cert: &TbsCertificate) -> Vec<Vec<u8>> {
+    let mut cns = Vec::new();
     for subject in &**cert.subject {
         for attr in &**subject {
             // 2.5.4.3 is OID for commonName
             if attr.typ.as_ref() == [85, 4, 3] {
-                let next_dom =
-                    Constructed::decode((**attr.value).clone(), bcder::Mode::Ber, take_tagged_ber);
-                if let Ok(dom) = next_dom {
-                    domains.push(dom);
+                let cn = Constructed::decode(
+                    (**attr.value).clone(),
+                    bcder::Mode::Ber,
+                    take_directory_string,
+                );
+                if let Ok(cn) = cn {
+                    cns.push(cn);
                 }
             }
         }
     }
+    cns
+}
+
+/// Gets the subject's commonName. If there are multiple, this is the first one.
+pub fn get_cert_cn(cert: &TbsCertificate) -> Option<Vec<u8>> {
+    get_cert_cns(cert).into_iter().next()
+}
+
+/// A name in the subjectAltNames extension (RFC 5280, section 4.2.1.6). Only the types seen in
+/// practice are included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubjectAltName {
+    Email(Vec<u8>),
+    Dns(Vec<u8>),
+    Uri(Vec<u8>),
+    IpAddress(IpAddr),
+    /// An otherName, like the UPNs on smart card certs. The value is decoded as a string if
+    /// possible.
+    OtherName {
+        type_id: Oid,
+        value: Vec<u8>,
+    },
+    /// A directoryName. Only the common attributes are included.
+    DirectoryName(String),
+}
+
+impl SubjectAltName {
+    /// The name, if it is stored and searched for as a domain. Email addresses and URIs are
+    /// included, since they usually contain one.
+    pub fn domain(&self) -> Option<&[u8]> {
+        match self {
+            Self::Email(name) | Self::Dns(name) | Self::Uri(name) => Some(name),
+            Self::IpAddress(_) | Self::OtherName { .. } | Self::DirectoryName(_) => None,
+        }
+    }
+}
+
+/// Takes the value of a subjectAltNames extension. Names of unsupported types are skipped.
+pub fn take_subject_alt_names(
+    cons: &mut Constructed<bytes::Bytes>,
+) -> Result<Vec<SubjectAltName>, decode::Error> {
+    cons.take_sequence(|subcons| {
+        let mut sans = Vec::new();
+        loop {
+            match take_general_name(subcons) {
+                Ok(san) => sans.push(san),
+                Err(decode::Error::Malformed) => break,
+                Err(decode::Error::Unimplemented) => {}
+            }
+        }
+        Ok(sans)
+    })
+}
+
+/// Gets the names in the subjectAltNames extension, without duplicates.
+pub fn get_cert_sans(cert: &TbsCertificate) -> Vec<SubjectAltName> {
+    let mut sans = Vec::new();
     if let Some(exts) = &cert.extensions {
         for ext in &**exts {
             // 2.5.29.17 is OID for subjectAltName
             if ext.id.as_ref() == [85, 29, 17] {
-                let doms = Constructed::decode(ext.value.to_bytes(), bcder::Mode::Ber, |cons| {
-                    cons.take_sequence(|subcons| {
-                        let mut doms = Vec::new();
-                        loop {
-                            match take_tagged_ber(subcons) {
-                                Ok(dom) => {
-                                    if !domains.contains(&dom) && !doms.contains(&dom) {
-                                        doms.push(dom);
-                                    }
-                                }
-                                Err(decode::Error::Malformed) => break,
-                                Err(decode::Error::Unimplemented) => {}
-                            }
+                let names = Constructed::decode(
+                    ext.value.to_bytes(),
+                    bcder::Mode::Ber,
+                    take_subject_alt_names,
+                );
+                if let Ok(names) = names {
+                    for name in names {
+                        if !sans.contains(&name) {
+                            sans.push(name);
                         }
-                        Ok(doms)
-                    })
-                });
-                if let Ok(doms) = doms {
-                    for dom in doms {
-                        domains.push(dom);
                     }
                 } else {
                     warn!("Cert has invalid subjectAltNames extension");
@@ -51,35 +104,275 @@ pub fn get_cert_domains(cert: &TbsCertificate) -> Vec<Vec<u8>> {
             }
         }
     }
-    domains
+    sans
+}
+
+/// Is the commonName also one of the subjectAltNames? This is `None` if there is no commonName.
+/// Names are compared case-insensitively, like domains.
+pub fn cn_matches_san(cert: &TbsCertificate) -> Option<bool> {
+    let cn = get_cert_cn(cert)?;
+    Some(
+        get_cert_domains(cert)
+            .iter()
+            .any(|domain| domain.eq_ignore_ascii_case(&cn)),
+    )
+}
+
+/// Gets the cert's domains, which are the subjectAltNames that have one. The commonName isn't
+/// included, since publicly-trusted certs must also list it in the subjectAltNames.
+pub fn get_cert_domains(cert: &TbsCertificate) -> Vec<Vec<u8>> {
+    get_cert_sans(cert)
+        .into_iter()
+        .filter_map(|san| san.domain().map(<[u8]>::to_vec))
+        .collect()
+}
+
+/// Counts the number of SCTs embedded in a certificate. Precertificates never have embedded SCTs.
+pub fn sct_count(cert: &TbsCertificate) -> usize {
+    let exts = match &cert.extensions {
+        Some(exts) => exts,
+        None => return 0,
+    };
+    for ext in &**exts {
+        // 1.3.6.1.4.1.11129.2.4.2 is OID for embedded SCT list
+        if ext.id.as_ref() == [43, 6, 1, 4, 1, 214, 121, 2, 4, 2] {
+            let list = Constructed::decode(ext.value.to_bytes(), bcder::Mode::Ber, |cons| {
+                OctetString::take_from(cons)
+            });
+            return match list {
+                Ok(list) => count_tls_list(&list.to_bytes()),
+                Err(_) => {
+                    warn!("Cert has invalid SCT list extension");
+                    0
+                }
+            };
+        }
+    }
+    0
+}
+
+/// Does the certificate have the CT poison extension (RFC 6962, section 3.1)? Only precertificates
+/// have it, but it's removed from the TBS certificate in precert log entries.
+pub fn has_ct_poison(cert: &TbsCertificate) -> bool {
+    cert.extensions.as_ref().is_some_and(|exts| {
+        exts.iter()
+            // 1.3.6.1.4.1.11129.2.4.3 is OID for the poison extension
+            .any(|ext| ext.id.as_ref() == [43, 6, 1, 4, 1, 214, 121, 2, 4, 3])
+    })
+}
+
+/// Is `precert` the TBS certificate of a precertificate that `cert` was issued from, or the
+/// reverse? RFC 6962 (section 3.1) requires them to be the same, except that only the
+/// precertificate has the poison extension and only the final certificate has embedded SCTs.
+pub fn is_same_issuance(precert: &TbsCertificate, cert: &TbsCertificate) -> bool {
+    fn without_ct_extensions(cert: &TbsCertificate) -> Vec<u8> {
+        let mut cert = cert.clone();
+        if let Some(exts) = &mut cert.extensions {
+            // 1.3.6.1.4.1.11129.2.4.2 is OID for embedded SCT list, and
+            // 1.3.6.1.4.1.11129.2.4.3 is OID for the poison extension
+            exts.retain(|ext| {
+                !matches!(
+                    ext.id.as_ref(),
+                    [43, 6, 1, 4, 1, 214, 121, 2, 4, 2] | [43, 6, 1, 4, 1, 214, 121, 2, 4, 3]
+                )
+            });
+        }
+        let encoded = cert.encode_ref().to_captured(bcder::Mode::Der);
+        encoded.to_vec()
+    }
+    without_ct_extensions(precert) == without_ct_extensions(cert)
+}
+
+/// Identifies the CA that issued a certificate, for finding other certificates from the same
+/// issuer. This is the authority key identifier if the certificate has one, since it identifies
+/// the issuing key even if several CAs share a name. Otherwise it's a hash of the issuer's name.
+pub fn issuer_key(cert: &TbsCertificate) -> Vec<u8> {
+    if let Some(exts) = &cert.extensions {
+        for ext in &**exts {
+            // 2.5.29.35 is OID for authorityKeyIdentifier
+            if ext.id.as_ref() == [85, 29, 35] {
+                let key_id = Constructed::decode(ext.value.to_bytes(), bcder::Mode::Ber, |cons| {
+                    cons.take_sequence(|subcons| {
+                        let key_id =
+                            subcons.take_opt_primitive_if(Tag::CTX_0, |prim| prim.take_all())?;
+                        // the issuer's name and serial can also be given, which aren't needed
+                        subcons.skip_all()?;
+                        Ok(key_id)
+                    })
+                });
+                match key_id {
+                    Ok(Some(key_id)) if !key_id.is_empty() => return key_id.to_vec(),
+                    Ok(_) => {}
+                    Err(_) => warn!("Cert has invalid authorityKeyIdentifier extension"),
+                }
+            }
+        }
+    }
+    let issuer = cert.issuer.encode_ref().to_captured(bcder::Mode::Der);
+    belvi_hash::db(issuer.as_slice()).to_vec()
 }
 
-fn take_tagged_ber(cons: &mut Constructed<bytes::Bytes>) -> Result<Vec<u8>, bcder::decode::Error> {
+/// Gets the serial number of a certificate, with leading zero bytes removed (see
+/// [`normalize_serial`]).
+pub fn serial(cert: &TbsCertificate) -> Vec<u8> {
+    normalize_serial(cert.serial_number.as_slice()).to_vec()
+}
+
+/// Removes leading zero bytes from a serial number. DER encodes positive integers with a leading
+/// zero byte if the high bit would otherwise be set, but people usually write serials without it.
+pub fn normalize_serial(serial: &[u8]) -> &[u8] {
+    let start = serial
+        .iter()
+        .position(|byte| *byte != 0)
+        .unwrap_or(serial.len());
+    &serial[start..]
+}
+
+/// Counts the items in a TLS-encoded list of variable-length items, each prefixed with a 16-bit
+/// length. The list itself also has a 16-bit length prefix.
+fn count_tls_list(bytes: &[u8]) -> usize {
+    fn take_u16(bytes: &[u8]) -> Option<(usize, &[u8])> {
+        if bytes.len() < 2 {
+            return None;
+        }
+        Some((u16::from_be_bytes([bytes[0], bytes[1]]).into(), &bytes[2..]))
+    }
+    let mut rest = match take_u16(bytes) {
+        Some((len, rest)) if len == rest.len() => rest,
+        _ => {
+            warn!("Cert has SCT list with invalid length");
+            return 0;
+        }
+    };
+    let mut count = 0;
+    while let Some((len, after_len)) = take_u16(rest) {
+        if len > after_len.len() {
+            warn!("Cert has truncated SCT");
+            break;
+        }
+        count += 1;
+        rest = &after_len[len..];
+    }
+    count
+}
+
+fn take_general_name(
+    cons: &mut Constructed<bytes::Bytes>,
+) -> Result<SubjectAltName, bcder::decode::Error> {
     cons.take_value(|tag, content| {
         match content {
             Content::Primitive(prim) => {
                 let bytes = prim.take_all()?;
                 // tag can be from 0-8: https://datatracker.ietf.org/doc/html/rfc5280#page-128
-                // in practice, almost always a DNS name
-                // TODO: support IP addresses, tagged with CTX_7
-                if
-                // email
-                tag == Tag::CTX_1 ||
-                    // DNS name
-                    tag == Tag::CTX_2 ||
-                    // URI
-                    tag == Tag::CTX_6
-                {
-                    Ok(ber_to_string(bytes))
+                // in practice, almost always a DNS name; constructed names are handled below
+                // TODO: store IP addresses as domains, so they can be searched for
+                if tag == Tag::CTX_1 {
+                    Ok(SubjectAltName::Email(ber_to_string(bytes)))
+                } else if tag == Tag::CTX_2 {
+                    Ok(SubjectAltName::Dns(ber_to_string(bytes)))
+                } else if tag == Tag::CTX_6 {
+                    Ok(SubjectAltName::Uri(ber_to_string(bytes)))
+                } else if tag == Tag::ctx(7) {
+                    match bytes.len() {
+                        4 => Ok(SubjectAltName::IpAddress(
+                            <[u8; 4]>::try_from(&*bytes).unwrap().into(),
+                        )),
+                        16 => Ok(SubjectAltName::IpAddress(
+                            <[u8; 16]>::try_from(&*bytes).unwrap().into(),
+                        )),
+                        _ => Err(decode::Error::Unimplemented),
+                    }
                 } else {
                     Err(decode::Error::Unimplemented)
                 }
             }
-            _ => Err(decode::Error::Malformed),
+            Content::Constructed(cons) => {
+                if tag == Tag::CTX_0 {
+                    // otherName, like the UPNs on smart card certs
+                    let type_id = Oid::take_from(cons)?;
+                    let value = cons.take_constructed_if(Tag::CTX_0, |cons| {
+                        let value = cons.capture_all()?.into_bytes();
+                        Ok(Constructed::decode(
+                            value.clone(),
+                            bcder::Mode::Ber,
+                            take_directory_string,
+                        )
+                        .unwrap_or_else(|_| value.to_vec()))
+                    })?;
+                    Ok(SubjectAltName::OtherName { type_id, value })
+                } else if tag == Tag::CTX_4 {
+                    let name = Name::take_from(cons)?.user_friendly_str()?;
+                    Ok(SubjectAltName::DirectoryName(name))
+                } else {
+                    cons.skip_all()?;
+                    Err(decode::Error::Unimplemented)
+                }
+            }
+        }
+    })
+}
+
+/// Takes a `DirectoryString`, as used for the commonName.
+fn take_directory_string(
+    cons: &mut Constructed<bytes::Bytes>,
+) -> Result<Vec<u8>, bcder::decode::Error> {
+    if let Some(string) = take_legacy_string(cons)? {
+        return Ok(string.into_bytes());
+    }
+    cons.take_value(|tag, content| {
+        let bytes = content.as_primitive()?.take_all()?;
+        if tag == Tag::UTF8_STRING || tag == Tag::PRINTABLE_STRING || tag == Tag::IA5_STRING {
+            Ok(bytes.to_vec())
+        } else {
+            Err(decode::Error::Unimplemented)
         }
     })
 }
 
+/// Takes one of the older string types that bcder can't decode, or returns `None` if the next
+/// value isn't one of them. TeletexString is treated as Latin-1, since that's how it's used in
+/// practice.
+pub fn take_legacy_string(
+    cons: &mut Constructed<bytes::Bytes>,
+) -> Result<Option<String>, decode::Error> {
+    if let Some(string) = cons.take_opt_value_if(Tag::TELETEX_STRING, |content| {
+        let bytes = content.as_primitive()?.take_all()?;
+        Ok(bytes.iter().map(|byte| char::from(*byte)).collect())
+    })? {
+        return Ok(Some(string));
+    }
+    if let Some(string) = cons.take_opt_value_if(Tag::VISIBLE_STRING, |content| {
+        let bytes = content.as_primitive()?.take_all()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| decode::Error::Malformed)
+    })? {
+        return Ok(Some(string));
+    }
+    // bcder's Tag::BMP_STRING is UNIVERSAL 29, but BMPString is UNIVERSAL 30
+    if let Some(string) = cons.take_opt_value_if(Tag::universal(30), |content| {
+        let bytes = content.as_primitive()?.take_all()?;
+        let units: Vec<u16> = bytes
+            .chunks(2)
+            .map(|pair| match pair {
+                [high, low] => Ok(u16::from_be_bytes([*high, *low])),
+                _ => Err(decode::Error::Malformed),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(String::from_utf16_lossy(&units))
+    })? {
+        return Ok(Some(string));
+    }
+    cons.take_opt_value_if(Tag::UNIVERSAL_STRING, |content| {
+        let bytes = content.as_primitive()?.take_all()?;
+        bytes
+            .chunks(4)
+            .map(|chunk| {
+                let chunk: [u8; 4] = chunk.try_into().map_err(|_| decode::Error::Malformed)?;
+                char::from_u32(u32::from_be_bytes(chunk)).ok_or(decode::Error::Malformed)
+            })
+            .collect()
+    })
+}
+
 fn ber_to_string(bytes: bytes::Bytes) -> Vec<u8> {
     let str_decode = Constructed::decode(bytes.clone(), bcder::Mode::Ber, |cons| {
         if let Ok(str) = bcder::Utf8String::take_from(cons) {
@@ -111,13 +404,78 @@ mod test {
             .as_ref()
             .tbs_certificate,
         );
-        let mut expected = Vec::new();
-        expected.push(b"*.smitop.com".to_vec());
-        expected.push(b"sni.cloudflaressl.com".to_vec());
-        expected.push(b"smitop.com".to_vec());
+        let expected = vec![
+            b"*.smitop.com".to_vec(),
+            b"sni.cloudflaressl.com".to_vec(),
+            b"smitop.com".to_vec(),
+        ];
         assert_eq!(domains, expected);
     }
 
+    #[test]
+    fn legacy_strings() {
+        fn take(der: &'static [u8]) -> Result<Option<String>, decode::Error> {
+            Constructed::decode(
+                bytes::Bytes::from_static(der),
+                bcder::Mode::Ber,
+                take_legacy_string,
+            )
+        }
+        let expected = Ok(Some("Hé".to_string()));
+        assert_eq!(take(b"\x14\x02H\xe9"), expected);
+        assert_eq!(take(b"\x1e\x04\x00H\x00\xe9"), expected);
+        assert_eq!(take(b"\x1c\x08\x00\x00\x00H\x00\x00\x00\xe9"), expected);
+        assert_eq!(take(b"\x1a\x02Hi"), Ok(Some("Hi".to_string())));
+        assert!(take(b"\x1e\x03\x00H\x00").is_err());
+        // left for bcder
+        assert!(!matches!(take(b"\x0c\x02Hi"), Ok(Some(_))));
+    }
+
+    #[test]
+    fn issuer_keys() {
+        fn cert(bytes: &[u8]) -> TbsCertificate {
+            x509_certificate::certificate::X509Certificate::from_der(bytes)
+                .unwrap()
+                .as_ref()
+                .tbs_certificate
+                .clone()
+        }
+        let ttw = cert(include_bytes!("../../test_certs/ttw.der"));
+        // from `openssl x509 -text`
+        assert_eq!(
+            issuer_key(&ttw),
+            vec![
+                0xa5, 0xce, 0x37, 0xea, 0xeb, 0xb0, 0x75, 0x0e, 0x94, 0x67, 0x88, 0xb4, 0x45, 0xfa,
+                0xd9, 0x24, 0x10, 0x87, 0x96, 0x1f
+            ]
+        );
+        let mut no_aki = ttw.clone();
+        no_aki.extensions = None;
+        assert_eq!(issuer_key(&no_aki).len(), 16);
+        assert_eq!(issuer_key(&no_aki), issuer_key(&no_aki.clone()));
+        let mut other_issuer = no_aki.clone();
+        other_issuer.issuer = no_aki.subject.clone();
+        assert_ne!(issuer_key(&no_aki), issuer_key(&other_issuer));
+    }
+
+    #[test]
+    fn common_names() {
+        fn cert(bytes: &[u8]) -> TbsCertificate {
+            x509_certificate::certificate::X509Certificate::from_der(bytes)
+                .unwrap()
+                .as_ref()
+                .tbs_certificate
+                .clone()
+        }
+        let ttw = cert(include_bytes!("../../test_certs/ttw.der"));
+        assert_eq!(get_cert_cn(&ttw), Some(b"sni.cloudflaressl.com".to_vec()));
+        assert_eq!(get_cert_sans(&ttw).len(), 3);
+        assert_eq!(cn_matches_san(&ttw), Some(true));
+        let geckome = cert(include_bytes!("../../test_certs/geckome.der"));
+        assert_eq!(get_cert_cn(&geckome), Some(b"*.gecko.me".to_vec()));
+        assert_eq!(cn_matches_san(&geckome), Some(true));
+    }
+
     #[test]
     fn geckome_domains() {
         let domains = get_cert_domains(
@@ -128,28 +486,158 @@ mod test {
             .as_ref()
             .tbs_certificate,
         );
-        let mut expected = Vec::new();
-        expected.push(b"*.gecko.me".to_vec());
-        expected.push(b"gecko.me".to_vec());
+        let expected = vec![b"*.gecko.me".to_vec(), b"gecko.me".to_vec()];
         assert_eq!(domains, expected);
     }
 
-    // haplorrhini.der
     #[test]
-    fn haplorrhini_domains() {
-        let domains = get_cert_domains(
-            &x509_certificate::certificate::X509Certificate::from_der(include_bytes!(
-                "../../test_certs/haplorrhini.der"
-            ))
+    fn sct_counts() {
+        fn count(bytes: &[u8]) -> usize {
+            sct_count(
+                &x509_certificate::certificate::X509Certificate::from_der(bytes)
+                    .unwrap()
+                    .as_ref()
+                    .tbs_certificate,
+            )
+        }
+        assert_eq!(count(include_bytes!("../../test_certs/ttw.der")), 3);
+        assert_eq!(count(include_bytes!("../../test_certs/policesf.der")), 2);
+        assert_eq!(count(include_bytes!("../../test_certs/haplorrhini.der")), 0);
+    }
+
+    #[test]
+    fn ct_poison() {
+        let poisoned = x509_certificate::certificate::X509Certificate::from_der(include_bytes!(
+            "../../test_certs/poisoned.der"
+        ))
+        .unwrap();
+        assert!(has_ct_poison(&poisoned.as_ref().tbs_certificate));
+        let ttw = x509_certificate::certificate::X509Certificate::from_der(include_bytes!(
+            "../../test_certs/ttw.der"
+        ))
+        .unwrap();
+        assert!(!has_ct_poison(&ttw.as_ref().tbs_certificate));
+        // precert log entries have the poison removed
+        let webcares = Constructed::decode(
+            include_bytes!("../../test_certs/webcares.der").as_ref(),
+            bcder::Mode::Der,
+            TbsCertificate::take_from,
+        )
+        .unwrap();
+        assert!(!has_ct_poison(&webcares));
+    }
+
+    #[test]
+    fn same_issuance() {
+        fn cert(bytes: &[u8]) -> TbsCertificate {
+            x509_certificate::certificate::X509Certificate::from_der(bytes)
+                .unwrap()
+                .as_ref()
+                .tbs_certificate
+                .clone()
+        }
+        let ttw = cert(include_bytes!("../../test_certs/ttw.der"));
+        let poison = cert(include_bytes!("../../test_certs/poisoned.der"))
+            .extensions
             .unwrap()
-            .as_ref()
-            .tbs_certificate,
+            .iter()
+            .find(|ext| ext.id.as_ref() == [43, 6, 1, 4, 1, 214, 121, 2, 4, 3])
+            .unwrap()
+            .clone();
+        // what ttw's precertificate would have been: the poison instead of the SCTs
+        let mut precert = ttw.clone();
+        let exts = precert.extensions.as_mut().unwrap();
+        exts.retain(|ext| ext.id.as_ref() != [43, 6, 1, 4, 1, 214, 121, 2, 4, 2]);
+        exts.push(poison);
+        assert!(is_same_issuance(&precert, &ttw));
+        assert!(is_same_issuance(&ttw, &precert));
+        // as in a log entry, without the poison
+        precert.extensions.as_mut().unwrap().pop();
+        assert!(is_same_issuance(&precert, &ttw));
+
+        let mut other_subject = precert.clone();
+        other_subject.subject = ttw.issuer.clone();
+        assert!(!is_same_issuance(&other_subject, &ttw));
+        let geckome = cert(include_bytes!("../../test_certs/geckome.der"));
+        assert!(!is_same_issuance(&geckome, &ttw));
+    }
+
+    #[test]
+    fn serials() {
+        let cert = x509_certificate::certificate::X509Certificate::from_der(include_bytes!(
+            "../../test_certs/ttw.der"
+        ))
+        .unwrap();
+        assert_eq!(
+            serial(&cert.as_ref().tbs_certificate),
+            [
+                0x01, 0x2b, 0x91, 0x98, 0xa7, 0x72, 0x63, 0x68, 0x66, 0x77, 0x8f, 0x00, 0xeb, 0x21,
+                0xb4, 0xeb
+            ]
         );
-        let mut expected = Vec::new();
-        expected.push(b"test1.http-01.production.haplorrhini.com".to_vec());
-        expected.push(b"test2.http-01.production.haplorrhini.com".to_vec());
-        expected.push(b"test3.http-01.production.haplorrhini.com".to_vec());
-        // TODO: ip address
+        assert_eq!(normalize_serial(&[0, 0, 0x80, 0]), [0x80, 0]);
+        assert!(normalize_serial(&[0]).is_empty());
+    }
+
+    #[test]
+    fn othername_domains() {
+        let cert = x509_certificate::certificate::X509Certificate::from_der(include_bytes!(
+            "../../test_certs/othername.der"
+        ))
+        .unwrap();
+        let cert = &cert.as_ref().tbs_certificate;
+        let sans = get_cert_sans(cert);
+        assert_eq!(sans.len(), 3);
+        assert_eq!(sans[0], SubjectAltName::Dns(b"smartcard.example".to_vec()));
+        match &sans[1] {
+            SubjectAltName::OtherName { type_id, value } => {
+                assert_eq!(type_id.to_string(), "1.3.6.1.4.1.311.20.2.3");
+                assert_eq!(value, b"user@corp.example");
+            }
+            other => panic!("expected otherName, got {:?}", other),
+        }
+        assert_eq!(
+            sans[2],
+            SubjectAltName::DirectoryName("CN=Test User, O=Example Corp".to_string())
+        );
+        // other types of names aren't domains
+        assert_eq!(get_cert_domains(cert), vec![b"smartcard.example".to_vec()]);
+    }
+
+    #[test]
+    fn ip_address_sans() {
+        let cert = x509_certificate::certificate::X509Certificate::from_der(include_bytes!(
+            "../../test_certs/haplorrhini.der"
+        ))
+        .unwrap();
+        let sans = get_cert_sans(&cert.as_ref().tbs_certificate);
+        assert_eq!(sans.len(), 5);
+        assert_eq!(
+            sans[3..],
+            [
+                SubjectAltName::IpAddress("34.117.169.92".parse().unwrap()),
+                SubjectAltName::IpAddress("2600:1901:0:631b::".parse().unwrap()),
+            ]
+        );
+    }
+
+    // haplorrhini.der
+    #[test]
+    fn haplorrhini_domains() {
+        let cert = x509_certificate::certificate::X509Certificate::from_der(include_bytes!(
+            "../../test_certs/haplorrhini.der"
+        ))
+        .unwrap();
+        let tbs = &cert.as_ref().tbs_certificate;
+        let domains = get_cert_domains(tbs);
+        let expected = vec![
+            b"test1.http-01.production.haplorrhini.com".to_vec(),
+            b"test2.http-01.production.haplorrhini.com".to_vec(),
+            b"test3.http-01.production.haplorrhini.com".to_vec(),
+        ];
+        // the IP address SAN isn't a domain
         assert_eq!(domains, expected);
+        let ip = SubjectAltName::IpAddress("2600:1901:0:631b::".parse().unwrap());
+        assert!(get_cert_sans(tbs).contains(&ip));
     }
 }
@@ -0,0 +1,91 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Rebuilds the domains table from the cached certs, so changes to domain extraction apply to
+//! existing certs. The only argument is the data directory.
+//!
+//! The domains of each cert are replaced only if the cert is in the cache, so certs that aren't
+//! cached keep their existing domains.
+use bcder::decode::Constructed;
+use log::{info, warn};
+use std::{env, path::PathBuf};
+use x509_certificate::rfc5280::{Certificate, TbsCertificate};
+
+/// Number of certs to rebuild in each transaction.
+const BATCH_SIZE: usize = 1000;
+
+fn parse_tbs(cert: &[u8]) -> Option<TbsCertificate> {
+    // cached certs are either a precert TBS or a full cert
+    Constructed::decode(cert, bcder::Mode::Der, TbsCertificate::take_from)
+        .or_else(|_| {
+            Constructed::decode(cert, bcder::Mode::Der, Certificate::take_from)
+                .map(|cert| cert.tbs_certificate)
+        })
+        .ok()
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    let data_path: PathBuf = env::args_os().nth(1).expect("no data path provided").into();
+    let mut db = belvi_db::connect_at(&data_path);
+    let mut cache = belvi_cache::Connection::new().await;
+
+    let leaf_hashes: Vec<Vec<u8>> = db
+        .prepare("SELECT leaf_hash FROM certs")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let total = leaf_hashes.len();
+    info!("Rebuilding domains for {} certs", total);
+
+    let mut missing = 0;
+    for (batch_idx, batch) in leaf_hashes.chunks(BATCH_SIZE).enumerate() {
+        let certs = cache.get_certs(batch).await;
+        let tx = db.transaction().unwrap();
+        {
+            let mut domain_delete = tx
+                .prepare_cached("DELETE FROM domains WHERE leaf_hash = ?")
+                .unwrap();
+            let mut domain_insert = tx
+                .prepare_cached("INSERT OR IGNORE INTO domains (leaf_hash, domain) VALUES (?, ?)")
+                .unwrap();
+            for (leaf_hash, cert) in batch.iter().zip(certs) {
+                let tbs = match cert.as_deref().map(parse_tbs) {
+                    Some(Some(tbs)) => tbs,
+                    Some(None) => {
+                        warn!("Cached cert {} is invalid", hex::encode(leaf_hash));
+                        missing += 1;
+                        continue;
+                    }
+                    None => {
+                        missing += 1;
+                        continue;
+                    }
+                };
+                domain_delete.execute([leaf_hash]).unwrap();
+                for domain in belvi_cert::get_cert_domains(&tbs) {
+                    domain_insert
+                        .execute(rusqlite::params![
+                            leaf_hash,
+                            String::from_utf8_lossy(&domain)
+                        ])
+                        .unwrap();
+                }
+            }
+        }
+        tx.commit().unwrap();
+        info!(
+            "Rebuilt {}/{} certs",
+            (batch_idx * BATCH_SIZE + batch.len()),
+            total
+        );
+    }
+    if missing > 0 {
+        warn!(
+            "{} certs weren't in the cache, so their domains weren't rebuilt",
+            missing
+        );
+    }
+}
@@ -1,9 +1,15 @@
 // SPDX-License-Identifier: Apache-2.0
-use crate::{Ctx, FetchState, LogId};
+use crate::{watchlist::WatchMatch, Ctx, FetchState, LogId};
+use batcher::HistState;
 use bcder::decode::Constructed;
-use belvi_log_list::{log_data::LogEntry, Log};
+use belvi_log_list::{
+    fetcher::FetchError,
+    log_data::{GetEntriesItem, LogEntry, LogSth},
+    Log,
+};
 use log::{debug, info, trace, warn};
-use std::sync::Mutex;
+use std::{future::Future, time::Instant};
+use tokio::sync::Mutex;
 use x509_certificate::asn1time::Time;
 
 pub mod batcher;
@@ -16,7 +22,38 @@ fn time_to_unix(time: Time) -> i64 {
     .timestamp()
 }
 
-impl<'ctx> FetchState {
+/// Was the entry at `idx` left out of `prev_sth` even though its maximum merge delay had passed
+/// by then? Only entries added since `prev_sth` can be checked, since there's no record of when
+/// older entries were first in the tree.
+fn merged_late(prev_sth: &LogSth, idx: u64, sct_timestamp: u64, log: &Log) -> bool {
+    let mmd_ms = log.max_merge_delay().num_milliseconds() as u64;
+    idx >= prev_sth.tree_size && sct_timestamp.saturating_add(mmd_ms) < prev_sth.timestamp
+}
+
+/// Adds a newly cached range to the range of cached entries. Entries are fetched in contiguous
+/// ranges, so only one range is kept. If the new range isn't next to it, it replaces it.
+fn extend_cached(cached: Option<(u64, u64)>, new_range: (u64, u64)) -> (u64, u64) {
+    cached
+        .and_then(|cached| HistState::merge_adjacent_ranges(cached, new_range))
+        .unwrap_or(new_range)
+}
+
+/// Checks that `last`, the last entry of a batch, is the entry at `end`. `leaf_index` is the index
+/// the log gives for `last`'s leaf hash. A leaf that was added more than once can be at any of its
+/// indices, so if it is somewhere else, `entry_at_end` is fetched to compare its leaf hash.
+async fn last_index_matches(
+    last: &GetEntriesItem,
+    end: u64,
+    leaf_index: impl Future<Output = Result<u64, FetchError>>,
+    entry_at_end: impl Future<Output = Result<GetEntriesItem, FetchError>>,
+) -> Result<bool, FetchError> {
+    if leaf_index.await? == end {
+        return Ok(true);
+    }
+    Ok(entry_at_end.await?.leaf_input.leaf_hash() == last.leaf_input.leaf_hash())
+}
+
+impl FetchState {
     pub async fn fetch_next_batch(
         self_mutex: &Mutex<Self>,
         ctx: &Mutex<Ctx>,
@@ -24,21 +61,36 @@ impl<'ctx> FetchState {
     ) -> Option<u64> {
         info!("Fetching batch of certs from \"{}\"", log.description);
         let id = LogId(log.log_id.clone());
-        let inner_ctx = ctx.lock().unwrap();
-        let next_batch = {
-            self_mutex
-                .lock()
-                .unwrap()
-                .next_batch(&inner_ctx, id.clone())
+        let inner_ctx = ctx.lock().await;
+        let (next_batch, prev_sth, cached, tree_size) = {
+            let state = self_mutex.lock().await;
+            let log_state = state.log_states.get(&id);
+            let prev_sth = log_state.and_then(|log_state| log_state.prev_sth.clone());
+            let cached = log_state.and_then(|log_state| log_state.cached);
+            let tree_size = log_state.map(|log_state| log_state.sth.tree_size);
+            (
+                state.next_batch(&inner_ctx, id.clone()),
+                prev_sth,
+                cached,
+                tree_size,
+            )
         };
         trace!("Desired range is {:?}", next_batch);
         if let Some((start, end)) = next_batch {
             assert!(start <= end);
             let fetcher = inner_ctx.fetcher.clone();
             let entries_future = fetcher.fetch_entries(log, start, end);
+            let metrics = inner_ctx.metrics.clone();
+            let verify_indices = inner_ctx.verify_indices;
             drop(inner_ctx);
+            let fetch_start = Instant::now();
             match entries_future.await {
                 Ok(entries) => {
+                    metrics.record_batch(
+                        &log.description,
+                        entries.len() as u64,
+                        fetch_start.elapsed(),
+                    );
                     assert!(
                         !entries.is_empty(),
                         "CT log sent empty response to get-entries"
@@ -54,7 +106,47 @@ impl<'ctx> FetchState {
                         entries.len(),
                     );
                     let end = new_end;
-                    let mut inner_ctx = ctx.lock().unwrap();
+                    // get-entries responses don't say which index each entry is at, so entries
+                    // are assumed to be in order from `start`. If a log left out or repeated an
+                    // entry, the last entry's index would be wrong.
+                    if verify_indices {
+                        let last = entries.last().expect("entries can't be empty");
+                        let tree_size = tree_size.expect("no data for log");
+                        let leaf_hash = last.leaf_input.leaf_hash();
+                        let index_matches = last_index_matches(
+                            last,
+                            end,
+                            fetcher.fetch_leaf_index(log, &leaf_hash, tree_size),
+                            async {
+                                Ok(fetcher
+                                    .fetch_entry_and_proof(log, end, tree_size)
+                                    .await?
+                                    .entry)
+                            },
+                        );
+                        match index_matches.await {
+                            Ok(true) => {}
+                            Ok(false) => {
+                                metrics.record_fetch_error(&log.description);
+                                warn!(
+                                    "\"{}\" sent a different entry than the one at {}, discarding {}-{}",
+                                    log.description, end, start, end
+                                );
+                                return None;
+                            }
+                            Err(err) => {
+                                metrics.record_fetch_error(&log.description);
+                                warn!(
+                                    "Failed to check the index of entry {} of \"{}\": {:?}",
+                                    end, log.description, err
+                                );
+                                return None;
+                            }
+                        }
+                    }
+                    let mut inner_ctx = ctx.lock().await;
+                    let cache_certs = inner_ctx.cache_certs;
+                    let force_recache = inner_ctx.force_recache;
                     let transient_entry = inner_ctx.log_transient.entry(id.clone()).or_default();
                     transient_entry.fetches += 1;
                     transient_entry.highest_page_size = transient_entry
@@ -63,7 +155,7 @@ impl<'ctx> FetchState {
                     let mut cert_insert = inner_ctx
                     .sqlite_conn
                         .prepare_cached(
-                            "INSERT OR IGNORE INTO certs (leaf_hash, extra_hash, not_before, not_after, cert_type) VALUES (?, ?, ?, ?, ?)",
+                            "INSERT OR IGNORE INTO certs (leaf_hash, extra_hash, not_before, not_after, cert_type, sct_count, serial, cn_matches_san, issuer_key) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
                         )
                         .unwrap();
                     let mut entry_insert = inner_ctx
@@ -76,90 +168,182 @@ impl<'ctx> FetchState {
                             "INSERT OR IGNORE INTO domains (leaf_hash, domain) VALUES (?, ?)",
                         )
                         .unwrap();
-                    let mut new_cache_items = Vec::new();
+                    let mut new_cache_certs = Vec::new();
+                    let mut new_cache_extra_data = Vec::new();
+                    let mut seen_leaf_hashes = Vec::new();
                     for (idx, entry) in entries.into_iter().enumerate() {
                         let idx: u64 = idx as u64 + start;
-                        let log_timestamp = entry.leaf_input.timestamped_entry.timestamp;
+                        let sct_timestamp = entry.leaf_input.timestamped_entry.timestamp;
+                        if let Some(prev_sth) = &prev_sth {
+                            if merged_late(prev_sth, idx, sct_timestamp, log) {
+                                warn!(
+                                    "idx {} of \"{}\" wasn't merged within the MMD: SCT timestamp {}, but not in tree of size {} at {}",
+                                    idx,
+                                    log.description,
+                                    sct_timestamp,
+                                    prev_sth.tree_size,
+                                    prev_sth.timestamp,
+                                );
+                                metrics.record_late_entry(&log.description);
+                            }
+                        }
                         let log_entry = &entry.leaf_input.timestamped_entry.log_entry;
                         let cert_bytes = log_entry.inner_cert();
-                        let (cert_type, cert) = if let LogEntry::X509(cert) = log_entry {
-                            let cert: x509_certificate::rfc5280::Certificate =
-                                x509_certificate::X509Certificate::from_der(cert)
-                                    .unwrap()
-                                    .into();
-                            ("cert", cert.tbs_certificate)
+                        let leaf_hash_bytes = entry.leaf_input.timestamped_entry.db_hash();
+                        if !inner_ctx.is_sampled(&leaf_hash_bytes) {
+                            trace!("idx {} of \"{}\" not sampled", idx, log.description);
+                            continue;
+                        }
+                        if inner_ctx.recent_leaf_hashes.contains(&leaf_hash_bytes) {
+                            // the cert was just stored from another log, so only this entry is new
+                            entry_insert
+                                .execute(rusqlite::params![
+                                    leaf_hash_bytes.to_vec(),
+                                    id.num(),
+                                    sct_timestamp,
+                                    idx
+                                ])
+                                .expect("failed to insert entry");
+                            continue;
+                        }
+                        let decoded = if let LogEntry::X509(cert) = log_entry {
+                            x509_certificate::X509Certificate::from_der(cert)
+                                .ok()
+                                .map(|cert| {
+                                    let cert: x509_certificate::rfc5280::Certificate = cert.into();
+                                    ("cert", cert.tbs_certificate)
+                                })
                         } else {
-                            let cert = Constructed::decode(
+                            Constructed::decode(
                                 cert_bytes.as_ref(),
                                 bcder::Mode::Der,
-                                |cons| x509_certificate::rfc5280::TbsCertificate::take_from(cons),
+                                x509_certificate::rfc5280::TbsCertificate::take_from,
                             )
-                            .expect("invalid cert in log");
-                            ("precert", cert)
+                            .ok()
+                            .map(|cert| ("precert", cert))
+                        };
+                        // skipped entries are still counted as fetched, so they aren't retried
+                        let (cert_type, cert) = match decoded {
+                            Some(decoded) => decoded,
+                            None => {
+                                warn!(
+                                    "idx {} of \"{}\" can't be decoded, skipping",
+                                    idx, log.description
+                                );
+                                metrics.record_invalid_entry(&log.description);
+                                continue;
+                            }
                         };
 
                         let domains = belvi_cert::get_cert_domains(&cert);
-                        assert!(!domains.contains(&b"&".to_vec()), "{:#?}", cert);
+                        if domains.contains(&b"&".to_vec()) {
+                            warn!(
+                                "idx {} of \"{}\" has an invalid domain, skipping",
+                                idx, log.description
+                            );
+                            metrics.record_invalid_entry(&log.description);
+                            continue;
+                        }
+                        let sct_count = belvi_cert::sct_count(&cert);
+                        let serial = belvi_cert::serial(&cert);
+                        let cn_matches_san = belvi_cert::cn_matches_san(&cert);
+                        let issuer_key = belvi_cert::issuer_key(&cert);
 
                         let validity = &cert.validity;
                         let not_before = validity.not_before.clone();
                         let not_after = validity.not_after.clone();
                         trace!(
-                            "idx {} of \"{}\": {} with ts {}, valid from {:?} to {:?}",
+                            "idx {} of \"{}\": {} with SCT timestamp {}, valid from {:?} to {:?}",
                             idx,
                             log.description,
                             cert_type,
-                            log_timestamp,
+                            sct_timestamp,
                             not_before,
                             not_after,
                         );
-                        let leaf_hash_bytes = belvi_hash::db(log_entry.inner_cert());
                         let leaf_hash = leaf_hash_bytes.to_vec();
                         let extra_hash = belvi_hash::db(&entry.extra_data);
-                        cert_insert
+                        let cert_inserted = cert_insert
                             .execute(rusqlite::params![
                                 leaf_hash,
                                 extra_hash.to_vec(),
                                 time_to_unix(not_before),
                                 time_to_unix(not_after),
                                 log_entry.num(),
+                                sct_count,
+                                serial,
+                                cn_matches_san,
+                                issuer_key,
                             ])
                             .expect("failed to insert cert");
                         entry_insert
-                            .execute(rusqlite::params![leaf_hash, id.num(), log_timestamp, idx])
+                            .execute(rusqlite::params![leaf_hash, id.num(), sct_timestamp, idx])
                             .expect("failed to insert entry");
-                        for domain in domains {
-                            domain_insert
-                                .execute(rusqlite::params![
-                                    leaf_hash,
-                                    String::from_utf8_lossy(&domain)
-                                ])
-                                .expect("failed to insert domain");
+                        // if the cert was already stored from another log (or an overlapping
+                        // fetch), its domains were stored along with it
+                        if cert_inserted > 0 {
+                            let mut domains: Vec<_> = domains
+                                .iter()
+                                .map(|domain| String::from_utf8_lossy(domain))
+                                .collect();
+                            // a cert can list the same name in more than one SAN
+                            domains.sort_unstable();
+                            domains.dedup();
+                            for domain in &domains {
+                                domain_insert
+                                    .execute(rusqlite::params![leaf_hash, domain])
+                                    .expect("failed to insert domain");
+                            }
+                            if let Some(watcher) = &inner_ctx.watchlist {
+                                if domains.iter().any(|domain| watcher.list.matches(domain)) {
+                                    watcher.found(WatchMatch {
+                                        leaf_hash: hex::encode(&leaf_hash),
+                                        domains: domains.iter().map(|d| d.to_string()).collect(),
+                                        log_id: log.log_id.clone(),
+                                        log_description: log.description.clone(),
+                                        idx,
+                                    });
+                                }
+                            }
                         }
-                        if inner_ctx.cache_certs {
-                            let cache_item_contents = log_entry.inner_cert().clone();
-                            new_cache_items.push((leaf_hash_bytes, cache_item_contents));
+                        let already_cached = cached.is_some_and(|(cached_start, cached_end)| {
+                            (cached_start..=cached_end).contains(&idx)
+                        });
+                        if cache_certs && (force_recache || !already_cached) {
+                            new_cache_certs.push((leaf_hash_bytes, log_entry.inner_cert().clone()));
+                            new_cache_extra_data.push((extra_hash, entry.extra_data));
                         }
+                        seen_leaf_hashes.push(leaf_hash_bytes);
                     }
                     drop(cert_insert);
                     drop(entry_insert);
                     drop(domain_insert);
-                    // TODO: parallelize
-                    for (id, content) in new_cache_items {
-                        inner_ctx.redis_conn.new_cert(&id, &content); // disable by default
+                    for leaf_hash in seen_leaf_hashes {
+                        inner_ctx.recent_leaf_hashes.insert(leaf_hash);
                     }
+                    inner_ctx.redis_conn.new_cert_batch(&new_cache_certs);
+                    // most entries in a batch share a few chains, so only send each one once
+                    new_cache_extra_data.sort_unstable_by_key(|(extra_hash, _)| *extra_hash);
+                    new_cache_extra_data.dedup_by_key(|(extra_hash, _)| *extra_hash);
+                    inner_ctx
+                        .redis_conn
+                        .new_extra_data_batch(&new_cache_extra_data);
                     drop(inner_ctx);
                     debug!("Fetched {}-{} from \"{}\"", start, end, log.description);
                     // adjust log_states
                     {
-                        let mut self_inner = self_mutex.lock().unwrap();
+                        let mut self_inner = self_mutex.lock().await;
                         let log_state =
                             self_inner.log_states.get_mut(&id).expect("no data for log");
                         log_state.fetched_to = log_state.fetched_to.merge_fetched((start, end));
+                        if cache_certs {
+                            log_state.cached = Some(extend_cached(log_state.cached, (start, end)));
+                        }
                     }
                     Some(end - start + 1)
                 }
                 Err(err) => {
+                    metrics.record_fetch_error(&log.description);
                     warn!(
                         "Failed to fetch certs for \"{}\" (range: {}-{}): {:?}",
                         log.description, start, end, err
@@ -173,3 +357,70 @@ impl<'ctx> FetchState {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn late_entries() {
+        let mut log = belvi_log_list::LogList::google()
+            .logs()
+            .next()
+            .unwrap()
+            .clone();
+        log.mmd = 86400;
+        let prev_sth = LogSth {
+            tree_size: 100,
+            timestamp: 200_000_000,
+            sha256_root_hash: String::new(),
+            tree_head_signature: String::new(),
+        };
+        // already in the previous tree
+        assert!(!merged_late(&prev_sth, 99, 0, &log));
+        // the MMD hadn't passed yet
+        assert!(!merged_late(&prev_sth, 100, 200_000_000 - 86_400_000, &log));
+        assert!(merged_late(&prev_sth, 100, 200_000_000 - 86_400_001, &log));
+    }
+
+    #[test]
+    fn cached_ranges() {
+        assert_eq!(extend_cached(None, (100, 199)), (100, 199));
+        // fetching forwards and backwards
+        assert_eq!(extend_cached(Some((100, 199)), (200, 299)), (100, 299));
+        assert_eq!(extend_cached(Some((100, 199)), (0, 99)), (0, 199));
+        // an overlapping refetch
+        assert_eq!(extend_cached(Some((100, 199)), (190, 249)), (100, 249));
+        // caching was turned off in between
+        assert_eq!(extend_cached(Some((100, 199)), (500, 599)), (500, 599));
+    }
+
+    #[tokio::test]
+    async fn last_indices() {
+        let entries = GetEntriesItem::parse(include_str!(
+            "../../belvi_log_list/test_data/argon2021-get-entries?start=0&end=1.json"
+        ))
+        .unwrap();
+        let (first, last) = (&entries[0], &entries[1]);
+        let not_fetched = || async { panic!("the entry at the end shouldn't be fetched") };
+        assert!(last_index_matches(last, 1, async { Ok(1) }, not_fetched())
+            .await
+            .unwrap());
+        // the same leaf is also at 0, so the log can give either index
+        assert!(
+            last_index_matches(last, 1, async { Ok(0) }, async { Ok(last.clone()) })
+                .await
+                .unwrap()
+        );
+        // an entry was left out or repeated before the end
+        assert!(
+            !last_index_matches(last, 1, async { Ok(0) }, async { Ok(first.clone()) })
+                .await
+                .unwrap()
+        );
+        assert!(matches!(
+            last_index_matches(last, 1, async { Err(FetchError::BadStatus) }, not_fetched()).await,
+            Err(FetchError::BadStatus)
+        ));
+    }
+}
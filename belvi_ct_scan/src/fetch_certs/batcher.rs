@@ -1,68 +1,31 @@
 // SPDX-License-Identifier: Apache-2.0
-use crate::{Ctx, FetchState, LogId};
+use crate::{Ctx, FetchState, LogId, LogTransient};
+pub use belvi_log_list::fetch_status::HistState;
 use log::trace;
-use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 
-/// Initially request certificates in batches of this size.
-const MAX_PAGE_SIZE: u64 = 1000;
-/// To improve server-side log caching, after N requests limit the page size to the learned value.
-const FETCHES_FOR_SMALLER_PAGES: u64 = 10;
-/// We always want at least the last N certs for every log.
-const MIN_HISTORY: u64 = 5000;
-
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub enum HistState {
-    NothingFetched,
-    FillingHistGap {
-        hist_gap: (u64, u64),
-        fetching: (u64, u64),
-    },
-    Fetching((u64, u64)),
+/// Settings for choosing which entries to fetch next.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BatchConfig {
+    /// Initially request certificates in batches of this size.
+    pub max_page_size: u64,
+    /// To improve server-side log caching, after N requests limit the page size to the learned
+    /// value.
+    pub fetches_for_smaller_pages: u64,
+    /// We always want at least the last N certs for every log. If this is `u64::MAX`, entries are
+    /// fetched back to the start of the log.
+    pub min_history: u64,
+    /// Number of already-fetched entries to fetch again when fetching new entries.
+    pub fetch_overlap: u64,
 }
 
-impl Default for HistState {
+impl Default for BatchConfig {
     fn default() -> Self {
-        Self::NothingFetched
-    }
-}
-
-impl HistState {
-    #[must_use]
-    fn merge_adjacent_ranges((a1, a2): (u64, u64), (b1, b2): (u64, u64)) -> Option<(u64, u64)> {
-        if a1 == (b2 + 1) {
-            // going forwards
-            Some((b1, a2))
-        } else if a2 == (b1 - 1) {
-            // going backwardss
-            Some((a1, b2))
-        } else {
-            None
-        }
-    }
-    #[must_use]
-    pub fn merge_fetched(self, new_range: (u64, u64)) -> Self {
-        match self {
-            Self::NothingFetched => Self::Fetching(new_range),
-            Self::Fetching(fetched) => {
-                if let Some(updated) = Self::merge_adjacent_ranges(fetched, new_range) {
-                    Self::Fetching(updated)
-                } else {
-                    Self::FillingHistGap {
-                        fetching: fetched,
-                        hist_gap: new_range,
-                    }
-                }
-            }
-            Self::FillingHistGap { hist_gap, fetching } => {
-                let hist_gap = Self::merge_adjacent_ranges(hist_gap, new_range)
-                    .expect("non adjacent range when filling hist gap");
-                if let Some(combined) = Self::merge_adjacent_ranges(hist_gap, fetching) {
-                    Self::Fetching(combined)
-                } else {
-                    Self::FillingHistGap { fetching, hist_gap }
-                }
-            }
+        Self {
+            max_page_size: 1000,
+            fetches_for_smaller_pages: 10,
+            min_history: 5000,
+            fetch_overlap: 0,
         }
     }
 }
@@ -73,38 +36,52 @@ impl FetchState {
     /// nothing should be fetched. The return value will be adjacent to the current fetched
     /// endpoints.
     pub fn next_batch(&self, ctx: &Ctx, id: LogId) -> Option<(u64, u64)> {
-        let transient = ctx
-            .log_transient
-            .get(&id)
-            .map(Clone::clone)
-            .unwrap_or_default();
+        let transient = ctx.log_transient.get(&id).copied().unwrap_or_default();
+        self.next_batch_with(&ctx.batch_config, transient, id)
+    }
+
+    fn next_batch_with(
+        &self,
+        config: &BatchConfig,
+        transient: LogTransient,
+        id: LogId,
+    ) -> Option<(u64, u64)> {
         let state = self
             .log_states
             .get(&id)
             .expect("next_batch called with bad id");
 
-        let page_size = if transient.fetches > FETCHES_FOR_SMALLER_PAGES {
+        let page_size = if transient.fetches > config.fetches_for_smaller_pages {
             transient.highest_page_size
         } else {
-            MAX_PAGE_SIZE
+            config.max_page_size
         };
 
+        if state.sth.tree_size == 0 {
+            // nothing has been logged yet
+            return None;
+        }
         // subtract 1 to account for 0-indexing
-        let tree_size = state.sth.tree_size.saturating_sub(1);
+        let tree_size = state.sth.tree_size - 1;
 
         // start and end are both inclusive bounds!
         #[must_use]
-        fn extend_range(cur_start: u64, cur_end: u64, endpoint: u64) -> Option<(u64, u64)> {
+        fn extend_range(
+            config: &BatchConfig,
+            cur_start: u64,
+            cur_end: u64,
+            endpoint: u64,
+        ) -> Option<(u64, u64)> {
             match cur_end.cmp(&endpoint) {
                 // we have got to the endpoint
                 Ordering::Equal => {
                     trace!("Fetched up to endpoint");
-                    let desired_start = cur_end.saturating_sub(MIN_HISTORY);
+                    let desired_start = cur_end.saturating_sub(config.min_history);
                     if desired_start < cur_start {
                         Some((
                             cur_start
-                                .saturating_sub(MIN_HISTORY)
-                                .max(cur_start.saturating_sub(MAX_PAGE_SIZE)),
+                                .saturating_sub(config.min_history)
+                                .max(cur_start.saturating_sub(config.max_page_size)),
                             cur_start - 1,
                         ))
                     } else {
@@ -115,9 +92,12 @@ impl FetchState {
                 Ordering::Less => {
                     trace!("Haven't fetched to endpoint");
                     Some((
-                        // from the current end, fetch up to a page to get closer to the endpoint
-                        cur_end + 1,
-                        endpoint.min(cur_end + MAX_PAGE_SIZE),
+                        // from the current end, fetch up to a page to get closer to the endpoint,
+                        // refetching the last few entries if an overlap is configured
+                        (cur_end + 1)
+                            .saturating_sub(config.fetch_overlap)
+                            .max(cur_start),
+                        endpoint.min(cur_end + config.max_page_size),
                     ))
                 }
                 Ordering::Greater => {
@@ -138,12 +118,166 @@ impl FetchState {
                 ))
             }
             HistState::Fetching((cur_start, cur_end)) => {
-                extend_range(cur_start, cur_end, tree_size)
+                extend_range(config, cur_start, cur_end, tree_size)
             }
             HistState::FillingHistGap {
                 hist_gap: (hist_gap_start, hist_gap_end),
                 fetching: (fetching_start, _fetching_end),
-            } => extend_range(hist_gap_start, hist_gap_end, fetching_start - 1),
+            } => extend_range(config, hist_gap_start, hist_gap_end, fetching_start - 1),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::LogFetchState;
+    use belvi_log_list::log_data::LogSth;
+    use std::collections::HashMap;
+
+    #[test]
+    fn merge_ranges() {
+        // adjacent
+        assert_eq!(
+            HistState::merge_adjacent_ranges((10, 20), (21, 30)),
+            Some((10, 30))
+        );
+        assert_eq!(
+            HistState::merge_adjacent_ranges((10, 20), (0, 9)),
+            Some((0, 20))
+        );
+        // overlapping
+        assert_eq!(
+            HistState::merge_adjacent_ranges((10, 20), (15, 30)),
+            Some((10, 30))
+        );
+        assert_eq!(
+            HistState::merge_adjacent_ranges((10, 20), (12, 18)),
+            Some((10, 20))
+        );
+        // disjoint
+        assert_eq!(HistState::merge_adjacent_ranges((10, 20), (22, 30)), None);
+        assert_eq!(HistState::merge_adjacent_ranges((0, 0), (2, 3)), None);
+    }
+
+    fn fetch_state(tree_size: u64, fetched_to: HistState) -> (FetchState, LogId) {
+        let id = LogId("test".to_string());
+        let mut log_states = HashMap::new();
+        log_states.insert(
+            id.clone(),
+            LogFetchState {
+                sth: LogSth {
+                    tree_size,
+                    timestamp: 0,
+                    sha256_root_hash: String::new(),
+                    tree_head_signature: String::new(),
+                },
+                fetched_to,
+                prev_sth: None,
+                cached: None,
+            },
+        );
+        let state = FetchState {
+            state_ver: 1,
+            log_states,
+        };
+        (state, id)
+    }
+
+    #[test]
+    fn first_sth() {
+        let (state, id) = fetch_state(20_000, HistState::default());
+        assert_eq!(state.log_states[&id].fetched_to, HistState::NothingFetched);
+        // starts with the newest page
+        assert_eq!(
+            state.next_batch_with(&BatchConfig::default(), LogTransient::default(), id),
+            Some((19_000, 19_999))
+        );
+        let (state, id) = fetch_state(10, HistState::default());
+        assert_eq!(
+            state.next_batch_with(&BatchConfig::default(), LogTransient::default(), id),
+            Some((0, 9))
+        );
+        // an empty log has nothing to fetch
+        let (state, id) = fetch_state(0, HistState::default());
+        assert_eq!(
+            state.next_batch_with(&BatchConfig::default(), LogTransient::default(), id),
+            None
+        );
+    }
+
+    #[test]
+    fn raised_min_history() {
+        // the last 6000 entries have been fetched
+        let (state, id) = fetch_state(20_000, HistState::Fetching((14_000, 19_999)));
+        let config = BatchConfig::default();
+        assert_eq!(
+            state.next_batch_with(&config, LogTransient::default(), id.clone()),
+            None
+        );
+        let config = BatchConfig {
+            min_history: 10_000,
+            ..config
+        };
+        assert_eq!(
+            state.next_batch_with(&config, LogTransient::default(), id.clone()),
+            Some((13_000, 13_999))
+        );
+        // pages are still limited to max_page_size
+        let config = BatchConfig {
+            max_page_size: 5000,
+            ..config
+        };
+        assert_eq!(
+            state.next_batch_with(&config, LogTransient::default(), id),
+            Some((9_000, 13_999))
+        );
+    }
+
+    #[test]
+    fn full_history() {
+        let config = BatchConfig {
+            min_history: u64::MAX,
+            ..BatchConfig::default()
+        };
+        let (mut state, id) = fetch_state(2500, HistState::Fetching((2000, 2499)));
+        let fetch = |state: &mut FetchState| {
+            let range = state.next_batch_with(&config, LogTransient::default(), id.clone());
+            if let Some(range) = range {
+                let log_state = state.log_states.get_mut(&id).unwrap();
+                log_state.fetched_to = log_state.fetched_to.merge_fetched(range);
+            }
+            range
+        };
+        assert_eq!(fetch(&mut state), Some((1000, 1999)));
+        // new entries are fetched before continuing backwards
+        state.log_states.get_mut(&id).unwrap().sth.tree_size = 2600;
+        assert_eq!(fetch(&mut state), Some((2500, 2599)));
+        assert_eq!(fetch(&mut state), Some((0, 999)));
+        assert_eq!(fetch(&mut state), None);
+        assert_eq!(
+            state.log_states[&id].fetched_to,
+            HistState::Fetching((0, 2599))
+        );
+    }
+
+    #[test]
+    fn readable_by_fetch_status() {
+        use belvi_log_list::fetch_status::FetchStatus;
+        let fetched_to = HistState::FillingHistGap {
+            hist_gap: (10, 19),
+            fetching: (50, 99),
+        };
+        let (state, id) = fetch_state(100, fetched_to);
+        let status: FetchStatus =
+            serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+        assert_eq!(status.log_states[&id].fetched_to, fetched_to);
+        assert_eq!(status.log_states[&id].sth.tree_size, 100);
+    }
+
+    #[test]
+    fn merge_overlapping_fetch() {
+        let state = HistState::Fetching((100, 200)).merge_fetched((195, 300));
+        assert_eq!(state, HistState::Fetching((100, 300)));
+    }
+}
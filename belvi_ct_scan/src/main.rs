@@ -1,20 +1,75 @@
 // SPDX-License-Identifier: Apache-2.0
 use chrono::{DateTime, Utc};
+use clap::Parser;
+use futures::StreamExt;
 use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet},
     env, fs,
+    net::SocketAddr,
     path::PathBuf,
-    sync::{atomic, Mutex},
+    sync::{atomic, Arc},
     time::{Duration, Instant},
 };
 
 mod fetch_certs;
+mod metrics;
+mod recent;
 mod update_sths;
+mod watchlist;
 
 use belvi_log_list::{fetcher::Fetcher, log_data::LogSth};
 use belvi_log_list::{Log, LogId, LogList};
+use fetch_certs::batcher::BatchConfig;
+use tokio::sync::Mutex;
+
+#[derive(Debug, Parser)]
+#[clap(about = "Fetches certificates from all active CT logs and stores them in the database")]
+struct Args {
+    /// Directory to store the database and fetch state in
+    #[clap(long, env = "BELVI_DATA_DIR")]
+    data_dir: PathBuf,
+    /// Don't add certificates to the Redis cache (also set by BELVI_NO_CACHE)
+    #[clap(long)]
+    no_cache: bool,
+    /// Add certificates to the Redis cache even if they were cached when they were last fetched
+    #[clap(long)]
+    force_recache: bool,
+    /// Fetch the log list from this URL instead of using the bundled one
+    #[clap(long, env = "BELVI_LOG_LIST_URL")]
+    log_list_url: Option<String>,
+    /// Maximum number of logs to fetch batches from at once
+    #[clap(long, env = "BELVI_CONCURRENCY", default_value_t = DEFAULT_CONCURRENCY)]
+    concurrency: usize,
+    /// Number of entries to request from a log at once
+    #[clap(
+        long,
+        env = "BELVI_MAX_PAGE_SIZE",
+        default_value_t = BatchConfig::default().max_page_size,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    max_page_size: u64,
+    /// After this many batches from a log, limit the page size to the largest page it has sent
+    #[clap(long, env = "BELVI_FETCHES_FOR_SMALLER_PAGES", default_value_t = BatchConfig::default().fetches_for_smaller_pages)]
+    fetches_for_smaller_pages: u64,
+    /// Number of recent entries to fetch from each log
+    #[clap(
+        long,
+        env = "BELVI_MIN_HISTORY",
+        default_value_t = BatchConfig::default().min_history
+    )]
+    min_history: u64,
+    /// Fetch every entry of each log, back to the first one (overrides --min-history)
+    #[clap(long)]
+    full_history: bool,
+    /// File with domains to watch for, one per line (regexes go between slashes)
+    #[clap(long, env = "BELVI_WATCHLIST")]
+    watchlist: Option<PathBuf>,
+    /// URL that certs for domains on the watchlist are POSTed to
+    #[clap(long, env = "BELVI_WATCHLIST_WEBHOOK", requires = "watchlist")]
+    watchlist_webhook: Option<String>,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct FetchState {
@@ -50,6 +105,13 @@ impl FetchState {
 struct LogFetchState {
     sth: LogSth,
     fetched_to: fetch_certs::batcher::HistState,
+    /// The STH from before the tree last grew, used to find entries that were merged late.
+    #[serde(default)]
+    prev_sth: Option<LogSth>,
+    /// The range of entries (inclusive) whose certs have been added to the Redis cache. They
+    /// aren't cached again when they are fetched again, unless `--force-recache` is passed.
+    #[serde(default)]
+    cached: Option<(u64, u64)>,
 }
 
 #[derive(Debug)]
@@ -62,7 +124,21 @@ struct Ctx {
     fetcher: Fetcher,
     start_time: DateTime<Utc>,
     cache_certs: bool,
+    force_recache: bool,
+    /// Check the index of the last entry of each batch with the log.
+    verify_indices: bool,
+    /// Only certs with a leaf hash whose first byte is less than this are stored. Since the leaf
+    /// hash doesn't depend on the log, a cert is either sampled in every log or in none of them.
+    sample_threshold: u16,
+    batch_config: BatchConfig,
     log_transient: HashMap<LogId, LogTransient>,
+    /// Leaf hashes of certs that were recently stored, so they don't need to be processed again
+    /// when they are fetched from another log.
+    recent_leaf_hashes: recent::RecentSet<[u8; 16]>,
+    metrics: Arc<metrics::Metrics>,
+    watchlist: Option<watchlist::Watcher>,
+    /// URL that append-only violations are POSTed to.
+    violation_webhook: Option<String>,
     sqlite_conn: rusqlite::Connection,
     redis_conn: belvi_cache::Connection,
 }
@@ -83,11 +159,10 @@ impl Default for LogTransient {
 }
 
 impl Ctx {
-    // redis_conn is an argument since it can only be created in an async fn
-    fn from_env_sync(redis_conn: belvi_cache::Connection) -> Self {
-        let mut args = env::args_os();
-        let data_path: PathBuf = args.nth(1).unwrap().into();
-        let fetch_state_path = data_path.join("state.json");
+    // redis_conn and log_list are arguments since they can only be created in an async fn
+    fn from_args_sync(args: Args, redis_conn: belvi_cache::Connection, log_list: LogList) -> Self {
+        let data_path = args.data_dir;
+        let fetch_state_path = data_path.join(belvi_log_list::fetch_status::FETCH_STATE_FILE);
         let certs_path = data_path.join("certs");
         if !certs_path.exists() {
             warn!("certs directory doesn't exist; creating");
@@ -95,21 +170,71 @@ impl Ctx {
         }
         let start_time = Utc::now();
         debug!("Start time is {:?}", start_time);
-        let cache_certs = env::var("BELVI_NO_CACHE").is_err();
-        let sqlite_conn = belvi_db::connect();
+        let cache_certs = !args.no_cache && env::var("BELVI_NO_CACHE").is_err();
+        let sample_threshold = match env::var("BELVI_SAMPLE_PERCENT") {
+            Ok(percent) => {
+                let percent: f64 = percent.parse().expect("invalid BELVI_SAMPLE_PERCENT");
+                assert!(
+                    percent > 0.0 && percent <= 100.0,
+                    "BELVI_SAMPLE_PERCENT must be in (0, 100]"
+                );
+                info!("Storing a {}% sample of certs", percent);
+                // always sample at least 1/256 of certs
+                ((percent / 100.0 * 256.0).round() as u16).max(1)
+            }
+            Err(_) => 256,
+        };
+        let fetch_overlap = match env::var("BELVI_FETCH_OVERLAP") {
+            Ok(overlap) => overlap.parse().expect("invalid BELVI_FETCH_OVERLAP"),
+            Err(_) => 0,
+        };
+        let sqlite_conn = belvi_db::connect_at(&data_path);
+        if env::var("BELVI_FULL_TEXT").is_ok() {
+            belvi_db::enable_full_text(&sqlite_conn);
+        }
+        let stmt_cache_size = match env::var("BELVI_STMT_CACHE_SIZE") {
+            Ok(size) => size.parse().expect("invalid BELVI_STMT_CACHE_SIZE"),
+            Err(_) => DEFAULT_STMT_CACHE_SIZE,
+        };
+        sqlite_conn.set_prepared_statement_cache_capacity(stmt_cache_size);
+        let watchlist = args.watchlist.map(|path| {
+            let text = fs::read_to_string(&path).expect("failed to read watchlist");
+            let list = watchlist::Watchlist::parse(&text).expect("invalid regex in watchlist");
+            watchlist::Watcher::new(list, args.watchlist_webhook)
+        });
         Ctx {
             data_path,
             fetch_state_path,
             certs_path,
             start_time,
             cache_certs,
+            force_recache: args.force_recache,
+            verify_indices: env::var("BELVI_VERIFY_INDICES").is_ok(),
+            sample_threshold,
+            batch_config: BatchConfig {
+                max_page_size: args.max_page_size,
+                fetches_for_smaller_pages: args.fetches_for_smaller_pages,
+                min_history: if args.full_history {
+                    u64::MAX
+                } else {
+                    args.min_history
+                },
+                fetch_overlap,
+            },
             sqlite_conn,
             log_transient: HashMap::new(),
-            log_list: LogList::google(),
+            recent_leaf_hashes: recent::RecentSet::new(RECENT_LEAF_HASHES),
+            metrics: Arc::new(metrics::Metrics::default()),
+            watchlist,
+            violation_webhook: env::var("BELVI_VIOLATION_WEBHOOK").ok(),
+            log_list,
             fetcher: Fetcher::new(),
             redis_conn,
         }
     }
+    fn is_sampled(&self, leaf_hash: &[u8; 16]) -> bool {
+        u16::from(leaf_hash[0]) < self.sample_threshold
+    }
     fn active_logs(&self) -> impl Iterator<Item = &Log> {
         self.log_list
             .logs()
@@ -117,6 +242,14 @@ impl Ctx {
     }
 }
 
+/// Statements prepared with `prepare_cached` are kept in a cache of this size. Throughput drops
+/// sharply once the cache is smaller than the number of statements used for each entry, so this
+/// leaves plenty of room for more.
+const DEFAULT_STMT_CACHE_SIZE: usize = 32;
+/// Number of recently stored leaf hashes to remember. Each one takes roughly 40 bytes.
+const RECENT_LEAF_HASHES: usize = 1_000_000;
+/// Fetching from too many logs at once makes requests compete for the network and the database.
+const DEFAULT_CONCURRENCY: usize = 8;
 const MAX_RECHECK_GAP: u64 = 90;
 const WAIT_TIME: u64 = 8;
 
@@ -124,6 +257,7 @@ static STOP_FETCHING: atomic::AtomicBool = atomic::AtomicBool::new(false);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
     env_logger::init();
     info!("Starting Belvi fetcher");
 
@@ -133,13 +267,31 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         STOP_FETCHING.store(true, atomic::Ordering::Relaxed);
     });
 
-    let ctx = Ctx::from_env_sync(belvi_cache::Connection::new().await);
+    let log_list = match &args.log_list_url {
+        Some(url) => {
+            info!("Fetching log list from {}", url);
+            Fetcher::new()
+                .fetch_log_list(url)
+                .await
+                .unwrap_or_else(|err| panic!("Failed to fetch log list: {:?}", err))
+        }
+        None => LogList::google(),
+    };
+    // times in the log list are used throughout scanning, so check them once here
+    if let Err(err) = log_list.validate() {
+        panic!("Invalid log list: {:?}", err);
+    }
+    let concurrency = args.concurrency.max(1);
+    let ctx = Ctx::from_args_sync(args, belvi_cache::Connection::new().await, log_list);
+    if let Ok(addr) = env::var("BELVI_METRICS_ADDR") {
+        let addr: SocketAddr = addr.parse().expect("invalid BELVI_METRICS_ADDR");
+        tokio::spawn(metrics::serve(ctx.metrics.clone(), addr));
+    }
     let mut fetch_state = FetchState::new_sync(&ctx);
 
     fetch_state.update_sths(&ctx).await;
     fetch_state.save(&ctx).await;
     let mut last_fetch_state_check = Instant::now();
-    // TODO: use Tokio mutex
     let fetch_state = Mutex::new(fetch_state);
 
     let mut active_logs: Vec<Log> = ctx.active_logs().cloned().collect();
@@ -152,21 +304,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let ctx = Mutex::new(ctx);
     loop {
         fastrand::shuffle(&mut active_logs);
-        let mut futures = Vec::new();
-        let mut logs = Vec::new();
-        for log in &active_logs {
-            if checked_logs.contains(&log.log_id) {
-                continue;
-            }
-            futures.push(FetchState::fetch_next_batch(&fetch_state, &ctx, log));
-            logs.push(log);
-        }
-        for (idx, count) in futures::future::join_all(futures)
-            .await
-            .into_iter()
-            .enumerate()
-        {
-            let log = logs[idx];
+        let (fetch_state_ref, ctx_ref) = (&fetch_state, &ctx);
+        let counts: Vec<_> = futures::stream::iter(
+            active_logs
+                .iter()
+                .filter(|log| !checked_logs.contains(&log.log_id)),
+        )
+        .map(|log| async move {
+            let count = FetchState::fetch_next_batch(fetch_state_ref, ctx_ref, log).await;
+            (log, count)
+        })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+        for (log, count) in counts {
             if let Some(count) = count {
                 info!("Fetched {} certs from \"{}\"", count, log.description);
             } else {
@@ -181,8 +332,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         if long_time_since_recheck || nothing_left || stop_fetching {
             // save state
-            let inner_ctx = ctx.lock().unwrap();
-            let mut inner_fetch_state = fetch_state.lock().unwrap();
+            let inner_ctx = ctx.lock().await;
+            let mut inner_fetch_state = fetch_state.lock().await;
             inner_fetch_state.save(&inner_ctx).await;
             inner_ctx
                 .sqlite_conn
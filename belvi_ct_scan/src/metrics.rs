@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Per-log metrics, served in the Prometheus text format if `BELVI_METRICS_ADDR` is set.
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Response, Server,
+};
+use log::info;
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[derive(Debug, Default, Clone, Copy)]
+struct LogMetrics {
+    entries_fetched: u64,
+    batches: u64,
+    batch_seconds: f64,
+    fetch_errors: u64,
+    invalid_entries: u64,
+    late_entries: u64,
+    tree_size: Option<u64>,
+}
+
+/// Gets the value of a metric for a log, or `None` if it has no value.
+type Value = fn(&LogMetrics) -> Option<f64>;
+
+/// Metrics for every log, keyed by log description. These are kept separate from `Ctx` so they
+/// can be read without waiting for a batch to finish.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    logs: Mutex<BTreeMap<String, LogMetrics>>,
+}
+
+/// Escapes a label value as described in the Prometheus text format.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Metrics {
+    fn update(&self, log: &str, f: impl FnOnce(&mut LogMetrics)) {
+        f(self
+            .logs
+            .lock()
+            .unwrap()
+            .entry(log.to_string())
+            .or_default());
+    }
+
+    pub fn record_batch(&self, log: &str, entries: u64, duration: Duration) {
+        self.update(log, |metrics| {
+            metrics.entries_fetched += entries;
+            metrics.batches += 1;
+            metrics.batch_seconds += duration.as_secs_f64();
+        });
+    }
+
+    pub fn record_fetch_error(&self, log: &str) {
+        self.update(log, |metrics| metrics.fetch_errors += 1);
+    }
+
+    pub fn record_invalid_entry(&self, log: &str) {
+        self.update(log, |metrics| metrics.invalid_entries += 1);
+    }
+
+    pub fn record_late_entry(&self, log: &str) {
+        self.update(log, |metrics| metrics.late_entries += 1);
+    }
+
+    pub fn set_tree_size(&self, log: &str, tree_size: u64) {
+        self.update(log, |metrics| metrics.tree_size = Some(tree_size));
+    }
+
+    /// Renders the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let logs = self.logs.lock().unwrap();
+        let mut out = String::new();
+        // each sample is a suffix of the metric name and its value
+        let mut family = |name: &str, typ: &str, help: &str, samples: &[(&str, Value)]| {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} {}", name, typ).unwrap();
+            for (suffix, value) in samples {
+                for (log, metrics) in logs.iter() {
+                    if let Some(value) = value(metrics) {
+                        writeln!(
+                            out,
+                            "{}{}{{log=\"{}\"}} {}",
+                            name,
+                            suffix,
+                            escape_label(log),
+                            value
+                        )
+                        .unwrap();
+                    }
+                }
+            }
+        };
+        let mut metric = |name: &str, typ: &str, help: &str, value| {
+            family(name, typ, help, &[("", value)]);
+        };
+        metric(
+            "belvi_entries_fetched_total",
+            "counter",
+            "Log entries fetched.",
+            |m| Some(m.entries_fetched as f64),
+        );
+        metric(
+            "belvi_fetch_errors_total",
+            "counter",
+            "Failed get-entries requests.",
+            |m| Some(m.fetch_errors as f64),
+        );
+        metric(
+            "belvi_invalid_entries_total",
+            "counter",
+            "Entries that were skipped because they couldn't be decoded.",
+            |m| Some(m.invalid_entries as f64),
+        );
+        metric(
+            "belvi_late_entries_total",
+            "counter",
+            "Entries that weren't in the tree by their SCT timestamp plus the log's MMD.",
+            |m| Some(m.late_entries as f64),
+        );
+        metric(
+            "belvi_tree_size",
+            "gauge",
+            "Tree size from the latest STH.",
+            |m| m.tree_size.map(|size| size as f64),
+        );
+        family(
+            "belvi_batch_duration_seconds",
+            "summary",
+            "Time spent fetching batches of entries.",
+            &[
+                ("_sum", |m| Some(m.batch_seconds)),
+                ("_count", |m| Some(m.batches as f64)),
+            ],
+        );
+        out
+    }
+}
+
+/// Serves the metrics at `/metrics` (or any other path) on `addr`.
+pub async fn serve(metrics: Arc<Metrics>, addr: SocketAddr) {
+    info!("Serving metrics on {}", addr);
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |_req| {
+                let body = metrics.render();
+                async move {
+                    Ok::<_, Infallible>(
+                        Response::builder()
+                            .header("Content-Type", "text/plain; version=0.0.4")
+                            .body(Body::from(body))
+                            .unwrap(),
+                    )
+                }
+            }))
+        }
+    });
+    Server::bind(&addr).serve(make_service).await.unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn renders() {
+        let metrics = Metrics::default();
+        metrics.record_batch("Log \"A\"", 100, Duration::from_millis(500));
+        metrics.record_batch("Log \"A\"", 50, Duration::from_millis(250));
+        metrics.record_fetch_error("Log B");
+        metrics.set_tree_size("Log B", 1234);
+        metrics.record_invalid_entry("Log B");
+        let rendered = metrics.render();
+        assert!(rendered.contains("belvi_entries_fetched_total{log=\"Log \\\"A\\\"\"} 150\n"));
+        assert!(rendered.contains("# TYPE belvi_batch_duration_seconds summary\n"));
+        assert!(rendered.contains("belvi_batch_duration_seconds_sum{log=\"Log \\\"A\\\"\"} 0.75\n"));
+        assert!(rendered.contains("belvi_batch_duration_seconds_count{log=\"Log \\\"A\\\"\"} 2\n"));
+        assert!(rendered.contains("belvi_fetch_errors_total{log=\"Log B\"} 1\n"));
+        assert!(rendered.contains("belvi_tree_size{log=\"Log B\"} 1234\n"));
+        assert!(rendered.contains("belvi_invalid_entries_total{log=\"Log B\"} 1\n"));
+        assert!(!rendered.contains("belvi_tree_size{log=\"Log \\\"A\\\"\"}"));
+    }
+}
@@ -0,0 +1,55 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Remembers recently seen items, using a bounded amount of memory.
+use std::{collections::HashSet, hash::Hash};
+
+/// A set that forgets old items once it gets too big. Items are kept in two generations; when the
+/// current generation is full, it becomes the old generation and the previous old generation is
+/// dropped. This always remembers at least the last `capacity / 2` items inserted.
+#[derive(Debug)]
+pub struct RecentSet<T> {
+    capacity: usize,
+    current: HashSet<T>,
+    old: HashSet<T>,
+}
+
+impl<T: Eq + Hash> RecentSet<T> {
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            current: HashSet::new(),
+            old: HashSet::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn contains(&self, item: &T) -> bool {
+        self.current.contains(item) || self.old.contains(item)
+    }
+
+    pub fn insert(&mut self, item: T) {
+        if self.current.len() >= self.capacity / 2 {
+            self.old = std::mem::take(&mut self.current);
+        }
+        self.current.insert(item);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn forgets_old_items() {
+        let mut set = RecentSet::new(4);
+        for i in 0..4 {
+            set.insert(i);
+        }
+        assert!((0..4).all(|i| set.contains(&i)));
+        set.insert(4);
+        assert!(!set.contains(&0));
+        assert!(!set.contains(&1));
+        assert!((2..5).all(|i| set.contains(&i)));
+        assert!(set.current.len() + set.old.len() <= 4);
+    }
+}
@@ -1,6 +1,55 @@
 // SPDX-License-Identifier: Apache-2.0
 use crate::{fetch_certs::batcher::HistState, Ctx, FetchState, LogFetchState, LogId};
-use log::{debug, error, info, trace};
+use belvi_log_list::{log_data::LogSth, Log};
+use chrono::Utc;
+use log::{debug, error, info, trace, warn};
+
+/// A log's tree can never shrink, and its STHs can never go back in time.
+fn violates_append_only(old_sth: &LogSth, new_sth: &LogSth) -> bool {
+    old_sth.tree_size > new_sth.tree_size || old_sth.timestamp > new_sth.timestamp
+}
+
+/// Records that `log` sent `new_sth` after `old_sth`, and sends it to the webhook if one is set.
+/// Each violation is only recorded once, even though the log will keep sending bad STHs.
+async fn record_violation(ctx: &Ctx, log: &Log, old_sth: &LogSth, new_sth: &LogSth) {
+    let log_id = LogId(log.log_id.clone());
+    let inserted = ctx
+        .sqlite_conn
+        .prepare_cached(
+            "INSERT OR IGNORE INTO sth_violations (log_id, old_tree_size, new_tree_size, old_timestamp, new_timestamp, detected_at) VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .unwrap()
+        .execute(rusqlite::params![
+            log_id.num(),
+            old_sth.tree_size,
+            new_sth.tree_size,
+            old_sth.timestamp,
+            new_sth.timestamp,
+            Utc::now().timestamp_millis(),
+        ])
+        .expect("failed to record STH violation");
+    if inserted == 0 {
+        return;
+    }
+    if let Some(webhook) = &ctx.violation_webhook {
+        let body = serde_json::json!({
+            "log_id": log.log_id,
+            "log_description": log.description,
+            "old_sth": old_sth,
+            "new_sth": new_sth,
+        });
+        match reqwest::Client::new()
+            .post(webhook)
+            .json(&body)
+            .send()
+            .await
+        {
+            Ok(res) if res.status().is_success() => {}
+            Ok(res) => warn!("Violation webhook returned {}", res.status()),
+            Err(err) => warn!("Failed to call violation webhook: {:?}", err),
+        }
+    }
+}
 
 impl FetchState {
     pub async fn update_sths(&mut self, ctx: &Ctx) {
@@ -14,21 +63,27 @@ impl FetchState {
                 )
             });
             trace!("Fetching STH for \"{}\"", log.description);
+            let tree_size = new_sth.tree_size;
             let log_id = LogId(log.log_id.clone());
             match self.log_states.get_mut(&log_id) {
                 Some(state) => {
                     let old_sth = &state.sth;
-                    if old_sth.tree_size > new_sth.tree_size
-                        || old_sth.timestamp > new_sth.timestamp
-                    {
-                        error!("log violated append-only {:?} to {:?}", old_sth, new_sth);
+                    if violates_append_only(old_sth, &new_sth) {
+                        error!(
+                            "Log \"{}\" violated append-only: {:?} to {:?}",
+                            log.description, old_sth, new_sth
+                        );
+                        record_violation(ctx, log, old_sth, &new_sth).await;
+                        // keep the last good STH
+                        continue;
                     }
                     if old_sth.tree_size == new_sth.tree_size {
                         debug!("Log \"{}\" is unchanged", log.description);
+                        state.sth = new_sth;
                     } else {
                         debug!("Log \"{}\" has new certs", log.description);
+                        state.prev_sth = Some(std::mem::replace(&mut state.sth, new_sth));
                     }
-                    state.sth = new_sth;
                 }
                 None => {
                     info!("Got first STH for log \"{}\"", log.description);
@@ -37,10 +92,37 @@ impl FetchState {
                         LogFetchState {
                             sth: new_sth,
                             fetched_to: HistState::default(),
+                            prev_sth: None,
+                            cached: None,
                         },
                     );
                 }
             }
+            // after the append-only check, so a violating tree size is never reported
+            ctx.metrics.set_tree_size(&log.description, tree_size);
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sth(tree_size: u64, timestamp: u64) -> LogSth {
+        LogSth {
+            tree_size,
+            timestamp,
+            sha256_root_hash: String::new(),
+            tree_head_signature: String::new(),
+        }
+    }
+
+    #[test]
+    fn append_only() {
+        assert!(!violates_append_only(&sth(10, 100), &sth(10, 100)));
+        assert!(!violates_append_only(&sth(10, 100), &sth(20, 200)));
+        assert!(!violates_append_only(&sth(10, 100), &sth(10, 200)));
+        assert!(violates_append_only(&sth(10, 100), &sth(9, 200)));
+        assert!(violates_append_only(&sth(10, 100), &sth(20, 99)));
+    }
+}
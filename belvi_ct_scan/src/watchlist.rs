@@ -0,0 +1,136 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Notifies a webhook when certs for watched domains are logged.
+use log::{info, warn};
+use regex::{RegexSet, RegexSetBuilder};
+use serde::Serialize;
+use std::{collections::HashSet, time::Duration};
+use tokio::sync::mpsc;
+
+/// After a match is found, wait this long for more matches before calling the webhook.
+const DEBOUNCE: Duration = Duration::from_secs(5);
+/// Most matches to send in one webhook request.
+const MAX_MATCHES_PER_REQUEST: usize = 1000;
+
+/// Domains to watch for. Each line of a watchlist file is a domain, which matches exactly, or a
+/// regex between slashes (like `/^.*\.example\.com$/`). Both are case-insensitive. Empty lines
+/// and lines starting with `#` are ignored.
+#[derive(Debug)]
+pub struct Watchlist {
+    exact: HashSet<String>,
+    regexes: RegexSet,
+}
+
+impl Watchlist {
+    pub fn parse(text: &str) -> Result<Self, regex::Error> {
+        let mut exact = HashSet::new();
+        let mut regexes = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.strip_prefix('/').and_then(|l| l.strip_suffix('/')) {
+                Some(regex) => regexes.push(regex),
+                None => {
+                    exact.insert(line.to_ascii_lowercase());
+                }
+            }
+        }
+        Ok(Self {
+            exact,
+            regexes: RegexSetBuilder::new(regexes)
+                .case_insensitive(true)
+                .build()?,
+        })
+    }
+
+    #[must_use]
+    pub fn matches(&self, domain: &str) -> bool {
+        self.exact.contains(&domain.to_ascii_lowercase()) || self.regexes.is_match(domain)
+    }
+}
+
+/// A cert with a domain on the watchlist.
+#[derive(Debug, Serialize)]
+pub struct WatchMatch {
+    pub leaf_hash: String,
+    pub domains: Vec<String>,
+    pub log_id: String,
+    pub log_description: String,
+    pub idx: u64,
+}
+
+#[derive(Debug)]
+pub struct Watcher {
+    pub list: Watchlist,
+    notify: Option<mpsc::UnboundedSender<WatchMatch>>,
+}
+
+impl Watcher {
+    /// Matches are always logged, and are also sent to `webhook` if it is set. This must be
+    /// called from within a Tokio runtime.
+    #[must_use]
+    pub fn new(list: Watchlist, webhook: Option<String>) -> Self {
+        Self {
+            list,
+            notify: webhook.map(spawn_notifier),
+        }
+    }
+
+    pub fn found(&self, found: WatchMatch) {
+        info!(
+            "Cert {} for watched domains {:?} logged in \"{}\"",
+            found.leaf_hash, found.domains, found.log_description
+        );
+        if let Some(notify) = &self.notify {
+            notify.send(found).expect("watchlist notifier stopped");
+        }
+    }
+}
+
+/// Sends matches to `webhook` as a JSON object with a `matches` array, in batches so that a burst
+/// of matches only makes a few requests.
+fn spawn_notifier(webhook: String) -> mpsc::UnboundedSender<WatchMatch> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        while let Some(first) = rx.recv().await {
+            tokio::time::sleep(DEBOUNCE).await;
+            let mut matches = vec![first];
+            while matches.len() < MAX_MATCHES_PER_REQUEST {
+                match rx.try_recv() {
+                    Ok(found) => matches.push(found),
+                    Err(_) => break,
+                }
+            }
+            let body = serde_json::json!({ "matches": matches });
+            match client.post(&webhook).json(&body).send().await {
+                Ok(res) if res.status().is_success() => {}
+                Ok(res) => warn!("Watchlist webhook returned {}", res.status()),
+                Err(err) => warn!("Failed to call watchlist webhook: {:?}", err),
+            }
+        }
+    });
+    tx
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn matches() {
+        let list = Watchlist::parse(
+            "# comment\n\nExample.com\n/^([a-z0-9-]+\\.)*example\\.org$/\n  belvi.test  \n",
+        )
+        .unwrap();
+        assert!(list.matches("example.com"));
+        assert!(list.matches("EXAMPLE.com"));
+        assert!(!list.matches("www.example.com"));
+        assert!(list.matches("example.org"));
+        assert!(list.matches("a.b.Example.org"));
+        assert!(!list.matches("example.org.evil"));
+        assert!(list.matches("belvi.test"));
+        assert!(!list.matches("# comment"));
+        assert!(Watchlist::parse("/(/").is_err());
+    }
+}
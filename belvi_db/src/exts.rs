@@ -1,15 +1,39 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use regex::bytes::{Regex, RegexBuilder};
+use regex_syntax::ast;
 use rusqlite::{functions::FunctionFlags, Connection};
-use std::sync::Arc;
+use std::{cell::RefCell, sync::Arc};
+
+/// The most memory a compiled regex can use, in bytes.
+pub const REGEX_SIZE_LIMIT: usize = 27500;
+/// How deeply groups and classes can be nested in a regex.
+pub const REGEX_NEST_LIMIT: u32 = 18;
 
 fn configure_regex(b: &mut RegexBuilder) {
     b
         // certificates usually (but not always) write names in lowercase
         .case_insensitive(true)
-        .size_limit(27500)
-        .nest_limit(18);
+        .size_limit(REGEX_SIZE_LIMIT)
+        .nest_limit(REGEX_NEST_LIMIT);
+}
+
+/// A complexity limit that a regex went over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegexLimit {
+    /// [`REGEX_SIZE_LIMIT`]
+    Size,
+    /// [`REGEX_NEST_LIMIT`]
+    Nesting,
+}
+
+/// Does this look like an IPv6 or IPv4 address, rather than a domain?
+#[must_use]
+pub fn is_ip_literal(dom: &[u8]) -> bool {
+    dom.contains(&b':')
+        || dom
+            .split(|c| *c == b'.')
+            .all(|part| !part.is_empty() && part.iter().all(u8::is_ascii_digit))
 }
 
 pub fn domrev(dom: &[u8]) -> Vec<u8> {
@@ -17,6 +41,10 @@ pub fn domrev(dom: &[u8]) -> Vec<u8> {
         // looks like an email, don't modify
         return dom.to_vec();
     }
+    if is_ip_literal(dom) {
+        // don't modify
+        return dom.to_vec();
+    }
     let mut v = Vec::with_capacity(2);
     for part in dom.rsplit(|c| *c == b'.') {
         v.extend_from_slice(part);
@@ -27,6 +55,49 @@ pub fn domrev(dom: &[u8]) -> Vec<u8> {
     v
 }
 
+/// Compiles a regex the same way as the `regex()` SQL function, so it matches the same domains.
+pub fn build_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    let mut builder = RegexBuilder::new(pattern);
+    configure_regex(&mut builder);
+    builder.build()
+}
+
+/// If `err`, from compiling `pattern` with [`build_regex`], is because the pattern is too complex
+/// rather than because it isn't valid, finds which limit it went over.
+#[must_use]
+pub fn regex_limit_exceeded(pattern: &str, err: &regex::Error) -> Option<RegexLimit> {
+    match err {
+        regex::Error::CompiledTooBig(_) => Some(RegexLimit::Size),
+        regex::Error::Syntax(_) => {
+            // the nesting limit is checked after parsing, so only the syntax needs to be parsed to
+            // see if that is what failed
+            let parsed = ast::parse::ParserBuilder::new()
+                .nest_limit(REGEX_NEST_LIMIT)
+                .build()
+                .parse(pattern);
+            match parsed {
+                Err(err) if matches!(err.kind(), ast::ErrorKind::NestLimitExceeded(_)) => {
+                    Some(RegexLimit::Nesting)
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+thread_local! {
+    /// The error from the last regex that the `regex()` SQL function couldn't compile on this
+    /// thread. SQLite only passes on the message of errors from functions.
+    static REGEX_ERROR: RefCell<Option<regex::Error>> = const { RefCell::new(None) };
+}
+
+/// Takes the error from the last regex that the `regex()` SQL function couldn't compile on this
+/// thread, so a query that failed because of it can tell why.
+pub fn take_regex_error() -> Option<regex::Error> {
+    REGEX_ERROR.with(|last| last.borrow_mut().take())
+}
+
 pub fn register(db: &mut Connection) {
     // https://docs.rs/rusqlite/latest/rusqlite/functions/index.html
     db.create_scalar_function(
@@ -38,9 +109,10 @@ pub fn register(db: &mut Connection) {
             let regex: Arc<Regex> = ctx.get_or_create_aux(
                 0,
                 |vr| -> Result<_, Box<dyn std::error::Error + Send + Sync + 'static>> {
-                    let mut builder = RegexBuilder::new(vr.as_str()?);
-                    configure_regex(&mut builder);
-                    Ok(builder.build()?)
+                    build_regex(vr.as_str()?).map_err(|err| {
+                        REGEX_ERROR.with(|last| *last.borrow_mut() = Some(err.clone()));
+                        err.into()
+                    })
                 },
             )?;
             Ok(match ctx.get_raw(1).as_bytes() {
@@ -117,6 +189,30 @@ mod test {
         );
     }
 
+    #[test]
+    fn limit_exceeded() {
+        let limit = |pattern: &str| regex_limit_exceeded(pattern, &build_regex(pattern).err()?);
+        assert_eq!(limit("^www\\."), None);
+        assert_eq!(limit("("), None);
+        let nested = format!("{}a{}", "(".repeat(20), ")".repeat(20));
+        assert_eq!(limit(&nested), Some(RegexLimit::Nesting));
+        // patterns that are also invalid are reported as invalid
+        assert_eq!(limit(&format!("{}(", nested)), None);
+        assert_eq!(limit("[a-z]{1000}"), Some(RegexLimit::Size));
+    }
+
+    #[test]
+    fn regex_errors() {
+        let mut db = Connection::open_in_memory().unwrap();
+        register(&mut db);
+        assert!(take_regex_error().is_none());
+        assert!(db
+            .query_row("SELECT regex('(', 'a')", [], |_| Ok(()))
+            .is_err());
+        assert!(matches!(take_regex_error(), Some(regex::Error::Syntax(_))));
+        assert!(take_regex_error().is_none());
+    }
+
     #[test]
     fn domrev() {
         let mut db = Connection::open_in_memory().unwrap();
@@ -149,6 +245,11 @@ mod test {
         t(&mut db, "domrev('.')", b".");
         t(&mut db, "domrev('.a.')", b".a.");
         t(&mut db, "domrev('abc@example.com')", b"abc@example.com");
+        t(&mut db, "domrev('2001:db8::1')", b"2001:db8::1");
+        t(&mut db, "domrev('::ffff:192.0.2.1')", b"::ffff:192.0.2.1");
+        t(&mut db, "domrev('192.0.2.1')", b"192.0.2.1");
+        t(&mut db, "domrev('1.example.2')", b"2.example.1");
+        t(&mut db, "domrev('1..2')", b"2..1");
         t(&mut db, "domrev('abc.com') >= '.com'", &true);
     }
 }
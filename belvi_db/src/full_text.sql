@@ -0,0 +1,22 @@
+-- SPDX-License-Identifier: Apache-2.0
+-- Run when full-text domain search is enabled. Requires SQLite to be built with FTS5.
+
+BEGIN;
+
+-- trigram tokenization allows searching for any substring of at least 3 characters
+CREATE VIRTUAL TABLE IF NOT EXISTS domains_fts USING fts5(
+    domain,
+    content = 'domains',
+    content_rowid = 'rowid',
+    tokenize = 'trigram'
+);
+-- domains_fts doesn't store its own copy of domains, so it needs to be kept in sync with the
+-- domains table
+CREATE TRIGGER IF NOT EXISTS domains_fts_insert AFTER INSERT ON domains BEGIN
+    INSERT INTO domains_fts (rowid, domain) VALUES (new.rowid, new.domain);
+END;
+CREATE TRIGGER IF NOT EXISTS domains_fts_delete AFTER DELETE ON domains BEGIN
+    INSERT INTO domains_fts (domains_fts, rowid, domain) VALUES ('delete', old.rowid, old.domain);
+END;
+
+COMMIT;
@@ -23,13 +23,17 @@ CREATE TABLE IF NOT EXISTS certs (
     extra_hash BLOB NOT NULL, -- SHA256 of extra data
     not_before INTEGER NOT NULL,
     not_after INTEGER NOT NULL,
-    cert_type NUMBER NOT NULL
+    cert_type NUMBER NOT NULL,
+    sct_count INTEGER NOT NULL DEFAULT 0, -- number of embedded SCTs
+    serial BLOB, -- serial number without leading zero bytes, NULL for certs added before this was stored
+    cn_matches_san INTEGER, -- whether the commonName is also a subjectAltName, NULL if there's no commonName or for certs added before this was stored
+    issuer_key BLOB -- authority key identifier, or a hash of the issuer's name if there isn't one (see belvi_cert::issuer_key), NULL for certs added before this was stored
 ) WITHOUT ROWID;
 CREATE TABLE IF NOT EXISTS log_entries (
     leaf_hash BLOB NOT NULL, -- SHA256 of leaf data
     log_id NUMBER NOT NULL, -- ID of log
     idx NUMBER NOT NULL, -- index in log
-    ts NUMBER NOT NULL, -- time the certificate was incorporated into the first log we saw this cert in
+    ts NUMBER NOT NULL, -- SCT timestamp from this log (ms since the epoch), not related to the cert's validity
     PRIMARY KEY (leaf_hash, log_id)
 );
 CREATE TABLE IF NOT EXISTS domains (
@@ -39,12 +43,30 @@ CREATE TABLE IF NOT EXISTS domains (
     PRIMARY KEY (domain, leaf_hash),
     FOREIGN KEY (leaf_hash) REFERENCES log_entries(leaf_hash)
 ); -- WITH ROWID
+CREATE TABLE IF NOT EXISTS sth_violations (
+    -- a log's STH went backwards, which a log that is append-only will never do
+    log_id NUMBER NOT NULL, -- ID of log
+    old_tree_size INTEGER NOT NULL, -- tree size of the last good STH, which is kept
+    new_tree_size INTEGER NOT NULL,
+    old_timestamp INTEGER NOT NULL, -- STH timestamps, in milliseconds since the epoch
+    new_timestamp INTEGER NOT NULL,
+    detected_at INTEGER NOT NULL, -- milliseconds since the epoch
+    PRIMARY KEY (log_id, old_tree_size, old_timestamp, new_tree_size, new_timestamp)
+);
 
 -- CREATE INDICIES --
 CREATE INDEX IF NOT EXISTS idx_domains_domain1 ON domains(domain);
 CREATE INDEX IF NOT EXISTS idx_domains_leaf_hash1 ON domains(leaf_hash);
-CREATE INDEX IF NOT EXISTS idx_domains_lower_domrev2 ON domains(domrev(lower(domain)));
-CREATE INDEX IF NOT EXISTS idx_log_entries_ts1 ON log_entries(ts);
+-- domrev was changed to not reverse IP addresses, so the index on it needs to be rebuilt
+DROP INDEX IF EXISTS idx_domains_lower_domrev2;
+CREATE INDEX IF NOT EXISTS idx_domains_lower_domrev3 ON domains(domrev(lower(domain)));
+-- the leaf hash is included for paging through recent certs, which makes the index on just ts redundant
+DROP INDEX IF EXISTS idx_log_entries_ts1;
+CREATE INDEX IF NOT EXISTS idx_log_entries_ts_leaf_hash1 ON log_entries(ts, leaf_hash);
+CREATE INDEX IF NOT EXISTS idx_certs_not_before1 ON certs(not_before);
+CREATE INDEX IF NOT EXISTS idx_certs_not_after1 ON certs(not_after);
+CREATE INDEX IF NOT EXISTS idx_certs_serial1 ON certs(serial);
+CREATE INDEX IF NOT EXISTS idx_certs_issuer_key1 ON certs(issuer_key);
 
 COMMIT;
 
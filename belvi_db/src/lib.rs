@@ -1,36 +1,367 @@
 // SPDX-License-Identifier: Apache-2.0
-use log::debug;
-use rusqlite::{Connection, OpenFlags};
-use std::{env, path::PathBuf};
+use log::{debug, info};
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use std::{path::Path, time::Duration};
 
 mod exts;
-pub use exts::domrev;
+pub use exts::{
+    build_regex, domrev, is_ip_literal, regex_limit_exceeded, take_regex_error, RegexLimit,
+    REGEX_NEST_LIMIT, REGEX_SIZE_LIMIT,
+};
 
-fn get_data_path() -> PathBuf {
-    let mut args = env::args_os();
-    args.nth(1).unwrap().into()
+/// How long to wait for another connection to release a lock before failing.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Name of the database file in the data directory.
+pub const DB_FILE: &str = "data.db";
+
+/// Opens the database in the data directory `data_path` for reading.
+///
+/// The database uses WAL mode (set by [`connect_at`]), so reads work while the scanner is writing.
+/// Even read-only connections need write access to the `-wal` and `-shm` files next to the
+/// database, so the data directory must be writable by the reader.
+///
+/// Panics if the database can't be opened, such as if the scanner hasn't created it yet. Use
+/// [`try_connect_readonly_at`] to handle that.
+pub fn connect_readonly_at(data_path: &Path) -> Connection {
+    try_connect_readonly_at(data_path).unwrap_or_else(|e| {
+        panic!(
+            "couldn't open the database at {} (has belvi_ct_scan been run yet?): {}",
+            data_path.join(DB_FILE).display(),
+            e
+        )
+    })
 }
 
-pub fn connect_readonly() -> Connection {
-    let db_path = get_data_path().join("data.db");
+/// Like [`connect_readonly_at`], but returns an error if the database can't be opened.
+pub fn try_connect_readonly_at(data_path: &Path) -> rusqlite::Result<Connection> {
+    let db_path = data_path.join(DB_FILE);
     // OPEN_CREATE isn't passed, so we don't create the DB if it doesn't exist
-    let mut db = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY).unwrap();
+    let mut db = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    db.busy_timeout(BUSY_TIMEOUT)?;
     exts::register(&mut db);
-    db
+    Ok(db)
 }
 
-pub fn connect() -> Connection {
-    let db_path = get_data_path().join("data.db");
+/// Columns that were added after their table was first created. `CREATE TABLE IF NOT EXISTS`
+/// doesn't add new columns to existing tables, so they are added here before `init_db.sql` runs.
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("certs", "sct_count", "INTEGER NOT NULL DEFAULT 0"),
+    ("certs", "serial", "BLOB"),
+    ("certs", "cn_matches_san", "INTEGER"),
+    ("certs", "issuer_key", "BLOB"),
+];
+
+fn add_missing_columns(db: &Connection) {
+    for (table, column, definition) in ADDED_COLUMNS {
+        let mut stmt = db
+            .prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))
+            .unwrap();
+        let columns: Vec<String> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        // if the table doesn't exist yet, init_db.sql will create it with every column
+        if !columns.is_empty() && !columns.iter().any(|name| name == column) {
+            info!("Adding column {} to table {}", column, table);
+            db.execute_batch(&format!(
+                "ALTER TABLE {} ADD COLUMN {} {}",
+                table, column, definition
+            ))
+            .unwrap();
+        }
+    }
+}
+
+/// Opens the database in the data directory `data_path` for writing, creating it if needed.
+pub fn connect_at(data_path: &Path) -> Connection {
+    let db_path = data_path.join(DB_FILE);
     let mut db = Connection::open(db_path).unwrap();
+    db.busy_timeout(BUSY_TIMEOUT).unwrap();
     exts::register(&mut db);
     debug!("SQLite version is {}", rusqlite::version());
+    add_missing_columns(&db);
     db.execute_batch(include_str!("init_db.sql")).unwrap();
     db
 }
 
+/// Enables full-text search of domains, indexing all existing domains if it wasn't enabled before.
+/// Once enabled, domains continue to be indexed as they are inserted.
+pub fn enable_full_text(db: &Connection) {
+    let enabled: bool = db
+        .query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'domains_fts')",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    db.execute_batch(include_str!("full_text.sql")).unwrap();
+    if !enabled {
+        info!("Indexing existing domains for full-text search");
+        db.execute_batch("INSERT INTO domains_fts (domains_fts) VALUES ('rebuild')")
+            .unwrap();
+    }
+}
+
+/// An entry for a cert in one log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CertLogEntry {
+    /// `LogId::num` of the log.
+    pub log_id: u32,
+    /// Index of the entry in the log.
+    pub idx: u64,
+    /// Timestamp of the SCT the log issued for the cert, in milliseconds since the epoch. This is
+    /// when the log promised to incorporate the cert.
+    pub sct_timestamp: i64,
+}
+
+/// Returns every log entry for the cert with `leaf_hash`, ordered by SCT timestamp. The first entry
+/// is the log that saw the cert first.
+pub fn cert_log_entries(db: &Connection, leaf_hash: &[u8]) -> rusqlite::Result<Vec<CertLogEntry>> {
+    db.prepare_cached(
+        "SELECT log_id, idx, ts FROM log_entries WHERE leaf_hash = ? ORDER BY ts, log_id",
+    )?
+    .query_map([leaf_hash], |row| {
+        Ok(CertLogEntry {
+            log_id: row.get(0)?,
+            idx: row.get(1)?,
+            sct_timestamp: row.get(2)?,
+        })
+    })?
+    .collect()
+}
+
+/// The other half of an issuance: the final certificate for a precertificate, or the reverse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelatedCert {
+    pub leaf_hash: Vec<u8>,
+    /// `LogEntry::num` of the related cert.
+    pub cert_type: u8,
+}
+
+/// Serials are much more selective than issuers, so the serial index is always used.
+const RELATED_CERTS_QUERY: &str = "SELECT other.leaf_hash, other.cert_type FROM certs AS this
+    INNER JOIN certs AS other INDEXED BY idx_certs_serial1
+        ON other.serial = this.serial AND other.issuer_key = this.issuer_key
+    WHERE this.leaf_hash = ? AND other.cert_type != this.cert_type
+    ORDER BY other.leaf_hash";
+
+/// Finds candidates for the final certificates for the precertificate with `leaf_hash`, or the
+/// precertificates for the certificate with `leaf_hash`. Returns `None` if the cert isn't in the
+/// database, or was added before its serial and issuer were stored, since then whether it has any
+/// can't be determined.
+///
+/// RFC 6962 requires a precertificate to have the same serial number as its final certificate,
+/// and the precertificate in the log has the final certificate's issuer, so certs are matched on
+/// their serial and `issuer_key`. The rest of the certificate isn't stored here, so candidates
+/// should be confirmed with `belvi_cert::is_same_issuance` before they're shown as related. A CA
+/// that reuses serial numbers (which isn't allowed) can give candidates that aren't related.
+///
+/// Matches aren't stored, since the precertificate and final certificate can be logged in either
+/// order (or in different logs, scanned at different times), and confirming them needs the
+/// certificates themselves, which are only kept in the cache.
+pub fn related_certs(
+    db: &Connection,
+    leaf_hash: &[u8],
+) -> rusqlite::Result<Option<Vec<RelatedCert>>> {
+    let matchable: Option<bool> = db
+        .prepare_cached(
+            "SELECT serial IS NOT NULL AND issuer_key IS NOT NULL FROM certs WHERE leaf_hash = ?",
+        )?
+        .query_row([leaf_hash], |row| row.get(0))
+        .optional()?;
+    if matchable != Some(true) {
+        return Ok(None);
+    }
+    db.prepare_cached(RELATED_CERTS_QUERY)?
+        .query_map([leaf_hash], |row| {
+            Ok(RelatedCert {
+                leaf_hash: row.get(0)?,
+                cert_type: row.get(1)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()
+        .map(Some)
+}
+
+/// Opens an empty in-memory database with the full schema, for testing and query planning.
 pub fn memory() -> Connection {
     let mut db = Connection::open_in_memory().unwrap();
     exts::register(&mut db);
     db.execute_batch(include_str!("init_db.sql")).unwrap();
     db
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn missing_db() {
+        let data_path = std::env::temp_dir().join("belvi-missing-db-test");
+        assert!(!data_path.join(DB_FILE).exists());
+        assert!(try_connect_readonly_at(&data_path).is_err());
+    }
+
+    #[test]
+    fn memory_has_schema() {
+        let db = memory();
+        let count: u32 = db
+            .query_row("SELECT COUNT(*) FROM certs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+        db.query_row("SELECT domrev('a.b')", [], |row| row.get::<_, Vec<u8>>(0))
+            .unwrap();
+    }
+
+    /// Returns the details column of `EXPLAIN QUERY PLAN`, like the `explain` binary.
+    fn query_plan(db: &Connection, query: &str) -> Vec<String> {
+        db.prepare(&format!("EXPLAIN QUERY PLAN {}", query))
+            .unwrap()
+            .query_map([42, 42], |row| row.get(3))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn range_queries_use_indexes() {
+        let db = memory();
+        for (query, index) in [
+            (
+                "SELECT leaf_hash FROM certs WHERE not_after >= ? AND not_after < ?",
+                "idx_certs_not_after1",
+            ),
+            (
+                "SELECT leaf_hash FROM certs WHERE not_before >= ? AND not_before < ?",
+                "idx_certs_not_before1",
+            ),
+            (
+                "SELECT leaf_hash FROM log_entries WHERE ts >= ? AND ts < ?",
+                "idx_log_entries_ts_leaf_hash1",
+            ),
+        ] {
+            let plan = query_plan(&db, query);
+            assert!(
+                plan.iter().any(|step| step.contains(index)),
+                "{} doesn't use {}: {:?}",
+                query,
+                index,
+                plan
+            );
+        }
+    }
+
+    #[test]
+    fn cert_log_entries_first_seen() {
+        let db = memory();
+        db.execute_batch(
+            "INSERT INTO log_entries (leaf_hash, log_id, idx, ts) VALUES
+                (x'01', 7, 100, 2000),
+                (x'01', 3, 5, 1000),
+                (x'01', 5, 50, 3000),
+                (x'02', 1, 1, 1);",
+        )
+        .unwrap();
+        let entries = cert_log_entries(&db, &[1]).unwrap();
+        assert_eq!(
+            entries.iter().map(|entry| entry.log_id).collect::<Vec<_>>(),
+            [3, 7, 5]
+        );
+        assert_eq!(
+            entries[0],
+            CertLogEntry {
+                log_id: 3,
+                idx: 5,
+                sct_timestamp: 1000,
+            }
+        );
+        assert!(cert_log_entries(&db, &[3]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn related_certs_match_serial_and_issuer() {
+        let db = memory();
+        db.execute_batch(
+            "INSERT INTO certs (leaf_hash, extra_hash, not_before, not_after, cert_type, serial, issuer_key) VALUES
+                (x'01', x'00', 0, 0, 2, x'AB', x'11'), -- precert
+                (x'02', x'00', 0, 0, 1, x'AB', x'11'), -- its final cert
+                (x'03', x'00', 0, 0, 1, x'AB', x'22'), -- same serial, other issuer
+                (x'04', x'00', 0, 0, 2, x'AB', x'11'), -- same serial, also a precert
+                (x'05', x'00', 0, 0, 2, NULL, NULL), -- added before serials were stored
+                (x'06', x'00', 0, 0, 1, NULL, NULL);",
+        )
+        .unwrap();
+        let final_cert = || RelatedCert {
+            leaf_hash: vec![2],
+            cert_type: 1,
+        };
+        assert_eq!(related_certs(&db, &[1]).unwrap().unwrap(), [final_cert()]);
+        assert_eq!(related_certs(&db, &[4]).unwrap().unwrap(), [final_cert()]);
+        assert_eq!(
+            related_certs(&db, &[2]).unwrap().unwrap(),
+            [
+                RelatedCert {
+                    leaf_hash: vec![1],
+                    cert_type: 2,
+                },
+                RelatedCert {
+                    leaf_hash: vec![4],
+                    cert_type: 2,
+                }
+            ]
+        );
+        assert_eq!(related_certs(&db, &[3]).unwrap(), Some(Vec::new()));
+        // the serial isn't known, so whether there are any can't be determined
+        assert_eq!(related_certs(&db, &[5]).unwrap(), None);
+        assert_eq!(related_certs(&db, &[6]).unwrap(), None);
+        assert_eq!(related_certs(&db, &[7]).unwrap(), None);
+
+        let plan: Vec<String> = db
+            .prepare(&format!("EXPLAIN QUERY PLAN {}", RELATED_CERTS_QUERY))
+            .unwrap()
+            .query_map([1], |row| row.get(3))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert!(
+            plan.iter().any(|step| step.contains("idx_certs_serial1")),
+            "{:?}",
+            plan
+        );
+    }
+
+    #[test]
+    fn adds_missing_columns() {
+        let mut db = Connection::open_in_memory().unwrap();
+        exts::register(&mut db);
+        db.execute_batch(
+            "CREATE TABLE certs (
+                leaf_hash BLOB PRIMARY KEY NOT NULL,
+                extra_hash BLOB NOT NULL,
+                not_before INTEGER NOT NULL,
+                not_after INTEGER NOT NULL,
+                cert_type NUMBER NOT NULL
+            ) WITHOUT ROWID;
+            INSERT INTO certs VALUES (x'00', x'00', 0, 0, 1);",
+        )
+        .unwrap();
+        add_missing_columns(&db);
+        db.execute_batch(include_str!("init_db.sql")).unwrap();
+        let (sct_count, serial, cn_matches_san): (u32, Option<Vec<u8>>, Option<bool>) = db
+            .query_row(
+                "SELECT sct_count, serial, cn_matches_san FROM certs",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        let issuer_key: Option<Vec<u8>> = db
+            .query_row("SELECT issuer_key FROM certs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(sct_count, 0);
+        assert_eq!(serial, None);
+        assert_eq!(cn_matches_san, None);
+        assert_eq!(issuer_key, None);
+    }
+}
@@ -1,22 +1,38 @@
 // SPDX-License-Identifier: Apache-2.0
 use belvi_frontend::search::{self, QueryMode, SearchResults};
-use std::{ffi::OsString, time::Instant};
+use std::{path::PathBuf, time::Instant};
 
 fn main() {
     env_logger::init();
 
-    let db = belvi_db::connect_readonly();
+    let data_path: PathBuf = std::env::args_os()
+        .nth(1)
+        .expect("no data path provided")
+        .into();
+    let db = belvi_db::connect_readonly_at(&data_path);
     let limit = 50;
     let query = search::Query {
         query: std::env::args_os().nth(2).map(|s| s.into_string().unwrap()),
         mode: match std::env::args_os().nth(3) {
             None => None,
-            Some(x) if x == OsString::from("regex") => Some(QueryMode::Regex),
-            Some(x) if x == OsString::from("subdomain") => Some(QueryMode::Subdomain),
+            Some(x) if x == "regex" => Some(QueryMode::Regex),
+            Some(x) if x == "subdomain" => Some(QueryMode::Subdomain),
+            Some(x) if x == "exact" => Some(QueryMode::Exact),
+            Some(x) if x == "wildcard" => Some(QueryMode::Wildcard),
+            Some(x) if x == "full_text" => Some(QueryMode::FullText),
+            Some(x) if x == "serial" => Some(QueryMode::Serial),
+            Some(x) if x == "issuer" => Some(QueryMode::Issuer),
             Some(_) => panic!("invalid mode"),
         },
         limit: Some(limit),
         after: None,
+        min_scts: None,
+        not_before_after: None,
+        not_before_before: None,
+        not_after_after: None,
+        not_after_before: None,
+        log_id: None,
+        newest_first: false,
     };
 
     let start = Instant::now();
@@ -26,7 +42,7 @@ fn main() {
         next: _,
     } = match query.search_sync(&db, limit) {
         Ok(v) => v,
-        Err(res) => panic!("failed: {:?}", res.body()),
+        Err(err) => panic!("failed: {}", err),
     };
     let end = Instant::now();
     let duration = end - start;
@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Pool of read-only database connections shared between requests.
+use log::warn;
+use rusqlite::Connection;
+use std::{path::PathBuf, time::Duration};
+
+pub type Pool = r2d2::Pool<ConnectionManager>;
+
+#[derive(Debug)]
+pub struct ConnectionManager {
+    data_path: PathBuf,
+}
+
+impl ConnectionManager {
+    #[must_use]
+    pub fn new(data_path: PathBuf) -> Self {
+        Self { data_path }
+    }
+}
+
+impl r2d2::ManageConnection for ConnectionManager {
+    type Connection = Connection;
+    type Error = rusqlite::Error;
+
+    fn connect(&self) -> Result<Connection, rusqlite::Error> {
+        belvi_db::try_connect_readonly_at(&self.data_path)
+    }
+
+    fn is_valid(&self, conn: &mut Connection) -> Result<(), rusqlite::Error> {
+        conn.execute_batch("")
+    }
+
+    fn has_broken(&self, _conn: &mut Connection) -> bool {
+        false
+    }
+}
+
+/// How long a request waits for a connection before giving up.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Creates a pool with up to `size` connections to the database in `data_path`. The database
+/// doesn't need to exist yet, since the frontend can be started before the scanner has created
+/// it. Until it does, getting a connection fails.
+#[must_use]
+pub fn pool(data_path: PathBuf, size: u32) -> Pool {
+    let db_path = data_path.join(belvi_db::DB_FILE);
+    if !db_path.exists() {
+        warn!(
+            "There is no database at {} yet, so searches will fail until belvi_ct_scan creates it",
+            db_path.display()
+        );
+    }
+    r2d2::Pool::builder()
+        .max_size(size)
+        .connection_timeout(CONNECTION_TIMEOUT)
+        .build_unchecked(ConnectionManager::new(data_path))
+}
@@ -6,13 +6,16 @@ use std::iter;
 pub fn sort(domains: &mut [String]) {
     domains.sort_by(|a, b| {
         for (a_part, b_part) in iter::zip(a.rsplit('.'), b.rsplit('.')) {
-            let order = a_part.partial_cmp(b_part).unwrap();
+            let order = a_part.cmp(b_part);
             if order.is_ne() {
                 return order;
             }
         }
-        assert_eq!(a, b);
-        a.partial_cmp(b).unwrap()
+        // one is a subdomain of the other, like example.com and www.example.com
+        a.split('.')
+            .count()
+            .cmp(&b.split('.').count())
+            .then_with(|| a.cmp(b))
     });
     domains.reverse();
 }
@@ -70,4 +73,31 @@ mod test {
             .collect::<Vec<_>>()
         )
     }
+
+    #[test]
+    fn subdomains() {
+        let mut doms: Vec<String> = vec!["example.com", "a.www.example.com", "www.example.com"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        sort(&mut doms);
+        assert_eq!(
+            doms,
+            vec!["a.www.example.com", "www.example.com", "example.com"]
+        );
+    }
+
+    #[test]
+    fn near_duplicates() {
+        let mut doms: Vec<String> =
+            vec!["example.com", "Example.com", "example.com.", "example.com"]
+                .into_iter()
+                .map(String::from)
+                .collect();
+        sort(&mut doms);
+        assert_eq!(
+            doms,
+            vec!["example.com", "example.com", "Example.com", "example.com."]
+        );
+    }
 }
@@ -2,7 +2,10 @@
 //! This library has modules useful for the frontend. It is seperate from the binary target to
 //! allow it to be tested seperately.
 
+pub mod db;
 pub mod domain_sort;
+pub mod not_found_cache;
+pub mod rate_limit;
 pub mod res;
 pub mod search;
 
@@ -4,38 +4,97 @@ use axum::{
     body::HttpBody,
     extract::{ConnectInfo, Path, Query},
     handler::Handler,
-    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode, Uri},
     middleware::{self, Next},
     response::{IntoResponse, Response},
     routing::get,
-    Extension, Router,
+    Extension, Json, Router,
 };
 use bcder::decode::Constructed;
-use belvi_frontend::*;
-use belvi_log_list::{fetcher::Fetcher, LogId, LogList};
-use belvi_render::{html_escape::HtmlEscapable, Render};
-use log::debug;
-use rusqlite::Connection;
-use std::{fmt::Debug, net::SocketAddr, sync::Arc, time::Instant};
+use belvi_db::CertLogEntry;
+use belvi_frontend::{not_found_cache::NotFoundCache, rate_limit::RateLimiter, *};
+use belvi_log_list::{
+    fetch_status::FetchStatus,
+    fetcher::{FetchError, Fetcher},
+    log_data::{parse_extra_data, GetEntriesItem, LogEntry},
+    LogId, LogList,
+};
+use belvi_render::{html_escape::HtmlEscapable, Format, Render};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use log::{debug, warn};
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Debug,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::{sync::Mutex, task};
-use tower_http::set_header::SetResponseHeaderLayer;
+use tower_http::{
+    compression::{
+        predicate::{DefaultPredicate, NotForContentType, Predicate},
+        CompressionLayer,
+    },
+    set_header::SetResponseHeaderLayer,
+};
 
 struct CacheState {
     cache_conn: belvi_cache::Connection,
     log_list: LogList,
     fetcher: Fetcher,
+    not_found: NotFoundCache,
 }
 
-// TODO: use put in global state
-thread_local! {
-    static DB_CONN: Connection = belvi_db::connect_readonly();
+/// The data directory is the first argument.
+fn data_path() -> PathBuf {
+    env::args_os().nth(1).expect("no data path provided").into()
 }
 
+/// Used if `BELVI_BIND_ADDR` isn't set.
+const DEFAULT_BIND_ADDR: &str = "0.0.0.0:47371";
+
+/// Used if `BELVI_DB_POOL_SIZE` isn't set.
+const DEFAULT_DB_POOL_SIZE: u32 = 8;
+
+/// Used if `BELVI_MEMORY_CACHE_SIZE` isn't set. Certs are usually a few kilobytes each.
+const DEFAULT_MEMORY_CACHE_SIZE: usize = 10_000;
+
+/// Number of certs that weren't found to remember.
+const NOT_FOUND_CACHE_SIZE: usize = 1024;
+/// How long to remember that a cert wasn't found for.
+const NOT_FOUND_CACHE_AGE: Duration = Duration::from_secs(60);
+
 const MAX_LIMIT: u32 = 200;
 const DEFAULT_LIMIT: u32 = 100;
 const TRIVIAL_SEARCHES: &[&str] = &["", "^", "$", "^$", ".*"];
 
-async fn get_root(query: Query<search::Query>) -> impl IntoResponse {
+/// Used if `BELVI_SEARCH_TIMEOUT` isn't set.
+const DEFAULT_SEARCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static::lazy_static! {
+    /// `None` if searches can take any amount of time.
+    static ref SEARCH_TIMEOUT: Option<Duration> = match env::var("BELVI_SEARCH_TIMEOUT") {
+        Ok(ms) => match ms.parse().expect("invalid BELVI_SEARCH_TIMEOUT") {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        },
+        Err(_) => Some(DEFAULT_SEARCH_TIMEOUT),
+    };
+}
+
+fn search_limit(query: &search::Query) -> u32 {
+    match query.limit {
+        Some(val @ 1..=MAX_LIMIT) => val,
+        _ => DEFAULT_LIMIT,
+    }
+}
+
+async fn get_root(
+    query: Query<search::Query>,
+    Extension(db_pool): Extension<db::Pool>,
+) -> impl IntoResponse {
     // redirect simple regex queries that match everything or nothing
     if let Some(domain) = &query.query {
         let domain = domain.trim();
@@ -44,119 +103,337 @@ async fn get_root(query: Query<search::Query>) -> impl IntoResponse {
         }
     };
 
-    let limit = match query.limit {
-        Some(val @ 1..=MAX_LIMIT) => val,
-        _ => DEFAULT_LIMIT,
-    };
+    let limit = search_limit(&query);
 
     task::spawn_blocking(move || {
-        DB_CONN.with(|db| {
-            let start = Instant::now();
-            let search::SearchResults { certs, count, next } = match query.search_sync(db, limit) {
-                Ok(v) => v,
-                Err(resp) => return resp,
-            };
-            let run_time = (Instant::now() - start).as_secs_f64();
-            let domain = query
-                .query
-                .clone()
-                .unwrap_or_else(|| "".to_string())
-                .html_escape();
-            (
-                StatusCode::OK,
-                res::html_headers(),
+        let db = match db_pool.get() {
+            Ok(db) => db,
+            Err(_) => return res::db_unavailable(),
+        };
+        let start = Instant::now();
+        let results = query.search_sync_with_timeout(&db, limit, *SEARCH_TIMEOUT);
+        let search::SearchResults { certs, count, next } = match results {
+            Ok(v) => v,
+            Err(err) => return search_error(err),
+        };
+        let run_time = (Instant::now() - start).as_secs_f64();
+        let highlight = query.highlighter();
+        let domain = query.query.clone().unwrap_or_default().html_escape();
+        let next_link = next
+            .map(|next| {
+                let mut query = (*query).clone();
+                query.after = Some(next);
                 format!(
-                    include_str!("tmpl/base.html"),
-                    title = if query.query.is_some() {
-                        format!("Search results - {}", PRODUCT_NAME)
-                    } else {
-                        PRODUCT_NAME.to_string()
-                    },
-                    product_name = PRODUCT_NAME,
-                    heading = if query.query.is_some() {
-                        "Search results"
-                    } else {
-                        "Newest certificates"
-                    },
-                    heading_classes = "",
-                    content = if certs.is_empty() {
-                        format!(
-                            include_str!("tmpl/no_results.html"),
-                            domain = domain,
-                            time = run_time,
-                        )
-                    } else {
-                        format!(
-                            include_str!("tmpl/certs_list.html"),
-                            count = certs.len(),
-                            total = if certs.len() < (limit as usize) {
-                                if let Some(val) = count {
-                                    assert_eq!(val, certs.len());
-                                }
-                                format!(" ({} total)", certs.len())
-                            } else if let Some(val) = count {
-                                format!(" ({} total)", val)
-                            } else {
-                                String::new()
-                            },
-                            domain = domain,
-                            certs = certs
-                                .iter()
-                                .map(search::CertData::render)
-                                .fold(String::new(), |a, b| a + &b),
-                            time = run_time,
-                            next = next.clone().map(|next| {
-                                let mut query = (*query).clone();
-                                query.after = Some(next);
-                                format!(
-                                    r#"<div class="bvfront-next-link"><a href="{}">Next page</a></div>"#,
-                                    query.url(),
-                                )
-                            }).unwrap_or_default(),
-                        )
-                    },
-                    css = include_str!("tmpl/base.css"),
-                    script = include_str!("tmpl/dates.js"),
-                ),
-            )
-                .into_response()
-        })
+                    r#"<div class="bvfront-next-link"><a href="{}">Next page</a></div>"#,
+                    query.url(),
+                )
+            })
+            .unwrap_or_default();
+        (
+            StatusCode::OK,
+            res::html_headers(),
+            format!(
+                include_str!("tmpl/base.html"),
+                title = if query.query.is_some() {
+                    format!("Search results - {}", PRODUCT_NAME)
+                } else {
+                    PRODUCT_NAME.to_string()
+                },
+                product_name = PRODUCT_NAME,
+                heading = if query.query.is_some() {
+                    "Search results"
+                } else {
+                    "Newest certificates"
+                },
+                heading_classes = "",
+                content = if certs.is_empty() {
+                    format!(
+                        include_str!("tmpl/no_results.html"),
+                        domain = domain,
+                        time = run_time,
+                    )
+                } else {
+                    format!(
+                        include_str!("tmpl/certs_list.html"),
+                        count = certs.len(),
+                        // a short first page has every result, but a short later page
+                        // only has the last few
+                        total = if query.after.is_none() && certs.len() < (limit as usize) {
+                            format!(" ({} total)", certs.len())
+                        } else if let Some(val) = count {
+                            format!(" ({} total)", val)
+                        } else {
+                            String::new()
+                        },
+                        domain = domain,
+                        certs = certs
+                            .iter()
+                            .map(|cert| cert.render(highlight.as_ref(), &LOG_DESCRIPTIONS))
+                            .fold(String::new(), |a, b| a + &b),
+                        time = run_time,
+                        next = next_link,
+                    )
+                },
+                css = include_str!("tmpl/base.css"),
+                script = include_str!("tmpl/dates.js"),
+            ),
+        )
+            .into_response()
+    })
+    .await
+    .unwrap()
+}
+
+fn search_error(err: search::SearchError) -> Response {
+    match err {
+        search::SearchError::Redirect(url) => res::redirect(&url),
+        search::SearchError::Unavailable => res::db_unavailable(),
+        err @ search::SearchError::RegexTooComplex(_) => {
+            res::error_page(StatusCode::PAYLOAD_TOO_LARGE, &err.to_string())
+        }
+        err => res::error(Some(err.to_string())),
+    }
+}
+
+/// Runs a search for routes that can't redirect to a fixed-up query like `/` does.
+async fn search_no_redirect(
+    mut query: search::Query,
+    db_pool: db::Pool,
+) -> Result<search::SearchResults, search::SearchError> {
+    match query.mode.unwrap_or(search::QueryMode::Recent) {
+        search::QueryMode::Recent => query.query = None,
+        _ if query.query.is_none() => {
+            return Err(search::SearchError::Invalid(
+                "No query provided".to_string(),
+            ))
+        }
+        _ => {}
+    }
+    let limit = search_limit(&query);
+
+    task::spawn_blocking(move || {
+        let db = db_pool
+            .get()
+            .map_err(|_| search::SearchError::Unavailable)?;
+        query.search_sync_with_timeout(&db, limit, *SEARCH_TIMEOUT)
     })
     .await
     .unwrap()
 }
 
+/// Like `/`, but returns the results as JSON.
+async fn get_api_search(
+    Query(query): Query<search::Query>,
+    Extension(db_pool): Extension<db::Pool>,
+) -> Response {
+    let results = search_no_redirect(query, db_pool).await;
+    match results {
+        Ok(search::SearchResults { certs, count, next }) => Json(serde_json::json!({
+            "certs": certs.iter().map(search::CertData::api_json).collect::<Vec<_>>(),
+            "count": count,
+            "next": next,
+        }))
+        .into_response(),
+        Err(search::SearchError::Unavailable) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({
+                "error": search::SearchError::Unavailable.to_string(),
+            })),
+        )
+            .into_response(),
+        Err(err @ search::SearchError::RegexTooComplex(_)) => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(serde_json::json!({ "error": err.to_string() })),
+        )
+            .into_response(),
+        Err(err) => res::json_error(Some(err.to_string())),
+    }
+}
+
+/// Like `/`, but returns the results as a CSV file.
+async fn get_search_csv(
+    Query(query): Query<search::Query>,
+    Extension(db_pool): Extension<db::Pool>,
+) -> Response {
+    let certs = match search_no_redirect(query, db_pool).await {
+        Ok(results) => results.certs,
+        Err(err) => return search_error(err),
+    };
+    let csv = std::iter::once(search::CSV_HEADER.to_string())
+        .chain(certs.iter().map(search::CertData::csv_row))
+        .collect::<String>();
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "text/csv"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"search.csv\"",
+            ),
+        ],
+        csv,
+    )
+        .into_response()
+}
+
+lazy_static::lazy_static! {
+    /// The site's absolute URL, like `https://belvi.example/`. `None` if it should be worked out
+    /// from each request.
+    static ref BASE_URL: Option<String> = env::var("BELVI_BASE_URL").ok();
+}
+
+/// The site's absolute URL, for responses that can't use relative links.
+fn base_url(headers: &HeaderMap) -> String {
+    if let Some(base_url) = &*BASE_URL {
+        return base_url.clone();
+    }
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or("localhost");
+    // set by reverse proxies that handle TLS
+    let scheme = match headers.get("x-forwarded-proto") {
+        Some(proto) if proto == "https" => "https",
+        _ => "http",
+    };
+    format!("{}://{}/", scheme, host)
+}
+
+/// Atom feed of the newest certs matching a search.
+async fn get_feed(
+    Query(mut query): Query<search::Query>,
+    Extension(db_pool): Extension<db::Pool>,
+    headers: HeaderMap,
+) -> Response {
+    query.after = None;
+    query.newest_first = true;
+    query.limit = Some(search_limit(&query).min(DEFAULT_LIMIT));
+    if query.mode.unwrap_or(search::QueryMode::Recent) == search::QueryMode::Recent {
+        query.query = None;
+    }
+    let certs = match search_no_redirect(query.clone(), db_pool).await {
+        Ok(results) => results.certs,
+        Err(err) => return search_error(err),
+    };
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/atom+xml")],
+        search::atom_feed(&query, &base_url(&headers), certs),
+    )
+        .into_response()
+}
+
 lazy_static::lazy_static! {
     // TODO: don't duplicate CacheState
-    static ref LOG_LIST: LogList = LogList::google();
+    static ref LOG_DESCRIPTIONS: HashMap<u32, &'static str> = LogList::google_shared().descriptions();
+}
+
+struct DecodedCert {
+    /// The cert rendered in the format it was decoded for.
+    rendered: String,
+    domains: Vec<Vec<u8>>,
+    /// Whether this is a full certificate rather than a precertificate.
+    full_cert: bool,
+    issuer_key: Vec<u8>,
 }
 
-fn cert_response(cert: &Vec<u8>, leaf_hash: &str, in_logs: Vec<(u32, usize)>) -> Response {
+/// Decodes a cert or precert, rendering it as `format`. Returns `None` if it isn't a valid cert or
+/// precert.
+fn decode_cert(cert: &[u8], format: Format) -> Option<DecodedCert> {
     // first try decoding as precert, then try normal cert
-    let (cert, domains, full_cert) =
-        match Constructed::decode(cert.as_ref(), bcder::Mode::Der, |cons| {
-            x509_certificate::rfc5280::TbsCertificate::take_from(cons)
-        }) {
-            Ok(tbs_cert) => (
-                tbs_cert.render(),
-                belvi_cert::get_cert_domains(&tbs_cert),
-                false,
-            ),
-            Err(_) => {
-                let cert = Constructed::decode(cert.as_ref(), bcder::Mode::Der, |cons| {
-                    x509_certificate::rfc5280::Certificate::take_from(cons)
-                })
-                .expect("invalid cert in log");
-                (
-                    cert.render(),
-                    belvi_cert::get_cert_domains(&cert.tbs_certificate),
-                    true,
+    match Constructed::decode(cert, bcder::Mode::Der, |cons| {
+        x509_certificate::rfc5280::TbsCertificate::take_from(cons)
+    }) {
+        Ok(tbs_cert) => Some(DecodedCert {
+            rendered: tbs_cert.render_as(format),
+            domains: belvi_cert::get_cert_domains(&tbs_cert),
+            full_cert: false,
+            issuer_key: belvi_cert::issuer_key(&tbs_cert),
+        }),
+        Err(_) => {
+            let cert = Constructed::decode(cert, bcder::Mode::Der, |cons| {
+                x509_certificate::rfc5280::Certificate::take_from(cons)
+            })
+            .ok()?;
+            // logs shouldn't accept precerts as normal certs, but if one did, don't offer it as
+            // a certificate that can be used
+            let poisoned = belvi_cert::has_ct_poison(&cert.tbs_certificate);
+            if poisoned {
+                warn!("Full cert has the CT poison extension");
+            }
+            Some(DecodedCert {
+                rendered: cert.render_as(format),
+                domains: belvi_cert::get_cert_domains(&cert.tbs_certificate),
+                full_cert: !poisoned,
+                issuer_key: belvi_cert::issuer_key(&cert.tbs_certificate),
+            })
+        }
+    }
+}
+
+/// Describes the other half of a cert's issuance, for the cert page.
+fn related_html(full_cert: bool, related: Option<&Related>) -> String {
+    let links = |leaf_hashes: &[Vec<u8>]| {
+        leaf_hashes
+            .iter()
+            .map(|leaf_hash| {
+                format!(
+                    r#"<a href="/cert/{id}"><code>{id}</code></a>"#,
+                    id = hex::encode(leaf_hash)
                 )
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match related {
+        Some(Related { confirmed, .. }) if !confirmed.is_empty() => {
+            if full_cert {
+                format!("<p>Issued from precertificate {}.</p>", links(confirmed))
+            } else {
+                format!("<p>Final certificate: {}.</p>", links(confirmed))
             }
-        };
+        }
+        Some(Related { unchecked, .. }) if !unchecked.is_empty() => {
+            if full_cert {
+                format!(
+                    "<p>Possibly issued from precertificate {} (it couldn't be fetched to compare).</p>",
+                    links(unchecked)
+                )
+            } else {
+                format!(
+                    "<p>Possible final certificate (it couldn't be fetched to compare): {}.</p>",
+                    links(unchecked)
+                )
+            }
+        }
+        _ if full_cert => String::new(),
+        Some(_) => "<p>No final certificate for this precertificate has been found.</p>".to_string(),
+        None => "<p>Whether this precertificate's final certificate has been logged can't be determined.</p>"
+            .to_string(),
+    }
+}
+
+fn cert_response(
+    cert: &[u8],
+    leaf_hash: &str,
+    in_logs: Vec<CertLogEntry>,
+    related: Option<&Related>,
+) -> Response {
+    let DecodedCert {
+        rendered: cert,
+        domains,
+        full_cert,
+        issuer_key,
+    } = match decode_cert(cert, Format::Html) {
+        Some(decoded) => decoded,
+        None => {
+            warn!("Cert {} couldn't be decoded", leaf_hash);
+            return res::error(Some("Certificate couldn't be decoded".to_string()));
+        }
+    };
 
     let first_domain = domains
-        .get(0)
+        .first()
         .map(|dom| String::from_utf8_lossy(dom).to_string())
         .unwrap_or_else(String::new);
     let typ = if full_cert {
@@ -165,29 +442,45 @@ fn cert_response(cert: &Vec<u8>, leaf_hash: &str, in_logs: Vec<(u32, usize)>) ->
         "precertificate"
     };
 
-    let log_iter = LOG_LIST.logs();
+    let related = related_html(full_cert, related);
+
+    let log_count = match in_logs.len() {
+        1 => "Seen in 1 log.".to_string(),
+        count => format!("Seen in {} logs.", count),
+    };
+    let log_iter = LogList::google_shared().logs();
+    // in_logs is ordered by SCT timestamp, so the first log saw the cert first
     let log_info = in_logs
         .into_iter()
-        .map(|(log_id, idx)| {
+        .enumerate()
+        .map(|(i, entry)| {
+            let CertLogEntry {
+                log_id,
+                idx,
+                sct_timestamp,
+            } = entry;
             let log = log_iter
                 .clone()
                 .find(|list_log| LogId(list_log.log_id.clone()).num() == log_id);
             let log_name = log
                 .map(|log| log.description.html_escape())
                 .unwrap_or_else(|| "unknown".to_string());
+            let sct_time = DateTime::<Utc>::from_utc(
+                NaiveDateTime::from_timestamp(sct_timestamp / 1000, 0),
+                Utc,
+            );
             format!(
-                r#"<li><a href="/logs/{}">{}</a> at {}</li>"#,
+                r#"<li><a href="/?log_id={}">{}</a> at {}, SCT issued <time datetime="{}">{}</time>{}</li>"#,
                 log_id,
                 log_name,
                 if let Some(log) = log {
-                    format!(
-                        r#"<a href="{}">#{}</a>"#,
-                        log.get_entries_url(idx as u64, idx as u64),
-                        idx
-                    )
+                    format!(r#"<a href="{}">#{}</a>"#, log.get_entries_url(idx, idx), idx)
                 } else {
                     format!("#{}", idx)
-                }
+                },
+                sct_time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                search::format_date(sct_time),
+                if i == 0 { " (first seen)" } else { "" },
             )
         })
         .fold(String::new(), |a, b| a + &b);
@@ -203,8 +496,21 @@ fn cert_response(cert: &Vec<u8>, leaf_hash: &str, in_logs: Vec<(u32, usize)>) ->
             content = format_args!(
                 include_str!("tmpl/cert_info.html"),
                 cert = cert,
-                id = leaf_hash,
+                downloads = if full_cert {
+                    format!(
+                        r#"<a href="/cert/{id}.der">DER</a> <a href="/cert/{id}.pem">PEM</a> <a href="/cert/{id}/chain.pem">PEM with chain</a> <a href="/cert/{id}.txt">Text</a>"#,
+                        id = leaf_hash
+                    )
+                } else {
+                    format!(
+                        r#"<a href="/cert/{id}.der">DER (TBS certificate)</a> <a href="/cert/{id}/chain.pem">PEM (signed precertificate with chain)</a> <a href="/cert/{id}.txt">Text</a>"#,
+                        id = leaf_hash
+                    )
+                },
                 typ = typ,
+                related = related,
+                issuer_key = hex::encode(issuer_key),
+                log_count = log_count,
                 logs = log_info,
             ),
             heading_classes = "bvfront-domain-heading",
@@ -221,10 +527,16 @@ fn cert_response(cert: &Vec<u8>, leaf_hash: &str, in_logs: Vec<(u32, usize)>) ->
 #[derive(Debug)]
 struct FoundCert {
     cert: Vec<u8>,
-    in_logs: Vec<(u32, usize)>,
+    in_logs: Vec<CertLogEntry>,
+    /// The entry's extra data, if the cert had to be fetched from a log.
+    extra_data: Option<Vec<u8>>,
 }
 
-async fn find_cert(state: Arc<Mutex<CacheState>>, leaf_hash: &str) -> Result<FoundCert, Response> {
+async fn find_cert(
+    state: Arc<Mutex<CacheState>>,
+    db_pool: db::Pool,
+    leaf_hash: &str,
+) -> Result<FoundCert, Response> {
     if leaf_hash.len() != 32 {
         return Err(res::error(Some(
             "Cert ID is not 32 characters long".to_string(),
@@ -234,97 +546,294 @@ async fn find_cert(state: Arc<Mutex<CacheState>>, leaf_hash: &str) -> Result<Fou
         Ok(val) => val,
         Err(_) => return Err(res::error(Some("Cert ID must be hex".to_string()))),
     };
-    let in_logs = DB_CONN.with(|db| {
-        // TODO: don't block executor
-        let mut query = db
-            .prepare_cached("SELECT log_id, idx FROM log_entries WHERE leaf_hash = ?")
-            .unwrap();
-        let mut rows = query.query([leaf_hash.clone()]).unwrap();
-        let mut logs: Vec<(u32, usize)> = Vec::new();
-        loop {
-            let val = match rows.next() {
-                Ok(Some(val)) => val,
-                Ok(None) => break,
-                Err(e) => panic!("unexpected error fetching certs {:#?}", e),
-            };
-            logs.push((val.get(0).unwrap(), val.get(1).unwrap()));
-        }
-        logs
-    });
+    if state.lock().await.not_found.contains(&leaf_hash) {
+        return Err(res::not_found("Certificate"));
+    }
+    let in_logs_leaf_hash = leaf_hash.clone();
+    let in_logs = task::spawn_blocking(move || {
+        let db = db_pool.get().ok()?;
+        Some(
+            belvi_db::cert_log_entries(&db, &in_logs_leaf_hash)
+                .unwrap_or_else(|e| panic!("unexpected error fetching certs {:#?}", e)),
+        )
+    })
+    .await
+    .unwrap();
+    let in_logs = match in_logs {
+        Some(in_logs) => in_logs,
+        None => return Err(res::db_unavailable()),
+    };
     if in_logs.is_empty() {
+        state.lock().await.not_found.insert(leaf_hash);
         return Err(res::not_found("Certificate"));
     }
 
     let maybe_cert = { state.lock().await.cache_conn.get_cert(&leaf_hash).await };
     match maybe_cert {
-        Some(cert) => Ok(FoundCert { cert, in_logs }),
+        Some(cert) => Ok(FoundCert {
+            cert,
+            in_logs,
+            extra_data: None,
+        }),
         None => {
-            let mut state = state.lock().await;
-            let mut matching_logs = state
-                .log_list
-                .logs()
-                .filter(|list_log| list_log.readable())
-                .filter_map(|list_log| {
-                    let wanted_id = LogId(list_log.log_id.clone()).num();
-                    in_logs
-                        .iter()
-                        .find(|wanted_log| wanted_id == wanted_log.0)
-                        .map(|v| (list_log, v.1))
-                });
-            let (log, idx) = match matching_logs.next() {
-                Some(val) => val,
-                None => {
-                    return Err(res::error(Some(
-                        "Found no current logs with cert".to_string(),
-                    )))
-                }
-            };
-            let entries = state
-                .fetcher
-                .fetch_entries(log, idx as u64, idx as u64)
-                .await;
-            let entries = match entries {
-                Ok(val) => val,
-                Err(err) => {
-                    return Err(res::error(Some(format!(
-                        "Error fetching cert from log: {:#?}",
-                        err
-                    ))))
-                }
-            };
-            match entries.len() {
-                1 => (),
-                0 => return Err(res::error(Some("Log found no cert at index".to_string()))),
-                _ => {
-                    return Err(res::error(Some(
-                        "Log responded with more certs than requested".to_string(),
-                    )))
-                }
-            };
-            let cert = entries[0]
-                .leaf_input
-                .timestamped_entry
-                .log_entry
-                .inner_cert();
-            drop(matching_logs);
-            state.cache_conn.new_cert(&belvi_hash::db(cert), cert);
+            let entry = fetch_from_log(&mut *state.lock().await, &in_logs).await?;
             Ok(FoundCert {
-                cert: cert.clone(),
+                cert: entry
+                    .leaf_input
+                    .timestamped_entry
+                    .log_entry
+                    .inner_cert()
+                    .clone(),
                 in_logs,
+                extra_data: Some(entry.extra_data),
             })
         }
     }
 }
 
+/// The other half of a cert's issuance: the final certificates for a precertificate, or the
+/// reverse.
+#[derive(Debug, Default)]
+struct Related {
+    /// Leaf hashes of the certs confirmed with `belvi_cert::is_same_issuance`.
+    confirmed: Vec<Vec<u8>>,
+    /// Leaf hashes of candidates that couldn't be fetched to confirm them.
+    unchecked: Vec<Vec<u8>>,
+}
+
+/// Decodes the TBS certificate of a cert or precert.
+fn decode_tbs(cert: &[u8]) -> Option<x509_certificate::rfc5280::TbsCertificate> {
+    Constructed::decode(cert, bcder::Mode::Der, |cons| {
+        x509_certificate::rfc5280::TbsCertificate::take_from(cons)
+    })
+    .or_else(|_| {
+        Constructed::decode(cert, bcder::Mode::Der, |cons| {
+            x509_certificate::rfc5280::Certificate::take_from(cons)
+        })
+        .map(|cert| cert.tbs_certificate)
+    })
+    .ok()
+}
+
+/// Finds the other half of a cert's issuance. Candidates are found by serial and issuer (see
+/// `belvi_db::related_certs`), then fetched to compare them with `cert`. Returns `None` if it
+/// can't be determined, such as if the cert's serial isn't stored. The cert page is still useful
+/// without them, so errors are logged and treated as it not being determined.
+async fn find_related_certs(
+    state: Arc<Mutex<CacheState>>,
+    db_pool: db::Pool,
+    cert: &[u8],
+    leaf_hash: Vec<u8>,
+) -> Option<Related> {
+    let tbs = decode_tbs(cert)?;
+    let candidates = task::spawn_blocking({
+        let db_pool = db_pool.clone();
+        move || {
+            let db = db_pool.get().map_err(|e| e.to_string())?;
+            belvi_db::related_certs(&db, &leaf_hash).map_err(|e| e.to_string())
+        }
+    })
+    .await
+    .unwrap()
+    .unwrap_or_else(|e| {
+        warn!("Couldn't find related certs: {}", e);
+        None
+    })?;
+
+    let mut related = Related::default();
+    for candidate in candidates {
+        let candidate_id = hex::encode(&candidate.leaf_hash);
+        match find_cert(state.clone(), db_pool.clone(), &candidate_id).await {
+            Ok(FoundCert { cert, .. }) => {
+                let other = decode_tbs(&cert);
+                if other.is_some_and(|other| belvi_cert::is_same_issuance(&tbs, &other)) {
+                    related.confirmed.push(candidate.leaf_hash);
+                }
+            }
+            Err(_) => related.unchecked.push(candidate.leaf_hash),
+        }
+    }
+    Some(related)
+}
+
+/// Fetches a cert's entry from one of the logs it is in, and adds it to the cache.
+async fn fetch_from_log(
+    state: &mut CacheState,
+    in_logs: &[CertLogEntry],
+) -> Result<GetEntriesItem, Response> {
+    let matching_logs: Vec<_> = state
+        .log_list
+        .logs()
+        .filter(|list_log| list_log.readable())
+        .filter_map(|list_log| {
+            let wanted_id = LogId(list_log.log_id.clone()).num();
+            in_logs
+                .iter()
+                .find(|wanted_log| wanted_id == wanted_log.log_id)
+                .map(|v| (list_log, v.idx))
+        })
+        .collect();
+    // the cert is known, so these are errors with the logs rather than the request
+    let (log, idx) = match matching_logs
+        .iter()
+        .find(|(log, idx)| log.has_readable_index(*idx))
+    {
+        Some(val) => *val,
+        None if matching_logs.is_empty() => {
+            return Err(res::error_page(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "This certificate isn't cached, and none of the logs it is in can be read from anymore.",
+            ))
+        }
+        None => {
+            return Err(res::error_page(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "This certificate isn't cached, and its index is beyond the readable range of the read-only logs it is in.",
+            ))
+        }
+    };
+    let entry = match state.fetcher.fetch_entries_exact(log, idx).await {
+        Ok(entry) => entry,
+        Err(FetchError::MissingEntry) => {
+            return Err(res::error_page(
+                StatusCode::BAD_GATEWAY,
+                "The log found no certificate at the index it should be at.",
+            ))
+        }
+        Err(FetchError::TooManyEntries(_)) => {
+            return Err(res::error_page(
+                StatusCode::BAD_GATEWAY,
+                "The log responded with more certificates than requested.",
+            ))
+        }
+        Err(err) => {
+            warn!(
+                "Error fetching cert from \"{}\": {:?}",
+                log.description, err
+            );
+            return Err(res::error_page(
+                StatusCode::BAD_GATEWAY,
+                "The certificate couldn't be fetched from its log. Try again later.",
+            ));
+        }
+    };
+    let timestamped_entry = &entry.leaf_input.timestamped_entry;
+    state.cache_conn.new_cert(
+        &timestamped_entry.db_hash(),
+        timestamped_entry.log_entry.inner_cert(),
+    );
+    state
+        .cache_conn
+        .new_extra_data(&belvi_hash::db(&entry.extra_data), &entry.extra_data);
+    Ok(entry)
+}
+
+/// Encodes certs as PEM. These must be full certs, since precerts are stored as TBS certificates,
+/// which have no PEM label.
+fn pem_response(certs: &[Vec<u8>]) -> Response {
+    let mut headers = HeaderMap::new();
+    // according to https://pki-tutorial.readthedocs.io/en/latest/mime.html
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/x-pem-file"),
+    );
+    (
+        StatusCode::OK,
+        headers,
+        certs
+            .iter()
+            .map(|cert| {
+                format!(
+                    "-----BEGIN CERTIFICATE-----\r\n{}\r\n-----END CERTIFICATE-----\r\n",
+                    base64::encode(cert)
+                )
+            })
+            .fold(String::new(), |a, b| a + &b),
+    )
+        .into_response()
+}
+
+/// The cert followed by the chain of issuers it was logged with. For precerts, this is the signed
+/// precert rather than the TBS certificate.
+async fn get_cert_chain(
+    Path(leaf_hash): Path<String>,
+    Extension(state): Extension<Arc<Mutex<CacheState>>>,
+    Extension(db_pool): Extension<db::Pool>,
+) -> Response {
+    let FoundCert {
+        cert,
+        in_logs,
+        extra_data,
+    } = match find_cert(state.clone(), db_pool.clone(), &leaf_hash).await {
+        Ok(found) => found,
+        Err(res) => return res,
+    };
+    // already validated by find_cert
+    let leaf_hash = hex::decode(leaf_hash).unwrap();
+    let cert_info = task::spawn_blocking(move || {
+        let db = db_pool.get()?;
+        let mut query = db
+            .prepare_cached("SELECT extra_hash, cert_type FROM certs WHERE leaf_hash = ?")
+            .unwrap();
+        Ok::<_, r2d2::Error>(
+            query
+                .query_row([leaf_hash], |row| {
+                    Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, u8>(1)?))
+                })
+                .ok(),
+        )
+    })
+    .await
+    .unwrap();
+    let (extra_hash, cert_type) = match cert_info {
+        Ok(Some(val)) => val,
+        Ok(None) => return res::not_found("Certificate"),
+        Err(_) => return res::db_unavailable(),
+    };
+
+    // find_cert already has the extra data if it fetched the cert from a log
+    let cached = match extra_data {
+        Some(extra_data) => Some(extra_data),
+        None => {
+            state
+                .lock()
+                .await
+                .cache_conn
+                .get_extra_data(&extra_hash)
+                .await
+        }
+    };
+    let extra_data = match cached {
+        Some(extra_data) => extra_data,
+        None => match fetch_from_log(&mut *state.lock().await, &in_logs).await {
+            Ok(entry) => entry.extra_data,
+            Err(res) => return res,
+        },
+    };
+    let is_precert = cert_type == LogEntry::PRECERT_NUM;
+    let chain = match parse_extra_data(is_precert, &extra_data) {
+        Ok(chain) => chain,
+        Err(_) => return res::error(Some("Log entry has invalid chain".to_string())),
+    };
+    if is_precert {
+        pem_response(&chain)
+    } else {
+        pem_response(&[vec![cert], chain].concat())
+    }
+}
+
 async fn get_cert(
     Path(leaf_hash): Path<String>,
+    req_headers: HeaderMap,
     Extension(state): Extension<Arc<Mutex<CacheState>>>,
+    Extension(db_pool): Extension<db::Pool>,
 ) -> impl IntoResponse {
     #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     enum OutputMode {
         Der,
         Html,
         Pem,
+        Text,
     }
 
     let mut parts = leaf_hash.split('.');
@@ -336,46 +845,85 @@ async fn get_cert(
         None => OutputMode::Html,
         Some("der") => OutputMode::Der,
         Some("pem") => OutputMode::Pem,
+        Some("txt") => OutputMode::Text,
         Some("ber" | "cer") => return res::redirect(&format!("/cert/{}.der", leaf_hash)),
         Some("html") => return res::redirect(&format!("/cert/{}", leaf_hash)),
         _ => return res::error(Some("Unknown extension".to_string())),
     };
 
-    match find_cert(state, leaf_hash).await {
-        Ok(FoundCert { cert, in_logs }) => match ext {
-            OutputMode::Html => cert_response(&cert, leaf_hash, in_logs),
-            OutputMode::Der => (
-                StatusCode::OK,
-                {
-                    let mut headers = HeaderMap::new();
-                    // according to https://pki-tutorial.readthedocs.io/en/latest/mime.html
-                    headers.insert(
-                        header::CONTENT_TYPE,
-                        HeaderValue::from_static("application/x-x509-ca-cert"),
-                    );
-                    headers
-                },
-                cert,
-            )
-                .into_response(),
-            OutputMode::Pem => (
-                StatusCode::OK,
-                {
-                    let mut headers = HeaderMap::new();
-                    // according to https://pki-tutorial.readthedocs.io/en/latest/mime.html
-                    headers.insert(
+    // certs are identified by their hash, so the DER and PEM forms never change. The HTML form
+    // lists the logs the cert is in, which can change, and the text form can change with how
+    // certs are rendered. The ETag is weak since the body can be compressed. It's only checked
+    // once the cert is known to exist in that form, so errors are never sent as 304s.
+    let etag = format!("W/\"{}\"", leaf_hash.to_ascii_lowercase());
+
+    match find_cert(state.clone(), db_pool.clone(), leaf_hash).await {
+        Ok(FoundCert { cert, in_logs, .. }) => match ext {
+            OutputMode::Html => {
+                // already validated by find_cert
+                let leaf_hash_bytes = hex::decode(leaf_hash).unwrap();
+                let related = find_related_certs(state, db_pool, &cert, leaf_hash_bytes).await;
+                cert_response(&cert, leaf_hash, in_logs, related.as_ref())
+            }
+            OutputMode::Der if res::etag_matches(&req_headers, &etag) => {
+                res::not_modified(&etag)
+            }
+            OutputMode::Der => res::cache_forever(
+                (
+                    StatusCode::OK,
+                    {
+                        let mut headers = HeaderMap::new();
+                        // according to https://pki-tutorial.readthedocs.io/en/latest/mime.html
+                        headers.insert(
+                            header::CONTENT_TYPE,
+                            HeaderValue::from_static("application/x-x509-ca-cert"),
+                        );
+                        headers
+                    },
+                    cert,
+                )
+                    .into_response(),
+                &etag,
+            ),
+            OutputMode::Text => match decode_cert(&cert, Format::Text) {
+                Some(DecodedCert {
+                    rendered,
+                    full_cert,
+                    ..
+                }) => (
+                    StatusCode::OK,
+                    [(
                         header::CONTENT_TYPE,
-                        HeaderValue::from_static("application/x-pem-file"),
-                    );
-                    headers
-                },
-                // TODO: CERTIFICATE should be different for precerts?
-                format!(
-                    "-----BEGIN CERTIFICATE-----\r\n{}\r\n-----END CERTIFICATE-----\r\n",
-                    base64::encode(cert)
-                ),
-            )
-                .into_response(),
+                        HeaderValue::from_static("text/plain; charset=utf-8"),
+                    )],
+                    format!(
+                        "{}:\n{}",
+                        if full_cert {
+                            "Certificate"
+                        } else {
+                            "Precertificate"
+                        },
+                        rendered
+                            .lines()
+                            .map(|line| format!("    {}\n", line))
+                            .collect::<String>()
+                    ),
+                )
+                    .into_response(),
+                None => res::error(Some("Certificate couldn't be decoded".to_string())),
+            },
+            OutputMode::Pem => match decode_cert(&cert, Format::Html) {
+                Some(DecodedCert {
+                    full_cert: true, ..
+                }) if res::etag_matches(&req_headers, &etag) => res::not_modified(&etag),
+                Some(DecodedCert {
+                    full_cert: true, ..
+                }) => res::cache_forever(pem_response(&[cert]), &etag),
+                _ => res::error(Some(format!(
+                    "Precertificates are only available as PEM with their chain, at /cert/{}/chain.pem",
+                    leaf_hash
+                ))),
+            },
         },
         Err(res) => res,
     }
@@ -385,13 +933,13 @@ macro_rules! pages {
     ($($page:expr),*) => {
         const PAGES: &[(&str, &str)] = &[
             $(
-                ($page, include_str!(concat!(concat!("pages/", $page), ".html")))
+                ($page, include_str!(concat!(concat!("pages/", $page), ".html"))),
             )*
         ];
     };
 }
 
-pages!["regex"];
+pages!["regex", "subdomain", "exact", "wildcard"];
 
 async fn get_page(Path(page): Path<String>) -> impl IntoResponse {
     let page = PAGES.iter().find(|(id, _)| **id == *page);
@@ -421,51 +969,320 @@ async fn get_page(Path(page): Path<String>) -> impl IntoResponse {
         .into_response()
 }
 
+/// Build and deployment info for debugging. The git commit is taken from `BELVI_GIT_COMMIT` at
+/// build time, e.g. `BELVI_GIT_COMMIT=$(git rev-parse HEAD) cargo build`.
+async fn get_version(Extension(state): Extension<Arc<Mutex<CacheState>>>) -> impl IntoResponse {
+    let state = state.lock().await;
+    Json(serde_json::json!({
+        "product_name": PRODUCT_NAME,
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_commit": option_env!("BELVI_GIT_COMMIT"),
+        "log_list": {
+            "version": state.log_list.version,
+            "timestamp": state.log_list.log_list_timestamp,
+        },
+        "cache": {
+            "ttl": state.cache_conn.cert_ttl(),
+            "compression": state.cache_conn.compression(),
+            "in_memory": state.cache_conn.is_in_memory(),
+        },
+    }))
+}
+
+/// How far the scanner has fetched each log, from its fetch state file.
+async fn get_status(Extension(state): Extension<Arc<Mutex<CacheState>>>) -> Response {
+    let status = match task::spawn_blocking(|| FetchStatus::read(&data_path()))
+        .await
+        .unwrap()
+    {
+        Ok(status) => status,
+        Err(err) => {
+            warn!("Couldn't read fetch state: {:?}", err);
+            return res::error_page(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "The scanner's fetch state couldn't be read.",
+            );
+        }
+    };
+    let state = state.lock().await;
+    let mut logs: Vec<_> = status
+        .log_states
+        .iter()
+        .map(|(id, log_status)| {
+            let name = state
+                .log_list
+                .logs()
+                .find(|log| log.log_id == id.0)
+                .map_or_else(|| id.0.clone(), |log| log.description.clone());
+            (name, log_status)
+        })
+        .collect();
+    drop(state);
+    logs.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let rows = logs
+        .iter()
+        .map(|(name, log_status)| {
+            let sth_time = match i64::try_from(log_status.sth.timestamp)
+                .ok()
+                .and_then(|timestamp| Utc.timestamp_millis_opt(timestamp).single())
+            {
+                Some(time) => format!(
+                    r#"<time datetime="{time}">{time}</time>"#,
+                    time = time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+                ),
+                None => format!("Invalid timestamp {}", log_status.sth.timestamp),
+            };
+            let ranges = log_status.fetched_to.ranges();
+            let ranges = if ranges.is_empty() {
+                "(none)".to_string()
+            } else {
+                ranges
+                    .iter()
+                    .map(|(start, end)| format!("{}–{}", start, end))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            let fetched = log_status.fetched_to.count();
+            let percent = if log_status.sth.tree_size == 0 {
+                100.0
+            } else {
+                fetched as f64 * 100.0 / log_status.sth.tree_size as f64
+            };
+            format!(
+                r#"<tr><td>{}</td><td>{}</td><td>{sth_time}</td><td>{}</td><td>{:.1}%</td></tr>"#,
+                name.html_escape(),
+                log_status.sth.tree_size,
+                ranges,
+                percent,
+                sth_time = sth_time,
+            )
+        })
+        .collect::<String>();
+    (
+        StatusCode::OK,
+        res::html_headers(),
+        format!(
+            include_str!("tmpl/base.html"),
+            title = format_args!("Status - {}", PRODUCT_NAME),
+            product_name = PRODUCT_NAME,
+            heading = "Status",
+            heading_classes = "",
+            content = format_args!(include_str!("tmpl/status.html"), rows = rows),
+            css = include_str!("tmpl/base.css"),
+            script = include_str!("tmpl/dates.js"),
+        ),
+    )
+        .into_response()
+}
+
+/// Used if `BELVI_ROBOTS_TXT` isn't set. Searches can be slow, so crawlers are kept away from
+/// them, but cert pages are permalinks that are fine to crawl.
+const DEFAULT_ROBOTS_TXT: &str =
+    "User-agent: *\nAllow: /cert/\nDisallow: /?\nDisallow: /api/\nDisallow: /search.csv\n";
+
+lazy_static::lazy_static! {
+    static ref ROBOTS_TXT: String =
+        env::var("BELVI_ROBOTS_TXT").unwrap_or_else(|_| DEFAULT_ROBOTS_TXT.to_string());
+}
+
+async fn get_robots_txt() -> impl IntoResponse {
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/plain")],
+        ROBOTS_TXT.as_str(),
+    )
+}
+
+/// Whether a request is for search results, which shouldn't be indexed since they change
+/// constantly. The root page is only a search if it has a query string.
+fn is_search(uri: &Uri) -> bool {
+    match uri.path() {
+        "/" => uri.query().is_some(),
+        "/api/search" | "/search.csv" => true,
+        _ => false,
+    }
+}
+
+async fn noindex_search_middleware<B>(req: Request<B>, next: Next<B>) -> Response {
+    let search = is_search(req.uri());
+    let mut res = next.run(req).await;
+    if search {
+        res.headers_mut().insert(
+            header::HeaderName::from_static("x-robots-tag"),
+            HeaderValue::from_static("noindex"),
+        );
+    }
+    res
+}
+
+/// Routes for load balancers, which aren't logged or rate limited.
+const HEALTH_CHECK_PATHS: &[&str] = &["/healthz", "/readyz"];
+
+/// How long `/readyz` waits for a database connection.
+const READY_DB_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The process is running.
+async fn get_healthz() -> impl IntoResponse {
+    "ok"
+}
+
+/// The database and Redis can both be used.
+async fn get_readyz(
+    Extension(state): Extension<Arc<Mutex<CacheState>>>,
+    Extension(db_pool): Extension<db::Pool>,
+) -> impl IntoResponse {
+    let db_ready = task::spawn_blocking(move || match db_pool.get_timeout(READY_DB_TIMEOUT) {
+        Ok(db) => db.query_row("SELECT 1", [], |_| Ok(())).is_ok(),
+        Err(_) => false,
+    })
+    .await
+    .unwrap();
+    let cache_ready = state.lock().await.cache_conn.ping().await;
+    let status = if db_ready && cache_ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(serde_json::json!({
+            "db": db_ready,
+            "cache": cache_ready,
+        })),
+    )
+}
+
 async fn global_404() -> impl IntoResponse {
     res::not_found("Page")
 }
 
+lazy_static::lazy_static! {
+    /// Whether requests are logged to stdout as JSON, instead of with `debug!`.
+    static ref JSON_ACCESS_LOG: bool = env::var("BELVI_JSON_ACCESS_LOG").is_ok();
+}
+
+/// One line of the JSON access log.
+fn access_log_json(
+    ip: SocketAddr,
+    method: &axum::http::Method,
+    uri: &Uri,
+    user_agent: Option<&HeaderValue>,
+    status: StatusCode,
+    duration: Duration,
+) -> String {
+    serde_json::json!({
+        "time": Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        "ip": ip.ip().to_string(),
+        "method": method.as_str(),
+        "path": uri.path(),
+        "query": uri.query(),
+        "status": status.as_u16(),
+        "duration_ms": duration.as_secs_f64() * 1000.0,
+        "user_agent": user_agent.and_then(|user_agent| user_agent.to_str().ok()),
+    })
+    .to_string()
+}
+
 async fn log_middleware<B>(req: Request<B>, next: Next<B>) -> Response {
-    debug!(
-        "{:?} {:?} {:?} {:?}",
-        req.extensions().get::<ConnectInfo<SocketAddr>>().unwrap().0,
-        req.method(),
-        req.uri(),
-        req.headers()
-            .get(axum::http::header::USER_AGENT)
-            .map(Clone::clone)
-            .unwrap_or_else(|| HeaderValue::from_static("-")),
-    );
-    next.run(req).await
+    if HEALTH_CHECK_PATHS.contains(&req.uri().path()) {
+        return next.run(req).await;
+    }
+    let ip = req.extensions().get::<ConnectInfo<SocketAddr>>().unwrap().0;
+    let user_agent = req.headers().get(header::USER_AGENT).cloned();
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+    let start = Instant::now();
+    // logged after the response is ready, so the status and time taken can be included
+    let res = next.run(req).await;
+    let duration = start.elapsed();
+    if *JSON_ACCESS_LOG {
+        println!(
+            "{}",
+            access_log_json(
+                ip,
+                &method,
+                &uri,
+                user_agent.as_ref(),
+                res.status(),
+                duration
+            )
+        );
+    } else {
+        debug!(
+            "{:?} {:?} {:?} {:?} {} {:?}",
+            ip,
+            method,
+            uri,
+            user_agent.unwrap_or_else(|| HeaderValue::from_static("-")),
+            res.status().as_u16(),
+            duration,
+        );
+    }
+    res
+}
+
+/// Used if `BELVI_RATE_LIMIT` isn't set.
+const DEFAULT_RATE_LIMIT: f64 = 120.0;
+/// Used if `BELVI_RATE_LIMIT_BURST` isn't set.
+const DEFAULT_RATE_LIMIT_BURST: f64 = 20.0;
+
+lazy_static::lazy_static! {
+    static ref RATE_LIMITER: std::sync::Mutex<RateLimiter> = {
+        let per_minute = match env::var("BELVI_RATE_LIMIT") {
+            Ok(rate) => rate.parse().expect("invalid BELVI_RATE_LIMIT"),
+            Err(_) => DEFAULT_RATE_LIMIT,
+        };
+        assert!(per_minute > 0.0, "BELVI_RATE_LIMIT must be positive");
+        let burst = match env::var("BELVI_RATE_LIMIT_BURST") {
+            Ok(burst) => burst.parse().expect("invalid BELVI_RATE_LIMIT_BURST"),
+            Err(_) => DEFAULT_RATE_LIMIT_BURST,
+        };
+        std::sync::Mutex::new(RateLimiter::new(per_minute, burst))
+    };
+}
+
+async fn rate_limit_middleware<B>(req: Request<B>, next: Next<B>) -> Response {
+    if HEALTH_CHECK_PATHS.contains(&req.uri().path()) {
+        return next.run(req).await;
+    }
+    let ip = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .unwrap()
+        .0
+        .ip();
+    let (allowed, retry_after) = {
+        let mut limiter = RATE_LIMITER.lock().unwrap();
+        (limiter.check(ip), limiter.retry_after())
+    };
+    if allowed {
+        next.run(req).await
+    } else {
+        debug!("Rate limited {:?}", ip);
+        let mut res = res::error_page(
+            StatusCode::TOO_MANY_REQUESTS,
+            "You are making too many requests. Try again in a few seconds.",
+        );
+        res.headers_mut().insert(
+            header::RETRY_AFTER,
+            HeaderValue::from(retry_after.as_secs().max(1)),
+        );
+        res
+    }
 }
 
 async fn handle_422_middleware<B>(req: Request<B>, next: Next<B>) -> Response {
     let mut res = next.run(req).await;
-    if res.status() == StatusCode::UNPROCESSABLE_ENTITY {
-        let error = res.data().await.map(|bytes| bytes.ok()).flatten();
-        (
+    let is_json = res.headers().get(header::CONTENT_TYPE)
+        == Some(&HeaderValue::from_static("application/json"));
+    if res.status() == StatusCode::UNPROCESSABLE_ENTITY && !is_json {
+        let error = res.data().await.and_then(|bytes| bytes.ok());
+        res::error_page(
             StatusCode::UNPROCESSABLE_ENTITY,
-            res::html_headers(),
-            format!(
-                include_str!("tmpl/base.html"),
-                title = format_args!("Error - {}", PRODUCT_NAME),
-                product_name = PRODUCT_NAME,
-                heading = "Error",
-                heading_classes = "",
-                content = format_args!(
-                    include_str!("tmpl/error.html"),
-                    error
-                        .map(|b| String::from_utf8_lossy(&*b).into_owned())
-                        .unwrap_or_else(
-                            || "Your request could not be processed at this time".to_string()
-                        )
-                        .html_escape()
-                ),
-                css = include_str!("tmpl/base.css"),
-                script = "",
-            ),
+            &error
+                .map(|b| String::from_utf8_lossy(&b).into_owned())
+                .unwrap_or_else(|| "Your request could not be processed at this time".to_string()),
         )
-            .into_response()
     } else {
         res
     }
@@ -475,27 +1292,249 @@ async fn handle_422_middleware<B>(req: Request<B>, next: Next<B>) -> Response {
 async fn main() {
     env_logger::init();
 
+    // parsed first, so a bad address or time zone fails before anything is set up
+    let bind_addr: SocketAddr = match env::var("BELVI_BIND_ADDR") {
+        Ok(addr) => addr
+            .parse()
+            .unwrap_or_else(|e| panic!("invalid BELVI_BIND_ADDR {:?}: {}", addr, e)),
+        Err(_) => DEFAULT_BIND_ADDR.parse().unwrap(),
+    };
+    lazy_static::initialize(&search::DISPLAY_TZ);
+
+    let cache_conn = match belvi_cache::Connection::try_new().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            let size = match env::var("BELVI_MEMORY_CACHE_SIZE") {
+                Ok(size) => size.parse().expect("invalid BELVI_MEMORY_CACHE_SIZE"),
+                Err(_) => DEFAULT_MEMORY_CACHE_SIZE,
+            };
+            warn!(
+                "Couldn't connect to Redis ({}), caching up to {} certs in memory instead",
+                e, size
+            );
+            belvi_cache::Connection::in_memory(size)
+        }
+    };
     let cache_state = Arc::new(Mutex::new(CacheState {
-        cache_conn: belvi_cache::Connection::new().await,
+        cache_conn,
         log_list: LogList::google(),
         fetcher: Fetcher::new(),
+        not_found: NotFoundCache::new(NOT_FOUND_CACHE_SIZE, NOT_FOUND_CACHE_AGE),
     }));
 
+    let db_pool_size = match env::var("BELVI_DB_POOL_SIZE") {
+        Ok(size) => size.parse().expect("invalid BELVI_DB_POOL_SIZE"),
+        Err(_) => DEFAULT_DB_POOL_SIZE,
+    };
+    let db_pool = db::pool(data_path(), db_pool_size);
+
     let app = Router::new()
         .route("/", get(get_root))
+        .route("/api/search", get(get_api_search))
+        .route("/search.csv", get(get_search_csv))
+        .route("/feed.atom", get(get_feed))
         .route("/cert/:leaf_hash", get(get_cert))
+        .route("/cert/:leaf_hash/chain.pem", get(get_cert_chain))
         .route("/docs/:page", get(get_page))
+        .route("/version", get(get_version))
+        .route("/status", get(get_status))
+        .route("/robots.txt", get(get_robots_txt))
+        .route("/healthz", get(get_healthz))
+        .route("/readyz", get(get_readyz))
         .fallback(global_404.into_service())
+        .layer(middleware::from_fn(rate_limit_middleware))
         .layer(middleware::from_fn(log_middleware))
         .layer(middleware::from_fn(handle_422_middleware))
+        .layer(middleware::from_fn(noindex_search_middleware))
         .layer(Extension(cache_state))
+        .layer(Extension(db_pool))
+        // outside of the other layers, so they see uncompressed bodies
+        .layer(
+            CompressionLayer::new().compress_when(
+                DefaultPredicate::new()
+                    // DER certs are small and don't compress well
+                    .and(NotForContentType::const_new("application/x-x509-ca-cert")),
+            ),
+        )
         .layer(SetResponseHeaderLayer::if_not_present(
             header::SERVER,
             HeaderValue::from_static("belvi/0.1"),
         ));
 
-    axum::Server::bind(&"0.0.0.0:47371".parse().unwrap())
+    axum::Server::bind(&bind_addr)
         .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_access_log() {
+        let line = access_log_json(
+            "192.0.2.1:1234".parse().unwrap(),
+            &axum::http::Method::GET,
+            &"/?query=example.com".parse().unwrap(),
+            Some(&HeaderValue::from_static("curl/7.0")),
+            StatusCode::NOT_FOUND,
+            Duration::from_micros(1500),
+        );
+        assert!(!line.contains('\n'));
+        let entry: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(entry["ip"], "192.0.2.1");
+        assert_eq!(entry["method"], "GET");
+        assert_eq!(entry["path"], "/");
+        assert_eq!(entry["query"], "query=example.com");
+        assert_eq!(entry["status"], 404);
+        assert_eq!(entry["duration_ms"], 1.5);
+        assert_eq!(entry["user_agent"], "curl/7.0");
+        let line = access_log_json(
+            "[::1]:1234".parse().unwrap(),
+            &axum::http::Method::HEAD,
+            &"/cert/00".parse().unwrap(),
+            None,
+            StatusCode::OK,
+            Duration::ZERO,
+        );
+        let entry: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(entry["ip"], "::1");
+        assert_eq!(entry["query"], serde_json::Value::Null);
+        assert_eq!(entry["user_agent"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn decode_garbage_cert() {
+        assert!(decode_cert(&[], Format::Html).is_none());
+        assert!(decode_cert(b"not a certificate", Format::Html).is_none());
+        assert!(decode_cert(&[0x30, 0x03, 0x02, 0x01, 0x00], Format::Html).is_none());
+        let decoded = decode_cert(
+            include_bytes!("../../test_certs/policesf.der"),
+            Format::Html,
+        )
+        .unwrap();
+        assert!(decoded.full_cert);
+        assert!(!decoded.domains.is_empty());
+        assert!(!decoded.issuer_key.is_empty());
+    }
+
+    #[test]
+    fn related_cert_text() {
+        let related = |confirmed: &[u8], unchecked: &[u8]| Related {
+            confirmed: confirmed.iter().map(|hash| vec![*hash]).collect(),
+            unchecked: unchecked.iter().map(|hash| vec![*hash]).collect(),
+        };
+        assert_eq!(
+            related_html(false, Some(&related(&[1], &[2]))),
+            r#"<p>Final certificate: <a href="/cert/01"><code>01</code></a>.</p>"#
+        );
+        assert!(related_html(true, Some(&related(&[], &[2]))).contains("Possibly issued"));
+        assert!(related_html(false, Some(&related(&[], &[]))).contains("No final certificate"));
+        assert!(related_html(false, None).contains("can't be determined"));
+        assert_eq!(related_html(true, Some(&related(&[], &[]))), "");
+        assert_eq!(related_html(true, None), "");
+    }
+
+    #[test]
+    fn decode_precerts() {
+        let decoded = decode_cert(
+            include_bytes!("../../test_certs/webcares.der"),
+            Format::Html,
+        )
+        .unwrap();
+        assert!(!decoded.full_cert);
+        // the poison is removed from precerts in log entries
+        assert!(!decoded.rendered.contains("bvcert-poison"));
+        let decoded = decode_cert(
+            include_bytes!("../../test_certs/poisoned.der"),
+            Format::Html,
+        )
+        .unwrap();
+        assert!(!decoded.full_cert);
+        assert!(decoded.rendered.contains("bvcert-poison"));
+        let decoded = decode_cert(
+            include_bytes!("../../test_certs/poisoned.der"),
+            Format::Text,
+        )
+        .unwrap();
+        assert!(decoded.rendered.contains("This is a precertificate"));
+        assert!(!decoded.rendered.contains('<'));
+    }
+
+    #[test]
+    fn search_pages() {
+        let uri = |s: &str| s.parse::<Uri>().unwrap();
+        assert!(!is_search(&uri("/")));
+        assert!(is_search(&uri("/?query=example.com")));
+        assert!(is_search(&uri("/api/search?query=example.com")));
+        assert!(is_search(&uri("/search.csv")));
+        assert!(!is_search(&uri("/cert/abc?query=example.com")));
+        assert!(!is_search(&uri("/docs/regex")));
+    }
+
+    #[test]
+    fn too_complex_regex_page() {
+        let res = search_error(search::SearchError::RegexTooComplex(
+            belvi_db::RegexLimit::Size,
+        ));
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(res.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+    }
+
+    #[tokio::test]
+    async fn etags_need_an_existing_cert() {
+        let data_path = env::temp_dir().join(format!("belvi-etag-test-{}", std::process::id()));
+        std::fs::create_dir_all(&data_path).unwrap();
+        belvi_db::connect_at(&data_path);
+        let state = Arc::new(Mutex::new(CacheState {
+            cache_conn: belvi_cache::Connection::in_memory(1),
+            log_list: LogList::google(),
+            fetcher: Fetcher::new(),
+            not_found: NotFoundCache::new(NOT_FOUND_CACHE_SIZE, NOT_FOUND_CACHE_AGE),
+        }));
+        let db_pool = db::pool(data_path.clone(), 1);
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static("*"));
+        for (id, status) in [
+            (
+                "00000000000000000000000000000000.der",
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                "00000000000000000000000000000000.pem",
+                StatusCode::NOT_FOUND,
+            ),
+            ("abc.der", StatusCode::UNPROCESSABLE_ENTITY),
+        ] {
+            let res = get_cert(
+                Path(id.to_string()),
+                headers.clone(),
+                Extension(state.clone()),
+                Extension(db_pool.clone()),
+            )
+            .await
+            .into_response();
+            assert_eq!(res.status(), status, "{}", id);
+            assert!(!res.headers().contains_key(header::ETAG), "{}", id);
+        }
+        std::fs::remove_dir_all(&data_path).unwrap();
+    }
+
+    #[test]
+    fn etags() {
+        let headers = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static(value));
+            headers
+        };
+        assert!(res::etag_matches(&headers("\"ab\""), "W/\"ab\""));
+        assert!(res::etag_matches(&headers("\"cd\", W/\"ab\""), "W/\"ab\""));
+        assert!(res::etag_matches(&headers("*"), "W/\"ab\""));
+        assert!(!res::etag_matches(&headers("\"abc\""), "W/\"ab\""));
+        assert!(!res::etag_matches(&HeaderMap::new(), "W/\"ab\""));
+    }
+}
@@ -0,0 +1,88 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Remembers recently requested certs that weren't found, so repeated requests for them don't need
+//! to query the database.
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+pub struct NotFoundCache {
+    capacity: usize,
+    /// Certs can be added later, so entries are only trusted for this long.
+    max_age: Duration,
+    entries: HashMap<Vec<u8>, Instant>,
+    /// Keys in the order they were added, oldest first.
+    order: VecDeque<Vec<u8>>,
+}
+
+impl NotFoundCache {
+    #[must_use]
+    pub fn new(capacity: usize, max_age: Duration) -> Self {
+        Self {
+            capacity,
+            max_age,
+            entries: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Was this leaf hash recently not found?
+    #[must_use]
+    pub fn contains(&self, leaf_hash: &[u8]) -> bool {
+        self.contains_at(leaf_hash, Instant::now())
+    }
+
+    fn contains_at(&self, leaf_hash: &[u8], now: Instant) -> bool {
+        match self.entries.get(leaf_hash) {
+            Some(added) => now.duration_since(*added) < self.max_age,
+            None => false,
+        }
+    }
+
+    pub fn insert(&mut self, leaf_hash: Vec<u8>) {
+        self.insert_at(leaf_hash, Instant::now());
+    }
+
+    fn insert_at(&mut self, leaf_hash: Vec<u8>, now: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.insert(leaf_hash.clone(), now).is_some() {
+            // already present, move to the back
+            self.order.retain(|key| *key != leaf_hash);
+        }
+        self.order.push_back(leaf_hash);
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn evicts_oldest() {
+        let mut cache = NotFoundCache::new(2, Duration::from_secs(60));
+        cache.insert(vec![1]);
+        cache.insert(vec![2]);
+        cache.insert(vec![1]);
+        cache.insert(vec![3]);
+        assert!(cache.contains(&[1]));
+        assert!(!cache.contains(&[2]));
+        assert!(cache.contains(&[3]));
+    }
+
+    #[test]
+    fn expires() {
+        let mut cache = NotFoundCache::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+        cache.insert_at(vec![1], now);
+        assert!(cache.contains_at(&[1], now + Duration::from_secs(59)));
+        assert!(!cache.contains_at(&[1], now + Duration::from_secs(60)));
+    }
+}
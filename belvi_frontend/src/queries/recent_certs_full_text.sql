@@ -0,0 +1,11 @@
+-- SPDX-License-Identifier: Apache-2.0
+SELECT log_entries.leaf_hash, log_entries.log_id, log_entries.ts, domains.domain, certs.extra_hash, certs.not_before, certs.not_after
+FROM domains_fts
+JOIN domains ON domains.rowid = domains_fts.rowid
+LEFT JOIN log_entries ON log_entries.leaf_hash = domains.leaf_hash
+LEFT JOIN certs ON log_entries.leaf_hash = certs.leaf_hash
+WHERE domains_fts MATCH ?
+    AND certs.not_before BETWEEN ? AND ? AND certs.not_after BETWEEN ? AND ?
+    AND certs.sct_count >= ?
+    AND log_entries.log_id BETWEEN ? AND ?
+ORDER BY domains.domain
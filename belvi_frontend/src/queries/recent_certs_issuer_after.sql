@@ -0,0 +1,17 @@
+-- SPDX-License-Identifier: Apache-2.0
+SELECT log_entries.leaf_hash, log_entries.log_id, log_entries.ts, domains.domain, certs.extra_hash, certs.not_before, certs.not_after
+FROM certs
+JOIN log_entries ON log_entries.leaf_hash = certs.leaf_hash
+LEFT JOIN domains ON log_entries.leaf_hash = domains.leaf_hash
+WHERE (log_entries.ts, log_entries.leaf_hash) < (?, ?)
+    AND certs.issuer_key = ?
+    AND certs.not_before BETWEEN ? AND ? AND certs.not_after BETWEEN ? AND ?
+    AND certs.sct_count >= ?
+    AND log_entries.log_id BETWEEN ? AND ?
+    -- only each cert's newest entry in those logs, so certs in more than one log are shown once
+    AND NOT EXISTS (
+        SELECT 1 FROM log_entries AS newer
+        WHERE newer.leaf_hash = log_entries.leaf_hash AND newer.log_id BETWEEN ? AND ?
+            AND newer.ts > log_entries.ts
+    )
+ORDER BY log_entries.ts DESC, log_entries.leaf_hash DESC
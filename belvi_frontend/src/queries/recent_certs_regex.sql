@@ -4,4 +4,7 @@ FROM domains
 LEFT JOIN log_entries ON log_entries.leaf_hash = domains.leaf_hash
 LEFT JOIN certs ON log_entries.leaf_hash = certs.leaf_hash
 WHERE regex(?, domains.domain)
+    AND certs.not_before BETWEEN ? AND ? AND certs.not_after BETWEEN ? AND ?
+    AND certs.sct_count >= ?
+    AND log_entries.log_id BETWEEN ? AND ?
 ORDER BY domains.domain
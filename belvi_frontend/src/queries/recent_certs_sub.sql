@@ -4,4 +4,9 @@ FROM domains
 LEFT JOIN log_entries ON log_entries.leaf_hash = domains.leaf_hash
 LEFT JOIN certs ON log_entries.leaf_hash = certs.leaf_hash
 WHERE domrev(lower(domains.domain)) >= ? AND domrev(lower(domains.domain)) < ?
+    -- the range also includes things like example-1.com, so only allow the exact domain or subdomains
+    AND (domrev(lower(domains.domain)) = ? OR domrev(lower(domains.domain)) >= ?)
+    AND certs.not_before BETWEEN ? AND ? AND certs.not_after BETWEEN ? AND ?
+    AND certs.sct_count >= ?
+    AND log_entries.log_id BETWEEN ? AND ?
 ORDER BY domrev(lower(domains.domain))
@@ -0,0 +1,132 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Limits how often each client can make requests, using a token bucket per IP address.
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+/// Once this many clients are tracked, clients with full buckets are forgotten. If that isn't
+/// enough, the clients that made requests least recently are also forgotten, down to half of this,
+/// so the sweep only happens once per `MAX_TRACKED / 2` new clients.
+const MAX_TRACKED: usize = 10_000;
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Tokens added to each bucket per second.
+    rate: f64,
+    /// Maximum number of tokens in a bucket, which is how many requests can be made at once.
+    burst: f64,
+    buckets: HashMap<IpAddr, Bucket>,
+}
+
+impl RateLimiter {
+    /// Allows `per_minute` requests per minute on average, and up to `burst` requests at once.
+    #[must_use]
+    pub fn new(per_minute: f64, burst: f64) -> Self {
+        Self {
+            rate: per_minute / 60.0,
+            burst,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Takes a token for a request from `ip`. Returns false if the request should be rejected.
+    pub fn check(&mut self, ip: IpAddr) -> bool {
+        self.check_at(ip, Instant::now())
+    }
+
+    fn refill(&self, bucket: &mut Bucket, now: Instant) {
+        let elapsed = now.saturating_duration_since(bucket.updated);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        bucket.updated = now;
+    }
+
+    fn check_at(&mut self, ip: IpAddr, now: Instant) -> bool {
+        if self.buckets.len() >= MAX_TRACKED && !self.buckets.contains_key(&ip) {
+            self.forget_some(now);
+        }
+        let mut bucket = *self.buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        self.refill(&mut bucket, now);
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        self.buckets.insert(ip, bucket);
+        allowed
+    }
+
+    /// Forgets clients whose buckets have refilled, since they are the same as new clients. If
+    /// more than half of [`MAX_TRACKED`] are left, the least recently updated are forgotten too.
+    fn forget_some(&mut self, now: Instant) {
+        let (rate, burst) = (self.rate, self.burst);
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated);
+            bucket.tokens + elapsed.as_secs_f64() * rate < burst
+        });
+        let keep = MAX_TRACKED / 2;
+        if self.buckets.len() > keep {
+            let mut updated: Vec<Instant> = self.buckets.values().map(|b| b.updated).collect();
+            let excess = updated.len() - keep;
+            let (_, &mut cutoff, _) = updated.select_nth_unstable(excess - 1);
+            self.buckets.retain(|_, bucket| bucket.updated > cutoff);
+        }
+    }
+
+    /// How long until a client that was just rejected can make another request.
+    #[must_use]
+    pub fn retry_after(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.rate)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn limits_bursts() {
+        let mut limiter = RateLimiter::new(60.0, 2.0);
+        let ip = "192.0.2.1".parse().unwrap();
+        let other_ip = "2001:db8::1".parse().unwrap();
+        let now = Instant::now();
+        assert!(limiter.check_at(ip, now));
+        assert!(limiter.check_at(ip, now));
+        assert!(!limiter.check_at(ip, now));
+        assert!(limiter.check_at(other_ip, now));
+        // one token per second
+        assert!(!limiter.check_at(ip, now + Duration::from_millis(500)));
+        assert!(limiter.check_at(ip, now + Duration::from_millis(1500)));
+        assert!(!limiter.check_at(ip, now + Duration::from_millis(1600)));
+    }
+
+    #[test]
+    fn stays_bounded() {
+        let mut limiter = RateLimiter::new(1.0, 2.0);
+        let now = Instant::now();
+        // every client uses a token, so none have full buckets
+        let ip = |i: usize| IpAddr::from(std::net::Ipv4Addr::from(i as u32));
+        for i in 0..MAX_TRACKED {
+            assert!(limiter.check_at(ip(i), now + Duration::from_millis(i as u64)));
+        }
+        assert_eq!(limiter.buckets.len(), MAX_TRACKED);
+        // a known client doesn't cause a sweep
+        assert!(limiter.check_at(ip(0), now + Duration::from_secs(20)));
+        assert_eq!(limiter.buckets.len(), MAX_TRACKED);
+        assert!(limiter.check_at(ip(MAX_TRACKED), now + Duration::from_secs(20)));
+        assert!(limiter.buckets.len() <= MAX_TRACKED / 2 + 1);
+        assert!(limiter.buckets.contains_key(&ip(MAX_TRACKED)));
+        assert!(limiter.buckets.contains_key(&ip(0)));
+        assert!(!limiter.buckets.contains_key(&ip(1)));
+        assert!(limiter.buckets.contains_key(&ip(MAX_TRACKED - 1)));
+    }
+}
@@ -1,8 +1,9 @@
 // SPDX-License-Identifier: Apache-2.0
 use axum::{
-    http::{HeaderMap, HeaderValue},
+    http::{header, HeaderMap, HeaderValue},
     response::{IntoResponse, Response},
 };
+use belvi_render::html_escape::HtmlEscapable;
 use reqwest::StatusCode;
 
 pub fn html_headers() -> HeaderMap {
@@ -22,12 +23,87 @@ pub fn error(e: Option<String>) -> Response {
         .into_response()
 }
 
+/// Like [`error`], but as JSON for API routes.
+pub fn json_error(e: Option<String>) -> Response {
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        axum::Json(serde_json::json!({
+            "error": e.unwrap_or_else(|| "Your request could not be processed at this time".to_string()),
+        })),
+    )
+        .into_response()
+}
+
+/// `Cache-Control` for responses that never change.
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Lets a response that never changes be cached forever. `etag` must be a valid ETag, which should
+/// be weak since compressed and uncompressed bodies are sent with the same one.
+pub fn cache_forever(mut res: Response, etag: &str) -> Response {
+    let headers = res.headers_mut();
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(IMMUTABLE_CACHE_CONTROL),
+    );
+    headers.insert(header::ETAG, HeaderValue::from_str(etag).unwrap());
+    res
+}
+
+/// Whether the request's `If-None-Match` header lists `etag` or is `*`, so [`not_modified`] can be
+/// sent.
+pub fn etag_matches(req_headers: &HeaderMap, etag: &str) -> bool {
+    // weak comparison, as If-None-Match uses
+    fn opaque(tag: &str) -> &str {
+        tag.strip_prefix("W/").unwrap_or(tag)
+    }
+    let etag = opaque(etag);
+    req_headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || opaque(tag) == etag)
+}
+
+pub fn not_modified(etag: &str) -> Response {
+    cache_forever(StatusCode::NOT_MODIFIED.into_response(), etag)
+}
+
 pub fn redirect(to: &str) -> Response {
     let mut headers = HeaderMap::new();
     headers.insert("Location", HeaderValue::from_str(to).unwrap());
     (StatusCode::FOUND, headers, String::new()).into_response()
 }
 
+/// An HTML page showing an error message.
+pub fn error_page(status: StatusCode, message: &str) -> Response {
+    (
+        status,
+        html_headers(),
+        format!(
+            include_str!("tmpl/base.html"),
+            title = format_args!("Error - {}", super::PRODUCT_NAME),
+            product_name = super::PRODUCT_NAME,
+            heading = "Error",
+            heading_classes = "",
+            content = format_args!(include_str!("tmpl/error.html"), message.html_escape()),
+            css = include_str!("tmpl/base.css"),
+            script = "",
+        ),
+    )
+        .into_response()
+}
+
+/// Sent when a database connection can't be made, such as before the scanner has created the
+/// database.
+pub fn db_unavailable() -> Response {
+    error_page(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "The database isn't available right now. Try again later.",
+    )
+}
+
 pub fn not_found(thing: &'static str) -> Response {
     (
         StatusCode::NOT_FOUND,
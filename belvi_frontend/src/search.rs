@@ -1,40 +1,196 @@
 // SPDX-License-Identifier: Apache-2.0
-use crate::res;
-use axum::response::Response;
 use belvi_render::html_escape::HtmlEscapable;
 use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono_tz::Tz;
 use log::trace;
-use rusqlite::Connection;
+use regex::bytes::Regex;
+use rusqlite::{Connection, ToSql};
 use serde::{Deserialize, Serialize};
-use std::cmp::Ordering;
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    env, fmt,
+    time::{Duration, Instant},
+};
 
-fn render_domain(s: &str) -> String {
+fn escape_domain(s: &str) -> String {
+    s.html_escape()
+        // suggest linebreaks after dots
+        .replace('.', "<wbr>.")
+}
+
+/// Renders a domain, with the first match of `highlight` in a `<mark>`. Each part is escaped
+/// separately, so the highlight can't change how the domain is parsed.
+fn render_domain(s: &str, highlight: Option<&Regex>) -> String {
+    let parts = highlight
+        .and_then(|regex| regex.find(s.as_bytes()))
+        .filter(|found| !found.range().is_empty())
+        .and_then(|found| {
+            Some((
+                s.get(..found.start())?,
+                s.get(found.range())?,
+                s.get(found.end()..)?,
+            ))
+        });
     format!(
         r#"<div class="bvfront-domain">{}</div>"#,
-        s.html_escape()
-            // suggest linebreaks after dots
-            .replace('.', "<wbr>.")
+        match parts {
+            Some((before, found, after)) => format!(
+                "{}<mark>{}</mark>{}",
+                escape_domain(before),
+                escape_domain(found),
+                escape_domain(after)
+            ),
+            None => escape_domain(s),
+        }
     )
 }
 
-fn format_date(date: DateTime<Utc>) -> String {
-    date.format("%k:%M, %e %b %Y").html_escape()
+/// Most domains that can be searched for at once in exact and wildcard searches.
+pub const MAX_QUERY_DOMAINS: usize = 16;
+/// Number of names in `queries/recent_certs_names.sql`. Wildcard searches look up two names for
+/// each domain.
+const QUERY_NAMES: usize = 2 * MAX_QUERY_DOMAINS;
+
+/// Splits a query into the domains in it, which are separated by commas or whitespace.
+fn query_domains(query: &str) -> Result<Vec<&str>, SearchError> {
+    let domains: Vec<&str> = query
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|domain| !domain.is_empty())
+        .collect();
+    if domains.is_empty() {
+        return Err(SearchError::Invalid("No domain to search for".to_string()));
+    }
+    if domains.len() > MAX_QUERY_DOMAINS {
+        return Err(SearchError::Invalid(format!(
+            "At most {} domains can be searched for at once",
+            MAX_QUERY_DOMAINS
+        )));
+    }
+    Ok(domains)
+}
+
+/// The wildcard that covers `domain`, which replaces its first label. Wildcards only cover one
+/// label, and can't be directly under a TLD.
+fn covering_wildcard(domain: &str) -> Option<String> {
+    let (first, parent) = domain.split_once('.')?;
+    if first == "*" || !parent.contains('.') {
+        return None;
+    }
+    Some(format!("*.{}", parent))
+}
+
+lazy_static::lazy_static! {
+    /// Time zone that dates are shown in until `dates.js` shows them in the viewer's time zone.
+    pub static ref DISPLAY_TZ: Tz = match env::var("BELVI_TZ") {
+        Ok(tz) => tz
+            .parse()
+            .unwrap_or_else(|e| panic!("invalid BELVI_TZ {:?}: {}", tz, e)),
+        Err(_) => Tz::UTC,
+    };
+}
+
+/// Formats `date` in the time zone set by `BELVI_TZ`, for people without JavaScript. The result
+/// is HTML escaped.
+pub fn format_date(date: DateTime<Utc>) -> String {
+    format_date_in(date, *DISPLAY_TZ).html_escape()
+}
+
+fn format_date_in(date: DateTime<Utc>, tz: Tz) -> String {
+    let date = date.with_timezone(&tz);
+    // UTC is the default, so it is left unlabelled
+    if tz == Tz::UTC {
+        date.format("%k:%M, %e %b %Y").to_string()
+    } else {
+        date.format("%k:%M %Z, %e %b %Y").to_string()
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMode {
     Regex,
+    /// Matches a domain and all of its subdomains, including wildcards. See `pages/subdomain.html`.
     Subdomain,
+    /// Matches exactly one domain. Wildcards only match themselves. See `pages/exact.html`.
+    Exact,
+    /// Matches one domain, and the wildcard that covers it. See `pages/wildcard.html`.
+    Wildcard,
+    /// Matches domains containing the query. Only works if full-text search has been enabled.
+    FullText,
+    /// Matches certs with a hex serial number. Serials are only unique per issuer, so there can be
+    /// multiple matches.
+    Serial,
+    /// Matches certs from an issuer, by its hex key from `belvi_cert::issuer_key`.
+    Issuer,
     Recent,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Why a search couldn't return results.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchError {
+    /// The query or one of its parameters isn't valid.
+    Invalid(String),
+    /// The regex couldn't be compiled.
+    BadRegex(String),
+    /// The regex is valid, but goes over a complexity limit.
+    RegexTooComplex(belvi_db::RegexLimit),
+    /// The search took longer than its timeout.
+    Timeout,
+    /// The database returned an error, with its message if it had one.
+    Db(Option<String>),
+    /// A database connection couldn't be made.
+    Unavailable,
+    /// The same search should be done at this URL instead.
+    Redirect(String),
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(msg) | Self::BadRegex(msg) | Self::Db(Some(msg)) => f.write_str(msg),
+            Self::RegexTooComplex(belvi_db::RegexLimit::Size) => write!(
+                f,
+                "Your pattern is too complex (limit {} bytes when compiled), try a simpler pattern",
+                belvi_db::REGEX_SIZE_LIMIT
+            ),
+            Self::RegexTooComplex(belvi_db::RegexLimit::Nesting) => write!(
+                f,
+                "Your pattern is too complex (limit {} nested groups), try a simpler pattern",
+                belvi_db::REGEX_NEST_LIMIT
+            ),
+            Self::Timeout => f.write_str("Search timed out, try a more specific search"),
+            Self::Db(None) => f.write_str("Your request could not be processed at this time"),
+            Self::Unavailable => f.write_str("The database isn't available right now"),
+            Self::Redirect(url) => write!(f, "Search moved to {}", url),
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Query {
     pub query: Option<String>,
     pub after: Option<String>,
     pub mode: Option<QueryMode>,
     pub limit: Option<u32>,
+    /// Only show certs with at least this many embedded SCTs.
+    pub min_scts: Option<u32>,
+    /// Only show certs with a `notBefore` at or after this Unix time.
+    pub not_before_after: Option<i64>,
+    /// Only show certs with a `notBefore` at or before this Unix time.
+    pub not_before_before: Option<i64>,
+    /// Only show certs with a `notAfter` at or after this Unix time.
+    pub not_after_after: Option<i64>,
+    /// Only show certs with a `notAfter` at or before this Unix time.
+    pub not_after_before: Option<i64>,
+    /// Only show certs from the log with this ID (see `LogId::num`).
+    pub log_id: Option<u32>,
+    /// Order the results by when they were logged, even in modes that are usually ordered by
+    /// domain. Feeds use this so new certs aren't left out.
+    #[serde(skip)]
+    pub newest_first: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -49,8 +205,17 @@ pub struct CertData {
 }
 
 impl CertData {
-    pub fn render(&self) -> String {
-        let domains = self.domain.iter().fold(String::new(), |a, b| a + b + "");
+    /// Renders the cert as a table row. Matches of `highlight` in its domains are marked, and the
+    /// log is shown using its description from `log_names`.
+    pub fn render(&self, highlight: Option<&Regex>, log_names: &HashMap<u32, &str>) -> String {
+        let domains = if self.domain.is_empty() {
+            "(none)".to_string()
+        } else {
+            self.domain
+                .iter()
+                .map(|domain| render_domain(domain, highlight))
+                .fold(String::new(), |a, b| a + &b)
+        };
         let logged_at =
             DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(self.ts / 1000, 0), Utc);
         let not_before =
@@ -68,16 +233,175 @@ impl CertData {
             not_after = format_date(not_after),
             json = serde_json::to_string(self).unwrap().html_escape(),
             cert_link = hex::encode(&self.leaf_hash),
+            log = log_names
+                .get(&self.log_id)
+                .map(|name| name.html_escape())
+                .unwrap_or_else(|| "unknown".to_string()),
         )
     }
+
+    /// A row of the CSV export, including the trailing line break. See [`CSV_HEADER`].
+    pub fn csv_row(&self) -> String {
+        let rfc3339 = |date: DateTime<Utc>| date.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        let logged_at =
+            DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(self.ts / 1000, 0), Utc);
+        let not_before =
+            DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(self.not_before, 0), Utc);
+        let not_after =
+            DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(self.not_after, 0), Utc);
+        format!(
+            "{},{},{},{},{}\r\n",
+            hex::encode(&self.leaf_hash),
+            csv_field(&self.domain.join(";")),
+            rfc3339(logged_at),
+            rfc3339(not_before),
+            rfc3339(not_after),
+        )
+    }
+
+    fn atom_entry(&self) -> String {
+        let logged_at =
+            DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(self.ts / 1000, 0), Utc);
+        format!(
+            include_str!("tmpl/feed_entry.xml"),
+            id = format_args!("urn:belvi:cert:{}", hex::encode(&self.leaf_hash)),
+            title = self
+                .domain
+                .first()
+                .map(String::as_str)
+                .unwrap_or("(none)")
+                .html_escape(),
+            updated = logged_at.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            cert_link = hex::encode(&self.leaf_hash),
+            summary = self.domain.join(", ").html_escape(),
+        )
+    }
+
+    /// The representation used by the JSON API.
+    pub fn api_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "leaf_hash": hex::encode(&self.leaf_hash),
+            "log_id": self.log_id,
+            "ts": self.ts,
+            "domains": self.domain,
+            "not_before": self.not_before,
+            "not_after": self.not_after,
+        })
+    }
+}
+
+/// Renders an Atom feed of the results of `query`, which should be searched for with
+/// [`Query::newest_first`] set. Links are relative to `base`, which is the site's absolute URL.
+pub fn atom_feed(query: &Query, base: &str, certs: Vec<CertData>) -> String {
+    let updated = certs
+        .first()
+        .map(|cert| {
+            DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(cert.ts / 1000, 0), Utc)
+        })
+        .unwrap_or_else(Utc::now);
+    let mut seen = HashSet::new();
+    let link = match query.url() {
+        url if url.is_empty() => "/".to_string(),
+        url => url,
+    };
+    format!(
+        include_str!("tmpl/feed.xml"),
+        id = format_args!("urn:belvi:feed:{}", link).html_escape(),
+        base = base.html_escape(),
+        title = match &query.query {
+            Some(query) => format!("{} - {}", query, crate::PRODUCT_NAME),
+            None => format!("Newest certificates - {}", crate::PRODUCT_NAME),
+        }
+        .html_escape(),
+        updated = updated.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        link = link.html_escape(),
+        product_name = crate::PRODUCT_NAME.html_escape(),
+        entries = certs
+            .iter()
+            // domain searches can have a cert once for each log it is in
+            .filter(|cert| seen.insert(&cert.leaf_hash))
+            .map(CertData::atom_entry)
+            .fold(String::new(), |a, b| a + &b),
+    )
+}
+
+/// Header of the CSV export. Domains are separated by semicolons.
+pub const CSV_HEADER: &str = "leaf_hash,domains,logged_at,not_before,not_after\r\n";
+
+/// Quotes a CSV field if needed, as described in RFC 4180.
+///
+/// Domains come from certs anyone can log, so fields that spreadsheets would run as a formula are
+/// prefixed with `'`.
+fn csv_field(s: &str) -> String {
+    let s = if s.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", s)
+    } else {
+        s.to_string()
+    };
+    if s.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s
+    }
+}
+
+/// Number of SQLite VM instructions between checks of the search timeout.
+const TIMEOUT_CHECK_OPS: i32 = 1000;
+
+/// Interrupts queries on a connection after a timeout, until dropped. Connections are reused
+/// between searches, so the handler needs to be removed afterwards.
+struct TimeoutGuard<'a> {
+    db: &'a Connection,
+}
+
+impl<'a> TimeoutGuard<'a> {
+    fn new(db: &'a Connection, timeout: Duration) -> Self {
+        let deadline = Instant::now() + timeout;
+        db.progress_handler(TIMEOUT_CHECK_OPS, Some(move || Instant::now() >= deadline));
+        Self { db }
+    }
+}
+
+impl Drop for TimeoutGuard<'_> {
+    fn drop(&mut self) {
+        self.db.progress_handler(0, None::<fn() -> bool>);
+    }
+}
+
+/// How long counting the results of a search can take. Counting finds every result instead of
+/// just the first page, so it's only done if it's fast.
+const COUNT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Counts the certs matched by a search query, or returns `None` if that would be slow. Regex
+/// searches aren't counted, since that always needs every domain to be checked.
+fn count_matches(db: &Connection, query_sql: &str, params: &[&dyn ToSql]) -> Option<usize> {
+    let mut stmt = db
+        .prepare_cached(&format!(
+            "SELECT COUNT(DISTINCT leaf_hash) FROM ({})",
+            query_sql
+        ))
+        .unwrap();
+    let _timeout_guard = TimeoutGuard::new(db, COUNT_TIMEOUT);
+    stmt.query_row(params, |row| row.get(0)).ok()
 }
 
 pub struct SearchResults {
     pub certs: Vec<CertData>,
     pub count: Option<usize>,
+    /// Cursor for the next page, used as `after`.
     pub next: Option<String>,
 }
 
+/// Splits an `after` cursor into its number and the rest.
+///
+/// For recent certs and other searches ordered by time, the cursor is the `ts` and hex leaf hash
+/// of the last cert on the previous page, so pages don't shift as new certs are added. For subdomain searches, it's the rowid and
+/// domain of the first result of the next page.
+fn split_cursor(after: &str) -> Option<(i64, &str)> {
+    let (num, rest) = after.split_once(':')?;
+    Some((num.parse().ok()?, rest))
+}
+
 impl Query {
     pub fn url(&self) -> String {
         let qstr = serde_urlencoded::ser::to_string(self).unwrap();
@@ -88,96 +412,412 @@ impl Query {
         }
     }
 
-    pub fn search_sync(&self, db: &Connection, limit: u32) -> Result<SearchResults, Response> {
+    /// A regex for the part of each domain that made it match, if the mode has one.
+    #[must_use]
+    pub fn highlighter(&self) -> Option<Regex> {
+        let query = self.query.as_deref()?;
+        match self.mode? {
+            QueryMode::Regex => belvi_db::build_regex(query).ok(),
+            QueryMode::FullText => belvi_db::build_regex(&regex::escape(query)).ok(),
+            _ => None,
+        }
+    }
+
+    fn has_validity_range(&self) -> bool {
+        self.not_before_after.is_some()
+            || self.not_before_before.is_some()
+            || self.not_after_after.is_some()
+            || self.not_after_before.is_some()
+    }
+
+    /// The inclusive range of log IDs to search.
+    fn log_range(&self) -> [u32; 2] {
+        match self.log_id {
+            Some(log_id) => [log_id, log_id],
+            None => [u32::MIN, u32::MAX],
+        }
+    }
+
+    /// The inclusive `notBefore` and `notAfter` ranges to search, as
+    /// `[not_before_start, not_before_end, not_after_start, not_after_end]`.
+    fn validity_range(&self) -> Result<[i64; 4], SearchError> {
+        let not_before = (
+            self.not_before_after.unwrap_or(i64::MIN),
+            self.not_before_before.unwrap_or(i64::MAX),
+        );
+        let not_after = (
+            self.not_after_after.unwrap_or(i64::MIN),
+            self.not_after_before.unwrap_or(i64::MAX),
+        );
+        if not_before.0 > not_before.1 {
+            return Err(SearchError::Invalid(
+                "not_before_after must not be later than not_before_before".to_string(),
+            ));
+        }
+        if not_after.0 > not_after.1 {
+            return Err(SearchError::Invalid(
+                "not_after_after must not be later than not_after_before".to_string(),
+            ));
+        }
+        Ok([not_before.0, not_before.1, not_after.0, not_after.1])
+    }
+
+    pub fn search_sync(&self, db: &Connection, limit: u32) -> Result<SearchResults, SearchError> {
+        self.search_sync_with_timeout(db, limit, None)
+    }
+
+    /// Like [`Query::search_sync`], but gives up if fetching the results takes longer than
+    /// `timeout`, so slow regexes can't use a thread for too long.
+    pub fn search_sync_with_timeout(
+        &self,
+        db: &Connection,
+        limit: u32,
+        timeout: Option<Duration>,
+    ) -> Result<SearchResults, SearchError> {
+        let (regex_sql, sub_sql, full_text_sql) = if self.newest_first {
+            (
+                include_str!("queries/recent_certs_regex_newest.sql"),
+                include_str!("queries/recent_certs_sub_newest.sql"),
+                include_str!("queries/recent_certs_full_text_newest.sql"),
+            )
+        } else {
+            (
+                include_str!("queries/recent_certs_regex.sql"),
+                include_str!("queries/recent_certs_sub.sql"),
+                include_str!("queries/recent_certs_full_text.sql"),
+            )
+        };
         let mut certs_stmt = db
             .prepare_cached(include_str!("queries/recent_certs.sql"))
             .unwrap();
-        let mut certs_regex_stmt = db
-            .prepare_cached(include_str!("queries/recent_certs_regex.sql"))
+        let mut certs_regex_stmt = db.prepare_cached(regex_sql).unwrap();
+        let mut cert_sub_stmt = db.prepare_cached(sub_sql).unwrap();
+        let mut certs_after_stmt = db
+            .prepare_cached(include_str!("queries/recent_certs_after.sql"))
+            .unwrap();
+        let mut certs_names_stmt = db
+            .prepare_cached(include_str!("queries/recent_certs_names.sql"))
             .unwrap();
-        let mut cert_sub_stmt = db
-            .prepare_cached(include_str!("queries/recent_certs_sub.sql"))
+        let mut certs_names_after_stmt = db
+            .prepare_cached(include_str!("queries/recent_certs_names_after.sql"))
+            .unwrap();
+        let mut certs_serial_stmt = db
+            .prepare_cached(include_str!("queries/recent_certs_serial.sql"))
+            .unwrap();
+        let mut certs_serial_after_stmt = db
+            .prepare_cached(include_str!("queries/recent_certs_serial_after.sql"))
+            .unwrap();
+        let mut certs_issuer_stmt = db
+            .prepare_cached(include_str!("queries/recent_certs_issuer.sql"))
+            .unwrap();
+        let mut certs_issuer_after_stmt = db
+            .prepare_cached(include_str!("queries/recent_certs_issuer_after.sql"))
+            .unwrap();
+        let mut certs_validity_stmt = db
+            .prepare_cached(include_str!("queries/recent_certs_validity.sql"))
             .unwrap();
         let mut certs_count_stmt = db.prepare_cached("SELECT COUNT(*) FROM certs").unwrap();
-        let mode = self.mode.unwrap_or(QueryMode::Recent);
-        let after = self.after.clone().and_then(|after| {
-            let (p1, p2) = after.split_once(':')?;
-            Some((p1.parse::<usize>().ok()?, p2.to_string()))
-        });
+        // only prepared when needed since the table doesn't exist if full-text search isn't enabled
+        let mut certs_full_text_stmt;
+        let mode = match (&self.query, self.mode.unwrap_or(QueryMode::Recent)) {
+            // IP addresses don't have subdomains, and reversed domains can start with them
+            (Some(query), QueryMode::Subdomain | QueryMode::Wildcard)
+                if belvi_db::is_ip_literal(query.as_bytes()) =>
+            {
+                QueryMode::Exact
+            }
+            (_, mode) => mode,
+        };
+        let min_scts = self.min_scts.unwrap_or(0);
+        let [log_start, log_end] = self.log_range();
+        let [nb_start, nb_end, na_start, na_end] = self.validity_range()?;
+        let after = self.after.as_deref().and_then(split_cursor);
         trace!("after = {:?}", after);
+        // the cursor for modes ordered by time
+        let ts_after = after.and_then(|(ts, leaf_hash)| Some((ts, hex::decode(leaf_hash).ok()?)));
         let (mut certs_rows, count) = match (&self.query, mode) {
-            (Some(query), QueryMode::Regex) => (certs_regex_stmt.query([query]).unwrap(), None),
-            (Some(query), QueryMode::Subdomain) => (
-                cert_sub_stmt
-                    .query([
-                        [
-                            belvi_db::domrev(
-                                (if let Some((_, ref dom)) = after {
-                                    dom
-                                } else {
-                                    query
-                                })
-                                .to_ascii_lowercase()
-                                .as_bytes(),
-                            ),
-                            if after.is_some() {
-                                Vec::new()
-                            } else {
-                                vec![b'.']
-                            },
-                        ]
-                        .concat(),
-                        [
-                            belvi_db::domrev(query.to_ascii_lowercase().as_bytes()),
-                            vec![b'/'],
-                        ]
-                        .concat(),
+            (Some(query), QueryMode::Regex) => (
+                certs_regex_stmt
+                    .query(rusqlite::params![
+                        query, nb_start, nb_end, na_start, na_end, min_scts, log_start, log_end
                     ])
                     .unwrap(),
                 None,
             ),
-            (None, QueryMode::Recent) => (
-                certs_stmt.query([]).unwrap(),
-                Some(
-                    certs_count_stmt
-                        .query_row([], |row| row.get::<_, usize>(0))
-                        .unwrap(),
-                ),
-            ),
+            (Some(query), QueryMode::Subdomain) => {
+                if query_domains(query)?.len() > 1 {
+                    return Err(SearchError::Invalid(
+                        "Subdomain searches can only be for one domain".to_string(),
+                    ));
+                }
+                // matches the domain itself, and anything ending in .domain (including wildcards)
+                let exact = belvi_db::domrev(query.to_ascii_lowercase().as_bytes());
+                let start = match after {
+                    Some((_, dom)) => belvi_db::domrev(dom.to_ascii_lowercase().as_bytes()),
+                    None => exact.clone(),
+                };
+                let subdomains_start = [&exact[..], b"."].concat();
+                let end = [&exact[..], b"/"].concat();
+                let params = rusqlite::params![
+                    start,
+                    end,
+                    exact,
+                    subdomains_start,
+                    nb_start,
+                    nb_end,
+                    na_start,
+                    na_end,
+                    min_scts,
+                    log_start,
+                    log_end
+                ];
+                (
+                    cert_sub_stmt.query(params).unwrap(),
+                    after
+                        .is_none()
+                        .then(|| count_matches(db, sub_sql, params))
+                        .flatten(),
+                )
+            }
+            (Some(query), QueryMode::Exact | QueryMode::Wildcard) => {
+                // uses the same index as subdomain searches, which is case-insensitive
+                let mut names = Vec::new();
+                for domain in query_domains(query)? {
+                    let domain = domain.to_ascii_lowercase();
+                    if mode == QueryMode::Wildcard && !belvi_db::is_ip_literal(domain.as_bytes()) {
+                        if let Some(wildcard) = covering_wildcard(&domain) {
+                            names.push(belvi_db::domrev(wildcard.as_bytes()));
+                        }
+                    }
+                    names.push(belvi_db::domrev(domain.as_bytes()));
+                }
+                names.resize(QUERY_NAMES, names[0].clone());
+                let mut params: Vec<&dyn ToSql> =
+                    names.iter().map(|name| name as &dyn ToSql).collect();
+                params.extend(rusqlite::params![
+                    nb_start, nb_end, na_start, na_end, min_scts, log_start, log_end, log_start,
+                    log_end
+                ]);
+                match &ts_after {
+                    Some((ts, leaf_hash)) => {
+                        let after_params: Vec<&dyn ToSql> = rusqlite::params![ts, leaf_hash]
+                            .iter()
+                            .chain(&params)
+                            .copied()
+                            .collect();
+                        (certs_names_after_stmt.query(&*after_params).unwrap(), None)
+                    }
+                    None => (
+                        certs_names_stmt.query(&*params).unwrap(),
+                        count_matches(db, include_str!("queries/recent_certs_names.sql"), &params),
+                    ),
+                }
+            }
+            (Some(query), QueryMode::FullText) => {
+                if query.chars().count() < 3 {
+                    return Err(SearchError::Invalid(
+                        "Search must be at least 3 characters long".to_string(),
+                    ));
+                }
+                certs_full_text_stmt = match db.prepare_cached(full_text_sql) {
+                    Ok(stmt) => stmt,
+                    Err(_) => {
+                        return Err(SearchError::Invalid(
+                            "Full-text search isn't enabled".to_string(),
+                        ))
+                    }
+                };
+                // search for the query as a single phrase
+                let phrase = format!("\"{}\"", query.replace('"', "\"\""));
+                let params = rusqlite::params![
+                    phrase, nb_start, nb_end, na_start, na_end, min_scts, log_start, log_end
+                ];
+                (
+                    certs_full_text_stmt.query(params).unwrap(),
+                    count_matches(db, full_text_sql, params),
+                )
+            }
+            (Some(query), QueryMode::Serial) => {
+                // allow the common formats like 01:2b:91 or 01 2B 91
+                let serial: String = query.chars().filter(|c| !matches!(c, ':' | ' ')).collect();
+                let serial = match hex::decode(serial) {
+                    Ok(serial) => serial,
+                    Err(_) => return Err(SearchError::Invalid("Serial must be hex".to_string())),
+                };
+                let serial = belvi_cert::normalize_serial(&serial);
+                if serial.len() > 20 {
+                    return Err(SearchError::Invalid(
+                        "Serial can't be longer than 20 bytes".to_string(),
+                    ));
+                }
+                match ts_after {
+                    Some((ts, leaf_hash)) => (
+                        certs_serial_after_stmt
+                            .query(rusqlite::params![
+                                ts, leaf_hash, serial, nb_start, nb_end, na_start, na_end,
+                                min_scts, log_start, log_end, log_start, log_end
+                            ])
+                            .unwrap(),
+                        None,
+                    ),
+                    None => {
+                        let params = rusqlite::params![
+                            serial, nb_start, nb_end, na_start, na_end, min_scts, log_start,
+                            log_end, log_start, log_end
+                        ];
+                        (
+                            certs_serial_stmt.query(params).unwrap(),
+                            count_matches(
+                                db,
+                                include_str!("queries/recent_certs_serial.sql"),
+                                params,
+                            ),
+                        )
+                    }
+                }
+            }
+            (Some(query), QueryMode::Issuer) => {
+                let issuer_key: String =
+                    query.chars().filter(|c| !matches!(c, ':' | ' ')).collect();
+                let issuer_key = match hex::decode(issuer_key) {
+                    Ok(issuer_key) => issuer_key,
+                    Err(_) => {
+                        return Err(SearchError::Invalid("Issuer key must be hex".to_string()))
+                    }
+                };
+                match ts_after {
+                    Some((ts, leaf_hash)) => (
+                        certs_issuer_after_stmt
+                            .query(rusqlite::params![
+                                ts, leaf_hash, issuer_key, nb_start, nb_end, na_start, na_end,
+                                min_scts, log_start, log_end, log_start, log_end
+                            ])
+                            .unwrap(),
+                        None,
+                    ),
+                    None => {
+                        let params = rusqlite::params![
+                            issuer_key, nb_start, nb_end, na_start, na_end, min_scts, log_start,
+                            log_end, log_start, log_end
+                        ];
+                        (
+                            certs_issuer_stmt.query(params).unwrap(),
+                            count_matches(
+                                db,
+                                include_str!("queries/recent_certs_issuer.sql"),
+                                params,
+                            ),
+                        )
+                    }
+                }
+            }
+            (None, QueryMode::Recent) => {
+                match ts_after {
+                    Some((ts, leaf_hash)) if self.has_validity_range() => (
+                        certs_validity_stmt
+                            .query(rusqlite::params![
+                                ts, leaf_hash, nb_start, nb_end, na_start, na_end, min_scts,
+                                log_start, log_end, log_start, log_end
+                            ])
+                            .unwrap(),
+                        None,
+                    ),
+                    None if self.has_validity_range() => (
+                        certs_validity_stmt
+                            .query(rusqlite::params![
+                                i64::MAX,
+                                Vec::<u8>::new(),
+                                nb_start,
+                                nb_end,
+                                na_start,
+                                na_end,
+                                min_scts,
+                                log_start,
+                                log_end,
+                                log_start,
+                                log_end
+                            ])
+                            .unwrap(),
+                        None,
+                    ),
+                    // the total is only shown on the first page
+                    Some((ts, leaf_hash)) => (
+                        certs_after_stmt
+                            .query(rusqlite::params![
+                                ts, leaf_hash, nb_start, nb_end, na_start, na_end, min_scts,
+                                log_start, log_end, log_start, log_end
+                            ])
+                            .unwrap(),
+                        None,
+                    ),
+                    None => (
+                        certs_stmt
+                            .query(rusqlite::params![
+                                nb_start, nb_end, na_start, na_end, min_scts, log_start, log_end,
+                                log_start, log_end
+                            ])
+                            .unwrap(),
+                        // counting only certs with enough SCTs or in one log would need a full table
+                        // scan
+                        if min_scts == 0 && self.log_id.is_none() {
+                            Some(
+                                certs_count_stmt
+                                    .query_row([], |row| row.get::<_, usize>(0))
+                                    .unwrap(),
+                            )
+                        } else {
+                            None
+                        },
+                    ),
+                }
+            }
             // query provided but is not needed
             (Some(_), QueryMode::Recent) => {
                 let mut query = (*self).clone();
                 query.query = None;
-                return Err(res::redirect(&query.url()));
+                return Err(SearchError::Redirect(query.url()));
             }
             // no query provided
-            (None, _) => return Err(res::redirect("/")),
+            (None, _) => return Err(SearchError::Redirect("/".to_string())),
         };
 
+        let _timeout_guard = timeout.map(|timeout| TimeoutGuard::new(db, timeout));
         let mut certs = Vec::new();
         let mut next = None;
         loop {
             let val = match certs_rows.next() {
                 Ok(Some(val)) => val,
                 Ok(None) => break,
-                Err(rusqlite::Error::SqliteFailure(_, err)) => return Err(res::error(err)),
+                Err(rusqlite::Error::SqliteFailure(
+                    rusqlite::ffi::Error {
+                        code: rusqlite::ErrorCode::OperationInterrupted,
+                        ..
+                    },
+                    _,
+                )) => return Err(SearchError::Timeout),
+                // errors from the regex function are reported this way
+                Err(rusqlite::Error::SqliteFailure(_, Some(err))) if mode == QueryMode::Regex => {
+                    let pattern = self.query.as_deref().unwrap_or_default();
+                    let limit = belvi_db::take_regex_error()
+                        .and_then(|regex_err| belvi_db::regex_limit_exceeded(pattern, &regex_err));
+                    return Err(match limit {
+                        Some(limit) => SearchError::RegexTooComplex(limit),
+                        None => SearchError::BadRegex(err),
+                    });
+                }
+                Err(rusqlite::Error::SqliteFailure(_, err)) => return Err(SearchError::Db(err)),
                 Err(e) => panic!("unexpected error fetching certs {:#?}", e),
             };
-            if let Some((min_rowid, _)) = after {
-                let rowid: usize = val.get(7).unwrap();
+            if let (QueryMode::Subdomain, Some((min_rowid, _))) = (mode, after) {
+                let rowid: i64 = val.get(7).unwrap();
                 if min_rowid == rowid {
                     // multiple domains with same name, skip earlier ones
                     certs = Vec::new();
                 }
             };
-            let (domain, domain_rendered) = match val.get::<_, String>(3) {
-                Ok(domain) => {
-                    let rendered = render_domain(&domain);
-                    (Some(domain), rendered)
-                }
-                Err(rusqlite::Error::InvalidColumnType(_, _, rusqlite::types::Type::Null)) => {
-                    (None, "(none)".to_string())
-                }
+            let domain = match val.get::<_, String>(3) {
+                Ok(domain) => Some(domain),
+                Err(rusqlite::Error::InvalidColumnType(_, _, rusqlite::types::Type::Null)) => None,
                 other => panic!("unexpected domain fetching error {:?}", other),
             };
             let leaf_hash = val.get(0).unwrap();
@@ -185,20 +825,35 @@ impl Query {
                 .last()
                 .map(|last: &CertData| last.leaf_hash == leaf_hash)
             {
-                // extension of last
-                certs.last_mut().unwrap().domain.push(domain_rendered);
+                // extension of last, which can also be the same cert in another log
+                let last = certs.last_mut().unwrap();
+                if let Some(domain) = domain {
+                    if !last.domain.contains(&domain) {
+                        last.domain.push(domain);
+                    }
+                }
             } else {
                 match certs.len().cmp(&(limit as usize)) {
                     Ordering::Less => {}
                     // stop requesting rows once we get enough
                     Ordering::Equal => {
-                        if mode == QueryMode::Subdomain {
-                            next = Some(format!(
+                        next = match mode {
+                            // the cursor is a position in domain order
+                            QueryMode::Subdomain if !self.newest_first => Some(format!(
                                 "{}:{}",
-                                val.get::<_, usize>(7).unwrap(),
+                                val.get::<_, i64>(7).unwrap(),
                                 domain.unwrap_or_else(String::new),
-                            ));
-                        }
+                            )),
+                            QueryMode::Recent
+                            | QueryMode::Exact
+                            | QueryMode::Wildcard
+                            | QueryMode::Serial
+                            | QueryMode::Issuer => {
+                                let last = certs.last().unwrap();
+                                Some(format!("{}:{}", last.ts, hex::encode(&last.leaf_hash)))
+                            }
+                            _ => None,
+                        };
                         break;
                     }
                     Ordering::Greater => unreachable!(),
@@ -207,7 +862,7 @@ impl Query {
                     leaf_hash,
                     log_id: val.get(1).unwrap(),
                     ts: val.get(2).unwrap(),
-                    domain: vec![domain_rendered],
+                    domain: domain.into_iter().collect(),
                     extra_hash: val.get(4).unwrap(),
                     not_before: val.get(5).unwrap(),
                     not_after: val.get(6).unwrap(),
@@ -221,3 +876,697 @@ impl Query {
         Ok(SearchResults { certs, count, next })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn display_tz() {
+        let date = DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(1_650_000_000, 0), Utc);
+        assert_eq!(format_date_in(date, Tz::UTC), " 5:20, 15 Apr 2022");
+        assert_eq!(
+            format_date_in(date, chrono_tz::America::New_York),
+            " 1:20 EDT, 15 Apr 2022"
+        );
+        assert_eq!(
+            format_date_in(date, chrono_tz::Asia::Tokyo),
+            "14:20 JST, 15 Apr 2022"
+        );
+        assert!("Not/A_Zone".parse::<Tz>().is_err());
+    }
+
+    fn test_db(domains: &[&str]) -> Connection {
+        test_db_with(belvi_db::memory(), domains)
+    }
+
+    fn test_db_with(db: Connection, domains: &[&str]) -> Connection {
+        for (idx, domain) in domains.iter().enumerate() {
+            let leaf_hash = vec![idx as u8];
+            db.execute(
+                "INSERT INTO certs (leaf_hash, extra_hash, not_before, not_after, cert_type) VALUES (?, x'00', 0, 0, 1)",
+                [&leaf_hash],
+            )
+            .unwrap();
+            db.execute(
+                "INSERT INTO log_entries (leaf_hash, log_id, ts, idx) VALUES (?, 1, ?, ?)",
+                rusqlite::params![leaf_hash, idx, idx],
+            )
+            .unwrap();
+            db.execute(
+                "INSERT INTO domains (leaf_hash, domain) VALUES (?, ?)",
+                rusqlite::params![leaf_hash, domain],
+            )
+            .unwrap();
+        }
+        db
+    }
+
+    fn search(db: &Connection, query: &str, mode: QueryMode) -> Vec<u8> {
+        let query = Query {
+            query: Some(query.to_string()),
+            mode: Some(mode),
+            ..Default::default()
+        };
+        let mut found: Vec<u8> = query
+            .search_sync(db, 100)
+            .unwrap_or_else(|_| panic!("search failed"))
+            .certs
+            .into_iter()
+            .map(|cert| cert.leaf_hash[0])
+            .collect();
+        found.sort_unstable();
+        found
+    }
+
+    #[test]
+    fn recent_pages() {
+        let db = test_db(&[
+            "a.example",
+            "b.example",
+            "c.example",
+            "d.example",
+            "e.example",
+        ]);
+        // same ts as the cert before it, so the cursor needs the leaf hash too
+        db.execute_batch(
+            "INSERT INTO certs (leaf_hash, extra_hash, not_before, not_after, cert_type) VALUES (x'05', x'00', 0, 0, 1);
+            INSERT INTO log_entries (leaf_hash, log_id, ts, idx) VALUES (x'05', 1, 4, 5);",
+        )
+        .unwrap();
+        let mut query = Query::default();
+        let mut pages = Vec::new();
+        loop {
+            let results = query
+                .search_sync(&db, 2)
+                .unwrap_or_else(|_| panic!("search failed"));
+            pages.push(
+                results
+                    .certs
+                    .iter()
+                    .map(|cert| cert.leaf_hash[0])
+                    .collect::<Vec<_>>(),
+            );
+            match results.next {
+                Some(next) => query.after = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(pages, vec![vec![5, 4], vec![3, 2], vec![1, 0]]);
+
+        // new certs don't change later pages
+        query.after = Some("3:03".to_string());
+        db.execute_batch(
+            "INSERT INTO certs (leaf_hash, extra_hash, not_before, not_after, cert_type) VALUES (x'06', x'00', 0, 0, 1);
+            INSERT INTO log_entries (leaf_hash, log_id, ts, idx) VALUES (x'06', 1, 10, 6);",
+        )
+        .unwrap();
+        let results = query
+            .search_sync(&db, 2)
+            .unwrap_or_else(|_| panic!("search failed"));
+        assert_eq!(results.certs.len(), 2);
+        assert_eq!(results.certs[0].leaf_hash, vec![2]);
+    }
+
+    #[test]
+    fn time_ordered_pages() {
+        let db = test_db(&["www.example.com"; 5]);
+        db.execute_batch("UPDATE certs SET serial = x'01', issuer_key = x'02';")
+            .unwrap();
+        for (mode, query) in [
+            (QueryMode::Exact, "www.example.com"),
+            (QueryMode::Wildcard, "www.example.com"),
+            (QueryMode::Serial, "01"),
+            (QueryMode::Issuer, "02"),
+        ] {
+            let mut query = Query {
+                query: Some(query.to_string()),
+                mode: Some(mode),
+                ..Default::default()
+            };
+            let mut pages = Vec::new();
+            let mut counts = Vec::new();
+            loop {
+                let results = query
+                    .search_sync(&db, 2)
+                    .unwrap_or_else(|_| panic!("{:?} search failed", mode));
+                pages.push(
+                    results
+                        .certs
+                        .iter()
+                        .map(|cert| cert.leaf_hash[0])
+                        .collect::<Vec<_>>(),
+                );
+                counts.push(results.count);
+                match results.next {
+                    Some(next) => query.after = Some(next),
+                    None => break,
+                }
+            }
+            assert_eq!(pages, vec![vec![4, 3], vec![2, 1], vec![0]], "{:?}", mode);
+            assert_eq!(counts, vec![Some(5), None, None], "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn recent_pages_multiple_logs() {
+        let db = test_db(&["a.example", "b.example", "c.example", "d.example"]);
+        db.execute_batch(
+            // cert 1 is in another log at the same time, and has another domain
+            "INSERT INTO log_entries (leaf_hash, log_id, ts, idx) VALUES (x'01', 2, 1, 0);
+            INSERT INTO domains (leaf_hash, domain) VALUES (x'01', 'b2.example');
+            -- cert 3 is in another log a bit earlier, which sorts right after its first entry
+            INSERT INTO log_entries (leaf_hash, log_id, ts, idx) VALUES (x'03', 2, 2, 1);",
+        )
+        .unwrap();
+        let mut query = Query::default();
+        let mut found = Vec::new();
+        for _ in 0..10 {
+            let results = query
+                .search_sync(&db, 1)
+                .unwrap_or_else(|_| panic!("search failed"));
+            for cert in results.certs {
+                if cert.leaf_hash == [1] {
+                    assert_eq!(cert.domain, vec!["b2.example", "b.example"]);
+                }
+                found.push(cert.leaf_hash[0]);
+            }
+            match results.next {
+                Some(next) => query.after = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(found, vec![3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn pages_with_nonadjacent_log_entries() {
+        let db = test_db(&["a.example", "b.example", "c.example", "d.example"]);
+        db.execute_batch(
+            // cert 0 was added to another log much later, and cert 2 much earlier
+            "INSERT INTO log_entries (leaf_hash, log_id, ts, idx) VALUES (x'00', 2, 10, 0);
+            INSERT INTO log_entries (leaf_hash, log_id, ts, idx) VALUES (x'02', 2, -10, 1);",
+        )
+        .unwrap();
+        for (mode, query) in [
+            (QueryMode::Recent, None),
+            (
+                QueryMode::Exact,
+                Some("a.example b.example c.example d.example"),
+            ),
+        ] {
+            let mut query = Query {
+                query: query.map(str::to_string),
+                mode: Some(mode),
+                ..Default::default()
+            };
+            let mut found = Vec::new();
+            for _ in 0..10 {
+                let results = query
+                    .search_sync(&db, 1)
+                    .unwrap_or_else(|_| panic!("{:?} search failed", mode));
+                found.extend(
+                    results
+                        .certs
+                        .iter()
+                        .map(|cert| (cert.leaf_hash[0], cert.log_id)),
+                );
+                match results.next {
+                    Some(next) => query.after = Some(next),
+                    None => break,
+                }
+            }
+            // each cert is shown once, at its newest entry
+            assert_eq!(found, vec![(0, 2), (3, 1), (2, 1), (1, 1)], "{:?}", mode);
+        }
+
+        // only entries in the logs being searched count
+        let query = Query {
+            log_id: Some(1),
+            ..Default::default()
+        };
+        let found: Vec<u8> = query
+            .search_sync(&db, 100)
+            .unwrap_or_else(|_| panic!("search failed"))
+            .certs
+            .iter()
+            .map(|cert| cert.leaf_hash[0])
+            .collect();
+        assert_eq!(found, vec![3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn validity_range() {
+        let db = test_db(&["a.example", "b.example", "c.example"]);
+        db.execute_batch(
+            "UPDATE certs SET not_before = 100, not_after = 200 WHERE leaf_hash = x'00';
+            UPDATE certs SET not_before = 150, not_after = 300 WHERE leaf_hash = x'01';
+            UPDATE certs SET not_before = 250, not_after = 400 WHERE leaf_hash = x'02';",
+        )
+        .unwrap();
+        let query = |mode, query: Option<&str>, not_after_after, not_after_before| Query {
+            query: query.map(str::to_string),
+            mode: Some(mode),
+            not_before_before: Some(200),
+            not_after_after,
+            not_after_before,
+            ..Default::default()
+        };
+        let found = |query: Query| {
+            let mut found: Vec<u8> = query
+                .search_sync(&db, 100)
+                .unwrap_or_else(|_| panic!("search failed"))
+                .certs
+                .into_iter()
+                .map(|cert| cert.leaf_hash[0])
+                .collect();
+            found.sort_unstable();
+            found
+        };
+        assert_eq!(
+            found(query(QueryMode::Recent, None, Some(200), Some(300))),
+            vec![0, 1]
+        );
+        assert_eq!(
+            found(query(QueryMode::Recent, None, Some(250), None)),
+            vec![1]
+        );
+        assert_eq!(
+            found(query(QueryMode::Regex, Some("^[ac]"), None, None)),
+            vec![0]
+        );
+        assert!(matches!(
+            query(QueryMode::Recent, None, Some(300), Some(200)).search_sync(&db, 100),
+            Err(SearchError::Invalid(_))
+        ));
+        assert!(matches!(
+            query(QueryMode::Regex, Some("("), None, None).search_sync(&db, 100),
+            Err(SearchError::BadRegex(_))
+        ));
+        assert!(matches!(
+            query(QueryMode::Regex, Some("[a-z]{1000}"), None, None).search_sync(&db, 100),
+            Err(SearchError::RegexTooComplex(belvi_db::RegexLimit::Size))
+        ));
+        let nested = format!("{}a{}", "(".repeat(20), ")".repeat(20));
+        assert!(matches!(
+            query(QueryMode::Regex, Some(&nested), None, None).search_sync(&db, 100),
+            Err(SearchError::RegexTooComplex(belvi_db::RegexLimit::Nesting))
+        ));
+    }
+
+    #[test]
+    fn filters_with_every_mode() {
+        let db = test_db(&["www.example.com", "www.example.com", "www.example.com"]);
+        db.execute_batch(
+            "UPDATE certs SET not_before = 100, not_after = 200, sct_count = 2 WHERE leaf_hash = x'00';
+            UPDATE certs SET not_before = 300, not_after = 400, sct_count = 2 WHERE leaf_hash = x'01';
+            UPDATE certs SET not_before = 300, not_after = 400, sct_count = 0 WHERE leaf_hash = x'02';",
+        )
+        .unwrap();
+        for (mode, query) in [
+            (QueryMode::Regex, "^www"),
+            (QueryMode::Subdomain, "example.com"),
+            (QueryMode::Exact, "www.example.com"),
+            (QueryMode::Wildcard, "www.example.com"),
+        ] {
+            let query = |not_before_after, min_scts| Query {
+                query: Some(query.to_string()),
+                mode: Some(mode),
+                min_scts,
+                not_before_after,
+                ..Default::default()
+            };
+            let found = |query: Query| {
+                let mut found: Vec<u8> = query
+                    .search_sync(&db, 100)
+                    .unwrap_or_else(|_| panic!("{:?} search failed", mode))
+                    .certs
+                    .into_iter()
+                    .map(|cert| cert.leaf_hash[0])
+                    .collect();
+                found.sort_unstable();
+                found
+            };
+            assert_eq!(found(query(None, None)), vec![0, 1, 2], "{:?}", mode);
+            assert_eq!(found(query(Some(200), None)), vec![1, 2], "{:?}", mode);
+            assert_eq!(found(query(None, Some(1))), vec![0, 1], "{:?}", mode);
+            assert_eq!(found(query(Some(200), Some(1))), vec![1], "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn log_filter() {
+        let db = test_db(&["a.example", "b.example", "c.example"]);
+        db.execute(
+            "INSERT INTO log_entries (leaf_hash, log_id, ts, idx) VALUES (x'01', 2, 5, 0)",
+            [],
+        )
+        .unwrap();
+        let query = |mode, query: Option<&str>, log_id| Query {
+            query: query.map(str::to_string),
+            mode: Some(mode),
+            log_id,
+            ..Default::default()
+        };
+        let found = |query: Query| {
+            let results = query
+                .search_sync(&db, 100)
+                .unwrap_or_else(|_| panic!("search failed"));
+            let mut found: Vec<(u8, u32)> = results
+                .certs
+                .into_iter()
+                .map(|cert| (cert.leaf_hash[0], cert.log_id))
+                .collect();
+            found.sort_unstable();
+            (found, results.count)
+        };
+        assert_eq!(
+            found(query(QueryMode::Recent, None, Some(2))),
+            (vec![(1, 2)], None)
+        );
+        assert_eq!(found(query(QueryMode::Recent, None, None)).1, Some(3),);
+        assert_eq!(
+            found(query(QueryMode::Regex, Some("example"), Some(1))).0,
+            vec![(0, 1), (1, 1), (2, 1)]
+        );
+        assert_eq!(
+            found(query(QueryMode::Exact, Some("b.example"), Some(2))),
+            (vec![(1, 2)], Some(1))
+        );
+        assert!(found(query(QueryMode::Exact, Some("a.example"), Some(2)))
+            .0
+            .is_empty());
+    }
+
+    #[test]
+    fn csv_rows() {
+        let cert = CertData {
+            leaf_hash: vec![0xab, 0xcd],
+            log_id: 1,
+            ts: 1_650_000_000_123,
+            domain: vec!["example.com".to_string(), "a,\"b\"".to_string()],
+            extra_hash: vec![],
+            not_before: 1_650_000_000,
+            not_after: 1_660_000_000,
+        };
+        assert_eq!(
+            cert.csv_row(),
+            "abcd,\"example.com;a,\"\"b\"\"\",2022-04-15T05:20:00Z,2022-04-15T05:20:00Z,2022-08-08T23:06:40Z\r\n"
+        );
+        assert_eq!(csv_field("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+        assert_eq!(csv_field("+1"), "'+1");
+        assert_eq!(csv_field("-1.example"), "'-1.example");
+        assert_eq!(csv_field("@sum"), "'@sum");
+        assert_eq!(csv_field("a-b.example"), "a-b.example");
+    }
+
+    #[test]
+    fn atom_feed_newest_first() {
+        let db = test_db(&["a.example", "b.example"]);
+        for (mode, query) in [
+            (QueryMode::Regex, "example"),
+            (QueryMode::Subdomain, "example"),
+        ] {
+            let query = Query {
+                query: Some(query.to_string()),
+                mode: Some(mode),
+                newest_first: true,
+                ..Default::default()
+            };
+            // only the first cert in domain order would be found without newest_first
+            let certs = query
+                .search_sync(&db, 1)
+                .unwrap_or_else(|_| panic!("{:?} search failed", mode))
+                .certs;
+            let feed = atom_feed(&query, "https://belvi.example/", certs);
+            assert!(feed.contains("urn:belvi:cert:01"), "{:?}", mode);
+            assert!(!feed.contains("urn:belvi:cert:00"), "{:?}", mode);
+            let base = format!("xml:base=\"{}\"", "https://belvi.example/".html_escape());
+            assert!(feed.contains(&base));
+            assert!(feed.contains(r#"href="/cert/01""#));
+        }
+
+        // an older entry in another log isn't another feed entry
+        db.execute(
+            "INSERT INTO log_entries (leaf_hash, log_id, ts, idx) VALUES (x'01', 2, -5, 0)",
+            [],
+        )
+        .unwrap();
+        let query = Query {
+            query: Some("example".to_string()),
+            mode: Some(QueryMode::Regex),
+            newest_first: true,
+            ..Default::default()
+        };
+        let certs = query
+            .search_sync(&db, 100)
+            .unwrap_or_else(|_| panic!("search failed"))
+            .certs;
+        assert_eq!(certs.len(), 3);
+        let feed = atom_feed(&query, "https://belvi.example/", certs);
+        assert_eq!(feed.matches("urn:belvi:cert:01").count(), 1);
+    }
+
+    #[test]
+    fn timeout() {
+        let db = test_db(&["a.example"]);
+        db.execute_batch(
+            "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n WHERE x < 10000)
+            INSERT INTO domains (domain, leaf_hash) SELECT 'd' || x || '.example', x'00' FROM n;",
+        )
+        .unwrap();
+        let query = Query {
+            query: Some("^nothing".to_string()),
+            mode: Some(QueryMode::Regex),
+            ..Default::default()
+        };
+        assert!(matches!(
+            query.search_sync_with_timeout(&db, 100, Some(Duration::ZERO)),
+            Err(SearchError::Timeout)
+        ));
+        // the timeout doesn't apply to later searches
+        assert!(query
+            .search_sync(&db, 100)
+            .unwrap_or_else(|_| panic!("search failed"))
+            .certs
+            .is_empty());
+    }
+
+    #[test]
+    fn counts() {
+        let db = test_db(&[
+            "a.example.com",
+            "b.example.com",
+            "c.example.com",
+            "example.org",
+        ]);
+        let query = Query {
+            query: Some("example.com".to_string()),
+            mode: Some(QueryMode::Subdomain),
+            ..Default::default()
+        };
+        let results = query
+            .search_sync(&db, 2)
+            .unwrap_or_else(|_| panic!("search failed"));
+        assert_eq!(results.certs.len(), 2);
+        assert_eq!(results.count, Some(3));
+
+        let query = Query {
+            mode: Some(QueryMode::Regex),
+            ..query
+        };
+        let results = query
+            .search_sync(&db, 2)
+            .unwrap_or_else(|_| panic!("search failed"));
+        assert_eq!(results.count, None);
+    }
+
+    #[test]
+    fn subdomain_wildcards() {
+        let db = test_db(&[
+            "example.com",
+            "www.example.com",
+            "*.example.com",
+            "a.b.EXAMPLE.com",
+            "example-1.com",
+            "myexample.com",
+            "example.com.au",
+            "com",
+        ]);
+        assert_eq!(
+            search(&db, "example.com", QueryMode::Subdomain),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(search(&db, "b.example.com", QueryMode::Subdomain), vec![3]);
+        assert!(search(&db, "example.org", QueryMode::Subdomain).is_empty());
+    }
+
+    #[test]
+    fn ip_literals() {
+        let db = test_db(&["192.0.2.1", "www.1.2.0.192", "192.0.2.10", "2001:db8::1"]);
+        assert_eq!(search(&db, "192.0.2.1", QueryMode::Subdomain), vec![0]);
+        assert_eq!(search(&db, "2001:DB8::1", QueryMode::Subdomain), vec![3]);
+    }
+
+    #[test]
+    fn highlight() {
+        let regex = belvi_db::build_regex("ex.mple").unwrap();
+        assert_eq!(
+            render_domain("www.EXAMPLE.com", Some(&regex)),
+            r#"<div class="bvfront-domain">www<wbr>.<mark>EXAMPLE</mark><wbr>.com</div>"#
+        );
+        assert_eq!(
+            render_domain("example.org", None),
+            r#"<div class="bvfront-domain">example<wbr>.org</div>"#
+        );
+        // the highlight is added after escaping
+        let regex = belvi_db::build_regex("<b>").unwrap();
+        assert_eq!(
+            render_domain("a<b>c", Some(&regex)),
+            r#"<div class="bvfront-domain">a<mark>&#x3C;b&#x3E;</mark>c</div>"#
+        );
+        // empty matches aren't marked
+        let regex = belvi_db::build_regex("^").unwrap();
+        assert_eq!(
+            render_domain("a", Some(&regex)),
+            r#"<div class="bvfront-domain">a</div>"#
+        );
+    }
+
+    #[test]
+    fn serial() {
+        let db = test_db(&["a.example", "b.example", "c.example"]);
+        db.execute_batch(
+            "UPDATE certs SET serial = x'012b91' WHERE leaf_hash IN (x'00', x'02');
+            UPDATE certs SET serial = x'80' WHERE leaf_hash = x'01';",
+        )
+        .unwrap();
+        assert_eq!(search(&db, "012b91", QueryMode::Serial), vec![0, 2]);
+        assert_eq!(search(&db, "01:2B:91", QueryMode::Serial), vec![0, 2]);
+        assert_eq!(search(&db, "0080", QueryMode::Serial), vec![1]);
+        assert!(search(&db, "2b91", QueryMode::Serial).is_empty());
+    }
+
+    #[test]
+    fn issuer() {
+        let db = test_db(&["a.example", "b.example", "c.example"]);
+        db.execute_batch(
+            "UPDATE certs SET issuer_key = x'a5ce37' WHERE leaf_hash IN (x'00', x'02');
+            UPDATE certs SET issuer_key = x'01' WHERE leaf_hash = x'01';",
+        )
+        .unwrap();
+        assert_eq!(search(&db, "a5ce37", QueryMode::Issuer), vec![0, 2]);
+        assert_eq!(search(&db, "A5:CE:37", QueryMode::Issuer), vec![0, 2]);
+        assert!(search(&db, "a5ce", QueryMode::Issuer).is_empty());
+    }
+
+    #[test]
+    fn exact() {
+        let db = test_db(&[
+            "example.com",
+            "www.example.com",
+            "*.example.com",
+            "WWW.EXAMPLE.COM",
+        ]);
+        assert_eq!(search(&db, "www.example.com", QueryMode::Exact), vec![1, 3]);
+        assert_eq!(search(&db, "*.example.com", QueryMode::Exact), vec![2]);
+        assert!(search(&db, "example", QueryMode::Exact).is_empty());
+    }
+
+    #[test]
+    fn domain_lists() {
+        assert_eq!(
+            query_domains(" a.example,b.example\tc.example ,, ").unwrap(),
+            ["a.example", "b.example", "c.example"]
+        );
+        assert!(query_domains(" , ").is_err());
+        let names = include_str!("queries/recent_certs_names.sql")
+            .split_once("IN (")
+            .unwrap()
+            .1
+            .split_once(')')
+            .unwrap()
+            .0;
+        assert_eq!(names.matches('?').count(), QUERY_NAMES);
+
+        let db = test_db(&[
+            "a.example.com",
+            "b.example.com",
+            "*.example.com",
+            "c.example.com",
+            "192.0.2.1",
+        ]);
+        // a cert matching more than one name is only returned once
+        db.execute(
+            "INSERT INTO domains (leaf_hash, domain) VALUES (x'00', 'b.example.com')",
+            [],
+        )
+        .unwrap();
+        assert_eq!(
+            search(&db, "a.example.com, B.example.com", QueryMode::Exact),
+            vec![0, 1]
+        );
+        assert_eq!(
+            search(&db, "a.example.com 192.0.2.1", QueryMode::Wildcard),
+            vec![0, 2, 4]
+        );
+        let all: Vec<String> = (0..MAX_QUERY_DOMAINS)
+            .map(|i| format!("{}.example.com", i))
+            .collect();
+        assert_eq!(search(&db, &all.join(","), QueryMode::Wildcard), vec![2]);
+        let too_many = format!("{},a.example.com", all.join(","));
+        for mode in [QueryMode::Exact, QueryMode::Wildcard] {
+            let query = Query {
+                query: Some(too_many.clone()),
+                mode: Some(mode),
+                ..Default::default()
+            };
+            assert!(matches!(
+                query.search_sync(&db, 100),
+                Err(SearchError::Invalid(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn wildcard() {
+        assert_eq!(
+            covering_wildcard("www.example.com").as_deref(),
+            Some("*.example.com")
+        );
+        assert_eq!(covering_wildcard("example.com"), None);
+        assert_eq!(covering_wildcard("*.example.com"), None);
+        let db = test_db(&[
+            "example.com",
+            "www.example.com",
+            "*.example.com",
+            "*.www.example.com",
+            "*.com",
+            "A.WWW.EXAMPLE.COM",
+        ]);
+        assert_eq!(
+            search(&db, "WWW.example.com", QueryMode::Wildcard),
+            vec![1, 2]
+        );
+        assert_eq!(
+            search(&db, "a.www.example.com", QueryMode::Wildcard),
+            vec![3, 5]
+        );
+        assert_eq!(
+            search(&db, "other.example.com", QueryMode::Wildcard),
+            vec![2]
+        );
+        assert_eq!(search(&db, "example.com", QueryMode::Wildcard), vec![0]);
+        assert_eq!(search(&db, "*.example.com", QueryMode::Wildcard), vec![2]);
+    }
+
+    #[test]
+    fn full_text() {
+        let db = belvi_db::memory();
+        belvi_db::enable_full_text(&db);
+        let db = test_db_with(db, &["paypal.com", "secure-PAYPAL.example", "pay.example"]);
+        assert_eq!(search(&db, "paypal", QueryMode::FullText), vec![0, 1]);
+        assert_eq!(search(&db, "l.com", QueryMode::FullText), vec![0]);
+        assert!(search(&db, "nothing", QueryMode::FullText).is_empty());
+    }
+}
@@ -2,13 +2,32 @@
 use ring::digest;
 
 /// 128-bit hash for storing in DB
+///
+/// This is SHA-256 truncated to 16 bytes. With `n` certs, the probability of any two having the
+/// same hash is about n²/2¹²⁹, so even a billion certs have a collision probability of around
+/// 10⁻²¹. Use [`db_n`] or [`db_full`] if a longer hash is needed, but note that the DB schema
+/// stores 16-byte hashes.
 #[must_use]
 pub fn db(bytes: &[u8]) -> [u8; 16] {
-    digest::digest(&digest::SHA256, bytes).as_ref()[0..16]
+    db_n(bytes)
+}
+
+/// SHA-256 truncated to `N` bytes. The collision probability for `n` certs is about
+/// n²/2^(8N + 1). Panics if `N` is more than 32.
+#[must_use]
+pub fn db_n<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    assert!(N <= 32, "SHA-256 hashes are only 32 bytes");
+    digest::digest(&digest::SHA256, bytes).as_ref()[0..N]
         .try_into()
         .unwrap()
 }
 
+/// Full 256-bit SHA-256 hash.
+#[must_use]
+pub fn db_full(bytes: &[u8]) -> [u8; 32] {
+    db_n(bytes)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -21,4 +40,17 @@ mod test {
             [206, 6, 9, 47, 185, 72, 217, 255, 172, 125, 26, 55, 110, 64, 75, 38]
         );
     }
+
+    #[test]
+    fn right_full_hash() {
+        assert_eq!(
+            db_full(b"hello!"),
+            // 32 byte hash
+            [
+                206, 6, 9, 47, 185, 72, 217, 255, 172, 125, 26, 55, 110, 64, 75, 38, 183, 87, 91,
+                204, 17, 238, 5, 164, 97, 95, 239, 79, 236, 58, 48, 139
+            ]
+        );
+        assert_eq!(db_n::<16>(b"hello!"), db(b"hello!"));
+    }
 }
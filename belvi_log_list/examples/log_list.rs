@@ -5,7 +5,7 @@ use chrono::Utc;
 fn main() {
     let google_list = LogList::google();
     let now = Utc::now();
-    println!("{:30} {:10} {}", "Log", "State", "Current");
+    println!("{:30} {:10} Current", "Log", "State");
     for log in google_list.logs() {
         println!(
             "{:30} {:10} {}",
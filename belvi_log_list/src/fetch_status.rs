@@ -0,0 +1,136 @@
+// SPDX-License-Identifier: Apache-2.0
+//! A read-only view of the scanner's fetch state, for showing how far each log has been fetched.
+//! The scanner owns the state file; this only has the parts that are useful to show, and the
+//! [`HistState`] type that the scanner also uses.
+use super::{log_data::LogSth, LogId};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::Path};
+
+/// Name of the fetch state file in the data directory.
+pub const FETCH_STATE_FILE: &str = "state.json";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FetchStatus {
+    pub log_states: HashMap<LogId, LogFetchStatus>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LogFetchStatus {
+    pub sth: LogSth,
+    pub fetched_to: HistState,
+}
+
+/// Inclusive ranges of a log's entries that have been fetched.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HistState {
+    #[default]
+    NothingFetched,
+    FillingHistGap {
+        hist_gap: (u64, u64),
+        fetching: (u64, u64),
+    },
+    Fetching((u64, u64)),
+}
+
+impl HistState {
+    /// Merges two ranges if they are adjacent or overlap. Ranges can overlap when
+    /// `BELVI_FETCH_OVERLAP` is set.
+    #[must_use]
+    pub fn merge_adjacent_ranges((a1, a2): (u64, u64), (b1, b2): (u64, u64)) -> Option<(u64, u64)> {
+        if a1 <= b2.saturating_add(1) && b1 <= a2.saturating_add(1) {
+            Some((a1.min(b1), a2.max(b2)))
+        } else {
+            None
+        }
+    }
+    #[must_use]
+    pub fn merge_fetched(self, new_range: (u64, u64)) -> Self {
+        match self {
+            Self::NothingFetched => Self::Fetching(new_range),
+            Self::Fetching(fetched) => {
+                if let Some(updated) = Self::merge_adjacent_ranges(fetched, new_range) {
+                    Self::Fetching(updated)
+                } else {
+                    Self::FillingHistGap {
+                        fetching: fetched,
+                        hist_gap: new_range,
+                    }
+                }
+            }
+            Self::FillingHistGap { hist_gap, fetching } => {
+                let hist_gap = Self::merge_adjacent_ranges(hist_gap, new_range)
+                    .expect("non adjacent range when filling hist gap");
+                if let Some(combined) = Self::merge_adjacent_ranges(hist_gap, fetching) {
+                    Self::Fetching(combined)
+                } else {
+                    Self::FillingHistGap { fetching, hist_gap }
+                }
+            }
+        }
+    }
+
+    /// The fetched ranges, oldest first.
+    #[must_use]
+    pub fn ranges(&self) -> Vec<(u64, u64)> {
+        match *self {
+            Self::NothingFetched => Vec::new(),
+            Self::FillingHistGap { hist_gap, fetching } => vec![hist_gap, fetching],
+            Self::Fetching(fetching) => vec![fetching],
+        }
+    }
+
+    /// How many entries have been fetched.
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.ranges()
+            .iter()
+            .map(|(start, end)| end - start + 1)
+            .sum()
+    }
+}
+
+#[derive(Debug)]
+pub enum ReadError {
+    Io(std::io::Error),
+    Deserialize(serde_json::Error),
+}
+
+impl FetchStatus {
+    /// Reads the fetch state from the data directory `data_path`.
+    pub fn read(data_path: &Path) -> Result<Self, ReadError> {
+        let data = fs::read(data_path.join(FETCH_STATE_FILE)).map_err(ReadError::Io)?;
+        serde_json::from_slice(&data).map_err(ReadError::Deserialize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let status: FetchStatus = serde_json::from_str(
+            r#"{
+                "state_ver": 1,
+                "log_states": {
+                    "9lyUL9F3MCIUVBgIMJRWjuNNExkzv98MLyALzE7xZOM=": {
+                        "sth": {"tree_size": 100, "timestamp": 0, "sha256_root_hash": "", "tree_head_signature": ""},
+                        "fetched_to": {"FillingHistGap": {"hist_gap": [10, 19], "fetching": [50, 99]}}
+                    },
+                    "b1N2rDHwMRnYmQCkURX/dxUcEdkCwQApBo2yCJo32RM=": {
+                        "sth": {"tree_size": 0, "timestamp": 0, "sha256_root_hash": "", "tree_head_signature": ""},
+                        "fetched_to": "NothingFetched"
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        let argon =
+            &status.log_states[&LogId("9lyUL9F3MCIUVBgIMJRWjuNNExkzv98MLyALzE7xZOM=".to_string())];
+        assert_eq!(argon.fetched_to.ranges(), vec![(10, 19), (50, 99)]);
+        assert_eq!(argon.fetched_to.count(), 60);
+        let xenon =
+            &status.log_states[&LogId("b1N2rDHwMRnYmQCkURX/dxUcEdkCwQApBo2yCJo32RM=".to_string())];
+        assert_eq!(xenon.fetched_to.count(), 0);
+    }
+}
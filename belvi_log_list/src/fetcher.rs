@@ -1,10 +1,16 @@
 // SPDX-License-Identifier: Apache-2.0
 use super::{
-    log_data::{GetEntriesItem, LogSth},
-    Log,
+    log_data::{
+        CTParseError, ConsistencyProof, EntryAndProof, GetEntriesItem, LogSth, ProofByHash,
+    },
+    Log, LogList,
 };
+use futures_util::stream;
 use log::{trace, warn};
 use reqwest::StatusCode;
+use std::{io, time::Duration};
+use tokio::task;
+use tokio_util::io::{StreamReader, SyncIoBridge};
 
 #[derive(Debug, Clone)]
 pub struct Fetcher {
@@ -20,10 +26,37 @@ pub enum FetchError {
         serde_error: serde_json::Error,
         input: bytes::Bytes,
     },
+    /// The log sent a get-entries or proof response that couldn't be parsed.
+    Parse(CTParseError),
+    /// The log kept sending no entries when asked for a single entry.
+    MissingEntry,
+    /// The log sent more entries than were requested.
+    TooManyEntries(usize),
+}
+
+/// How many times to ask for a single entry before giving up, since some logs send empty
+/// responses when they're under load.
+const SINGLE_ENTRY_ATTEMPTS: usize = 3;
+
+/// How long `Fetcher::new` waits to connect to a log.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long `Fetcher::new` waits for a whole request, including reading the response. A page of
+/// entries can be a few megabytes, so this leaves room for slow logs.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+impl Default for Fetcher {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Fetcher {
     pub fn new() -> Self {
+        Self::with_timeouts(DEFAULT_CONNECT_TIMEOUT, DEFAULT_REQUEST_TIMEOUT)
+    }
+    /// Requests that take longer than these fail with a `FetchError::Reqwest`, so a log that stops
+    /// responding can't hold up a fetch forever.
+    pub fn with_timeouts(connect: Duration, request: Duration) -> Self {
         let mut headers = reqwest::header::HeaderMap::new();
         headers.insert(
             "From",
@@ -36,6 +69,8 @@ impl Fetcher {
                 .brotli(true)
                 .gzip(true)
                 .https_only(true)
+                .connect_timeout(connect)
+                .timeout(request)
                 .build()
                 .unwrap(),
         }
@@ -56,6 +91,23 @@ impl Fetcher {
             }),
         }
     }
+    /// Fetches a log list in the same format as Google's (version 3).
+    pub async fn fetch_log_list(&self, url: &str) -> Result<LogList, FetchError> {
+        let res = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(FetchError::Reqwest)?;
+        if res.status() != StatusCode::OK {
+            return Err(FetchError::BadStatus);
+        }
+        let bytes = res.bytes().await.map_err(FetchError::Reqwest)?;
+        serde_json::from_slice(&bytes).map_err(|serde_error| FetchError::DeserializeError {
+            serde_error,
+            input: bytes,
+        })
+    }
     pub async fn fetch_entries(
         &self,
         log: &Log,
@@ -81,7 +133,136 @@ impl Fetcher {
             );
             Err(FetchError::BadStatus)
         } else {
-            Ok(GetEntriesItem::parse(&resp.text().await.map_err(FetchError::Reqwest)?).unwrap())
+            // responses can be many megabytes, so they're parsed as they're downloaded instead of
+            // being buffered
+            let body = Box::pin(stream::try_unfold(resp, |mut resp| async move {
+                let chunk = resp.chunk().await.map_err(io::Error::other)?;
+                Ok::<_, io::Error>(chunk.map(|chunk| (chunk, resp)))
+            }));
+            let reader = io::BufReader::new(SyncIoBridge::new(StreamReader::new(body)));
+            task::spawn_blocking(move || GetEntriesItem::parse_reader(reader))
+                .await
+                .expect("get-entries parser panicked")
+                .map_err(FetchError::Parse)
+        }
+    }
+    /// Gets a small JSON response, such as a proof, and parses it with `parse`.
+    async fn fetch_parsed<T>(
+        &self,
+        url: String,
+        parse: impl FnOnce(&[u8]) -> Result<T, CTParseError>,
+    ) -> Result<T, FetchError> {
+        let res = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(FetchError::Reqwest)?;
+        if res.status() != StatusCode::OK {
+            return Err(FetchError::BadStatus);
+        }
+        let bytes = res.bytes().await.map_err(FetchError::Reqwest)?;
+        match parse(&bytes) {
+            Ok(v) => Ok(v),
+            Err(CTParseError::JsonError(serde_error)) => Err(FetchError::DeserializeError {
+                serde_error,
+                input: bytes,
+            }),
+            Err(err) => Err(FetchError::Parse(err)),
+        }
+    }
+    /// Fetches the proof that the tree of size `second` contains the tree of size `first`.
+    pub async fn fetch_consistency(
+        &self,
+        log: &Log,
+        first: u64,
+        second: u64,
+    ) -> Result<ConsistencyProof, FetchError> {
+        self.fetch_parsed(
+            log.get_sth_consistency_url(first, second),
+            ConsistencyProof::parse,
+        )
+        .await
+    }
+    /// Fetches the index and audit path of the entry with `merkle_leaf_hash` in the tree of size
+    /// `tree_size`.
+    pub async fn fetch_proof_by_hash(
+        &self,
+        log: &Log,
+        merkle_leaf_hash: &[u8; 32],
+        tree_size: u64,
+    ) -> Result<ProofByHash, FetchError> {
+        self.fetch_parsed(
+            log.get_proof_by_hash_url(merkle_leaf_hash, tree_size),
+            ProofByHash::parse,
+        )
+        .await
+    }
+    /// Fetches the entry at `index` along with its audit path in the tree of size `tree_size`.
+    pub async fn fetch_entry_and_proof(
+        &self,
+        log: &Log,
+        index: u64,
+        tree_size: u64,
+    ) -> Result<EntryAndProof, FetchError> {
+        self.fetch_parsed(
+            log.get_entry_and_proof_url(index, tree_size),
+            EntryAndProof::parse,
+        )
+        .await
+    }
+    /// Asks the log for the index of the entry with `merkle_leaf_hash` in the tree of size
+    /// `tree_size`, using get-proof-by-hash. If the same leaf was added more than once, logs
+    /// may return any of its indices.
+    pub async fn fetch_leaf_index(
+        &self,
+        log: &Log,
+        merkle_leaf_hash: &[u8; 32],
+        tree_size: u64,
+    ) -> Result<u64, FetchError> {
+        Ok(self
+            .fetch_proof_by_hash(log, merkle_leaf_hash, tree_size)
+            .await?
+            .leaf_index)
+    }
+    /// Fetches the entry at `index`, retrying if the log sends back no entries.
+    pub async fn fetch_entries_exact(
+        &self,
+        log: &Log,
+        index: u64,
+    ) -> Result<GetEntriesItem, FetchError> {
+        for _ in 0..SINGLE_ENTRY_ATTEMPTS {
+            let mut entries = self.fetch_entries(log, index, index).await?;
+            match entries.len() {
+                0 => warn!(
+                    "got no entries when fetching {} from \"{}\"",
+                    index, log.description
+                ),
+                1 => return Ok(entries.remove(0)),
+                len => return Err(FetchError::TooManyEntries(len)),
+            }
+        }
+        Err(FetchError::MissingEntry)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn request_timeout() {
+        // connections are accepted by the OS, but nothing ever responds
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut log = LogList::google().logs().next().unwrap().clone();
+        log.url = format!("https://{}/", listener.local_addr().unwrap());
+        let fetcher = Fetcher::with_timeouts(Duration::from_secs(5), Duration::from_millis(200));
+        let start = Instant::now();
+        match fetcher.fetch_sth(&log).await {
+            Err(FetchError::Reqwest(err)) => assert!(err.is_timeout(), "{:?}", err),
+            other => panic!("expected a timeout, got {:?}", other),
         }
+        assert!(start.elapsed() < Duration::from_secs(5));
     }
 }
@@ -1,7 +1,9 @@
 // SPDX-License-Identifier: Apache-2.0
 use chrono::{DateTime, Duration, FixedOffset, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
+pub mod fetch_status;
 pub mod fetcher;
 pub mod log_data;
 #[cfg(test)]
@@ -12,6 +14,10 @@ mod log_list_test;
 
 type TreeSize = u64;
 
+lazy_static::lazy_static! {
+    static ref GOOGLE: LogList = LogList::google();
+}
+
 #[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct LogId(pub String);
 
@@ -70,6 +76,29 @@ pub struct TemporalInterval {
     pub end_exclusive: String,
 }
 
+impl TemporalInterval {
+    /// Certs that expire at or after this time can be logged.
+    pub fn start(&self) -> Result<DateTime<Utc>, chrono::ParseError> {
+        DateTime::parse_from_rfc3339(&self.start_inclusive).map(|time| time.with_timezone(&Utc))
+    }
+
+    /// Certs that expire before this time can be logged.
+    pub fn end(&self) -> Result<DateTime<Utc>, chrono::ParseError> {
+        DateTime::parse_from_rfc3339(&self.end_exclusive).map(|time| time.with_timezone(&Utc))
+    }
+}
+
+/// A problem with a log in a log list, found by [`LogList::validate`].
+#[derive(Debug)]
+pub enum LogListError {
+    InvalidTime {
+        log: String,
+        error: chrono::ParseError,
+    },
+    /// The temporal interval doesn't have any times in it.
+    EmptyInterval { log: String },
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TreeHead {
     pub sha256_root_hash: String,
@@ -104,14 +133,19 @@ impl Log {
         format!("{}ct/v1/get-entries?start={}&end={}", self.url, start, end)
     }
     #[must_use]
-    pub fn get_proof_by_hash_url(&self, hash: String, tree_size: TreeSize) -> String {
+    pub fn get_proof_by_hash_url(&self, hash: &[u8], tree_size: TreeSize) -> String {
+        // base64 can contain characters that need to be escaped in URLs
+        let hash = base64::encode(hash)
+            .replace('+', "%2B")
+            .replace('/', "%2F")
+            .replace('=', "%3D");
         format!(
             "{}ct/v1/get-proof-by-hash?hash={}&tree_size={}",
             self.url, hash, tree_size
         )
     }
     #[must_use]
-    pub fn get_entry_and_proof_url(&self, leaf_index: u32, tree_size: TreeSize) -> String {
+    pub fn get_entry_and_proof_url(&self, leaf_index: u64, tree_size: TreeSize) -> String {
         format!(
             "{}ct/v1/get-entry-and-proof?leaf_index={}&tree_size={}",
             self.url, leaf_index, tree_size
@@ -137,17 +171,11 @@ impl Log {
             oldest_certs_expiration.timestamp() > now
         }
 
-        if let Some(TemporalInterval {
-            start_inclusive: _,
-            end_exclusive,
-        }) = &self.temporal_interval
-        {
+        if let Some(interval) = &self.temporal_interval {
             if matches!(self.state, LogState::Retired { .. }) {
                 false
             } else {
-                let end_exclusive =
-                    DateTime::parse_from_rfc3339(end_exclusive).expect("invalid log data");
-                now < end_exclusive
+                now < interval.end().expect("invalid log data")
             }
         } else {
             match self.state {
@@ -165,6 +193,47 @@ impl Log {
         }
     }
 
+    /// Checks that all of the times in the log's data are valid.
+    pub fn validate(&self) -> Result<(), LogListError> {
+        let invalid_time = |error| LogListError::InvalidTime {
+            log: self.description.clone(),
+            error,
+        };
+        let state_timestamp = match &self.state {
+            LogState::Usable { timestamp }
+            | LogState::Retired { timestamp }
+            | LogState::ReadOnly { timestamp, .. } => timestamp,
+        };
+        DateTime::parse_from_rfc3339(state_timestamp).map_err(invalid_time)?;
+        if let Some(interval) = &self.temporal_interval {
+            let start = interval.start().map_err(invalid_time)?;
+            let end = interval.end().map_err(invalid_time)?;
+            if start >= end {
+                return Err(LogListError::EmptyInterval {
+                    log: self.description.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The longest time the log may take to add a cert to its tree after issuing an SCT for it.
+    #[must_use]
+    pub fn max_merge_delay(&self) -> Duration {
+        Duration::seconds(self.mmd.into())
+    }
+
+    /// Can the entry at `idx` be fetched? Read-only logs only serve entries in their final tree.
+    #[must_use]
+    pub fn has_readable_index(&self, idx: u64) -> bool {
+        match &self.state {
+            LogState::ReadOnly {
+                final_tree_head, ..
+            } => idx < final_tree_head.tree_size,
+            _ => true,
+        }
+    }
+
     #[must_use]
     pub fn readable(&self) -> bool {
         matches!(
@@ -180,8 +249,26 @@ impl LogList {
         serde_json::from_str(include_str!("../log_list.json")).unwrap()
     }
 
+    /// Like [`LogList::google`], but parsed once and shared, for looking up logs by ID.
+    #[must_use]
+    pub fn google_shared() -> &'static Self {
+        &GOOGLE
+    }
+
+    /// Checks that all of the times in the list are valid, so they can be used without errors.
+    pub fn validate(&self) -> Result<(), LogListError> {
+        self.logs().try_for_each(Log::validate)
+    }
+
     /// Returns an iterator of all logs run by all log operators.
     pub fn logs(&self) -> impl Iterator<Item = &Log> + Clone {
         self.operators.iter().flat_map(|op| op.logs.iter())
     }
+
+    /// Each log's description, keyed by its [`LogId::num`].
+    pub fn descriptions(&self) -> HashMap<u32, &str> {
+        self.logs()
+            .map(|log| (LogId(log.log_id.clone()).num(), log.description.as_str()))
+            .collect()
+    }
 }
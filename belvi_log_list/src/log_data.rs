@@ -1,7 +1,9 @@
 // SPDX-License-Identifier: Apache-2.0
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::cmp;
+use serde::{
+    de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserialize, Serialize,
+};
+use std::{cmp, fmt, io};
 
 #[cfg(test)]
 mod test;
@@ -16,7 +18,7 @@ pub struct LogSth {
 
 impl PartialOrd for LogSth {
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        self.tree_size.partial_cmp(&other.tree_size)
+        Some(self.cmp(other))
     }
 }
 
@@ -28,15 +30,15 @@ impl Ord for LogSth {
 
 #[derive(Debug)]
 pub enum CTParseError {
-    GetEntriesRootNotObject,
-    GetEntriesNoEntriesArray,
-    GetEntriesEntryNoLeafInput,
-    GetEntriesEntryNoExtraData,
-    GetEntriesEntryNotObject,
     MerkleTreeLeafTooShort,
     MerkleTreeLeafUnknownLeafType,
     TimestampedEntryTooShort,
+    /// The length of a leaf's extensions didn't match how many bytes were left.
+    TimestampedEntryExtensionsWrongLength,
     LogEntryUnknownEntryType,
+    ExtraDataInvalid,
+    /// A node in a Merkle proof wasn't a SHA-256 hash.
+    ProofNodeWrongLength(usize),
     Base64Error(base64::DecodeError),
     JsonError(serde_json::Error),
 }
@@ -48,24 +50,9 @@ pub struct GetEntriesItem {
 }
 
 impl GetEntriesItem {
-    fn from_get_entries_item(item: Value) -> Result<Self, CTParseError> {
-        let mut obj = if let Value::Object(map) = item {
-            map
-        } else {
-            return Err(CTParseError::GetEntriesEntryNotObject);
-        };
-        let extra_data = if let Some(Value::String(extra_data)) = obj.remove("extra_data") {
-            extra_data
-        } else {
-            return Err(CTParseError::GetEntriesEntryNoExtraData);
-        };
-        let extra_data = base64::decode(extra_data).map_err(CTParseError::Base64Error)?;
-        let leaf_input = if let Some(Value::String(leaf_input)) = obj.remove("leaf_input") {
-            leaf_input
-        } else {
-            return Err(CTParseError::GetEntriesEntryNoLeafInput);
-        };
-        let leaf_input = base64::decode(leaf_input).map_err(CTParseError::Base64Error)?;
+    fn from_raw(raw: RawGetEntriesItem) -> Result<Self, CTParseError> {
+        let extra_data = base64::decode(raw.extra_data).map_err(CTParseError::Base64Error)?;
+        let leaf_input = base64::decode(raw.leaf_input).map_err(CTParseError::Base64Error)?;
         let leaf_input = MerkleTreeLeaf::parse(&leaf_input)?;
         Ok(Self {
             extra_data,
@@ -73,22 +60,95 @@ impl GetEntriesItem {
         })
     }
     pub fn parse(entries: &str) -> Result<Vec<Self>, CTParseError> {
-        let json = serde_json::from_str(entries).map_err(CTParseError::JsonError)?;
-        let mut obj = if let Value::Object(map) = json {
-            map
-        } else {
-            return Err(CTParseError::GetEntriesRootNotObject);
-        };
-        let entries = if let Some(Value::Array(entries)) = obj.remove("entries") {
-            entries
-        } else {
-            return Err(CTParseError::GetEntriesNoEntriesArray);
-        };
-        let mut parsed_entries = Vec::with_capacity(entries.len());
-        for entry in entries {
-            parsed_entries.push(Self::from_get_entries_item(entry)?);
+        Self::parse_reader(entries.as_bytes())
+    }
+    /// Parses a get-entries response as it is read. Each entry is decoded as soon as it has been
+    /// read, so only one entry's base64 is held at a time.
+    pub fn parse_reader(reader: impl io::Read) -> Result<Vec<Self>, CTParseError> {
+        let mut entry_error = None;
+        let mut de = serde_json::Deserializer::from_reader(reader);
+        let res = GetEntriesSeed {
+            entry_error: &mut entry_error,
+        }
+        .deserialize(&mut de)
+        .and_then(|entries| de.end().map(|()| entries));
+        match (res, entry_error) {
+            // the JSON error is just a placeholder for the entry's error
+            (_, Some(error)) => Err(error),
+            (Ok(entries), None) => Ok(entries),
+            (Err(error), None) => Err(CTParseError::JsonError(error)),
+        }
+    }
+}
+
+/// A get-entries item before its fields are decoded.
+#[derive(Deserialize)]
+struct RawGetEntriesItem {
+    leaf_input: String,
+    extra_data: String,
+}
+
+/// Deserializes a get-entries response, keeping the first entry that couldn't be decoded's error
+/// in `entry_error`.
+struct GetEntriesSeed<'a> {
+    entry_error: &'a mut Option<CTParseError>,
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for GetEntriesSeed<'a> {
+    type Value = Vec<GetEntriesItem>;
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for GetEntriesSeed<'a> {
+    type Value = Vec<GetEntriesItem>;
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a get-entries response")
+    }
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut entries = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "entries" {
+                entries = Some(map.next_value_seed(EntriesSeed {
+                    entry_error: &mut *self.entry_error,
+                })?);
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        entries.ok_or_else(|| de::Error::missing_field("entries"))
+    }
+}
+
+struct EntriesSeed<'a> {
+    entry_error: &'a mut Option<CTParseError>,
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for EntriesSeed<'a> {
+    type Value = Vec<GetEntriesItem>;
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for EntriesSeed<'a> {
+    type Value = Vec<GetEntriesItem>;
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an array of entries")
+    }
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut entries = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(raw) = seq.next_element::<RawGetEntriesItem>()? {
+            match GetEntriesItem::from_raw(raw) {
+                Ok(entry) => entries.push(entry),
+                Err(error) => {
+                    *self.entry_error = Some(error);
+                    return Err(de::Error::custom("invalid entry"));
+                }
+            }
         }
-        Ok(parsed_entries)
+        Ok(entries)
     }
 }
 
@@ -101,36 +161,94 @@ pub struct TimestampedEntry {
 
 impl TimestampedEntry {
     pub fn parse(v: &[u8]) -> Result<Self, CTParseError> {
-        if v.len() <= 11 {
+        if v.len() < 10 {
             return Err(CTParseError::TimestampedEntryTooShort);
         };
         let timestamp =
             u64::from_be_bytes(v[0..=7].try_into().expect("slice is always right length"));
         let entry_type =
             u16::from_be_bytes(v[8..=9].try_into().expect("slice is always right length"));
-        let log_entry = match entry_type {
-            // just skip the next 3 bytes?
-            0 => LogEntry::X509(v[13..].to_vec()),
+        let rest = &v[10..];
+        let (log_entry, rest) = match entry_type {
+            0 => {
+                let (cert, rest) =
+                    take_u24_prefixed(rest).map_err(|_| CTParseError::TimestampedEntryTooShort)?;
+                (LogEntry::X509(cert.to_vec()), rest)
+            }
             1 => {
-                if v.len() <= 43 {
+                if rest.len() < 32 {
                     return Err(CTParseError::TimestampedEntryTooShort);
                 };
-                assert!(v[v.len() - 1] == 0, "TODO: extensions");
-                assert!(v[v.len() - 2] == 0, "TODO: extensions");
-                LogEntry::Precert {
-                    issuer_key_hash: v[10..=41].try_into().expect("slice is always right length"),
-                    // just skip the next 4 bytes?
-                    tbs_certificate: v[45..].to_vec(),
-                }
+                let (issuer_key_hash, rest) = rest.split_at(32);
+                let (tbs_certificate, rest) =
+                    take_u24_prefixed(rest).map_err(|_| CTParseError::TimestampedEntryTooShort)?;
+                (
+                    LogEntry::Precert {
+                        issuer_key_hash: issuer_key_hash
+                            .try_into()
+                            .expect("slice is always right length"),
+                        tbs_certificate: tbs_certificate.to_vec(),
+                    },
+                    rest,
+                )
             }
             _ => return Err(CTParseError::LogEntryUnknownEntryType),
         };
+        if rest.len() < 2 {
+            return Err(CTParseError::TimestampedEntryTooShort);
+        }
+        let (extensions_len, extensions) = rest.split_at(2);
+        let extensions_len = u16::from_be_bytes(
+            extensions_len
+                .try_into()
+                .expect("slice is always right length"),
+        );
+        if extensions.len() != usize::from(extensions_len) {
+            return Err(CTParseError::TimestampedEntryExtensionsWrongLength);
+        }
         Ok(Self {
             timestamp,
             log_entry,
-            extensions: CtExtensions(vec![]), // TODO: extensions
+            extensions: CtExtensions(extensions.to_vec()),
         })
     }
+
+    /// The inverse of [`TimestampedEntry::parse`].
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut v = self.timestamp.to_be_bytes().to_vec();
+        let cert = match &self.log_entry {
+            LogEntry::X509(cert) => {
+                v.extend(0u16.to_be_bytes());
+                cert
+            }
+            LogEntry::Precert {
+                issuer_key_hash,
+                tbs_certificate,
+            } => {
+                v.extend(1u16.to_be_bytes());
+                v.extend(issuer_key_hash);
+                tbs_certificate
+            }
+        };
+        let len = u32::try_from(cert.len()).expect("certificate too long");
+        v.extend(&len.to_be_bytes()[1..]);
+        v.extend(cert);
+        v.extend(self.extensions.to_bytes());
+        v
+    }
+
+    /// The hash the certificate is stored under in the database and cache.
+    ///
+    /// The parser used to leave the encoded extensions on the end of the certificate, so they are
+    /// hashed along with it to keep the hashes of existing databases. Logs don't use extensions
+    /// yet, so this is almost always the certificate followed by two zero bytes.
+    #[must_use]
+    pub fn db_hash(&self) -> [u8; 16] {
+        let mut v = self.log_entry.inner_cert().clone();
+        v.extend(self.extensions.to_bytes());
+        belvi_hash::db(&v)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -144,6 +262,11 @@ pub enum LogEntry {
 }
 
 impl LogEntry {
+    /// What [`LogEntry::num`] returns for X.509 entries.
+    pub const X509_NUM: u8 = 1;
+    /// What [`LogEntry::num`] returns for precert entries.
+    pub const PRECERT_NUM: u8 = 2;
+
     #[must_use]
     pub fn inner_cert(&self) -> &Vec<u8> {
         match self {
@@ -157,12 +280,49 @@ impl LogEntry {
     #[must_use]
     pub fn num(&self) -> u8 {
         match self {
-            Self::X509(_) => 1,
-            Self::Precert { .. } => 2,
+            Self::X509(_) => Self::X509_NUM,
+            Self::Precert { .. } => Self::PRECERT_NUM,
         }
     }
 }
 
+/// Takes a value with a 24-bit length prefix, as used by the TLS encoding of certificates.
+fn take_u24_prefixed(bytes: &[u8]) -> Result<(&[u8], &[u8]), CTParseError> {
+    if bytes.len() < 3 {
+        return Err(CTParseError::ExtraDataInvalid);
+    }
+    let len = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]) as usize;
+    let rest = &bytes[3..];
+    if rest.len() < len {
+        return Err(CTParseError::ExtraDataInvalid);
+    }
+    Ok(rest.split_at(len))
+}
+
+/// Gets the certificates from the `extra_data` of an entry, in the order they are in the log.
+///
+/// For certificates, this is the chain of issuers. For precertificates, the first item is the
+/// signed precertificate, followed by its chain of issuers.
+pub fn parse_extra_data(is_precert: bool, extra_data: &[u8]) -> Result<Vec<Vec<u8>>, CTParseError> {
+    let mut certs = Vec::new();
+    let mut rest = extra_data;
+    if is_precert {
+        let (precert, after) = take_u24_prefixed(rest)?;
+        certs.push(precert.to_vec());
+        rest = after;
+    }
+    let (mut chain, after) = take_u24_prefixed(rest)?;
+    if !after.is_empty() {
+        return Err(CTParseError::ExtraDataInvalid);
+    }
+    while !chain.is_empty() {
+        let (cert, after) = take_u24_prefixed(chain)?;
+        certs.push(cert.to_vec());
+        chain = after;
+    }
+    Ok(certs)
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MerkleTreeLeaf {
     pub version: u8,
@@ -185,7 +345,106 @@ impl MerkleTreeLeaf {
             timestamped_entry,
         })
     }
+
+    /// Encodes the leaf the same way as in the `leaf_input` it was parsed from.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut v = vec![self.version, 0];
+        v.extend(self.timestamped_entry.to_bytes());
+        v
+    }
+
+    /// The hash of the leaf in the log's Merkle tree, as described in RFC 6962 section 2.1. Logs
+    /// use this to look up entries.
+    #[must_use]
+    pub fn leaf_hash(&self) -> [u8; 32] {
+        belvi_hash::db_full(&[&[0], &self.to_bytes()[..]].concat())
+    }
 }
 
+/// The extensions of a log entry, without their length prefix.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CtExtensions(pub Vec<u8>);
+
+impl CtExtensions {
+    /// Encodes the extensions with their 2-byte length prefix.
+    fn to_bytes(&self) -> Vec<u8> {
+        let len = u16::try_from(self.0.len()).expect("extensions too long");
+        let mut v = len.to_be_bytes().to_vec();
+        v.extend(&self.0);
+        v
+    }
+}
+
+/// Decodes the base64-encoded nodes of a Merkle audit path or consistency proof.
+fn decode_proof_nodes(nodes: &[String]) -> Result<Vec<[u8; 32]>, CTParseError> {
+    nodes
+        .iter()
+        .map(|node| {
+            let node = base64::decode(node).map_err(CTParseError::Base64Error)?;
+            node.as_slice()
+                .try_into()
+                .map_err(|_| CTParseError::ProofNodeWrongLength(node.len()))
+        })
+        .collect()
+}
+
+/// A get-sth-consistency response: the nodes proving that a tree is an extension of an older one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsistencyProof(pub Vec<[u8; 32]>);
+
+impl ConsistencyProof {
+    pub fn parse(v: &[u8]) -> Result<Self, CTParseError> {
+        #[derive(Deserialize)]
+        struct Raw {
+            consistency: Vec<String>,
+        }
+        let raw: Raw = serde_json::from_slice(v).map_err(CTParseError::JsonError)?;
+        Ok(Self(decode_proof_nodes(&raw.consistency)?))
+    }
+}
+
+/// A get-proof-by-hash response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofByHash {
+    pub leaf_index: u64,
+    pub audit_path: Vec<[u8; 32]>,
+}
+
+impl ProofByHash {
+    pub fn parse(v: &[u8]) -> Result<Self, CTParseError> {
+        #[derive(Deserialize)]
+        struct Raw {
+            leaf_index: u64,
+            audit_path: Vec<String>,
+        }
+        let raw: Raw = serde_json::from_slice(v).map_err(CTParseError::JsonError)?;
+        Ok(Self {
+            leaf_index: raw.leaf_index,
+            audit_path: decode_proof_nodes(&raw.audit_path)?,
+        })
+    }
+}
+
+/// A get-entry-and-proof response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryAndProof {
+    pub entry: GetEntriesItem,
+    pub audit_path: Vec<[u8; 32]>,
+}
+
+impl EntryAndProof {
+    pub fn parse(v: &[u8]) -> Result<Self, CTParseError> {
+        #[derive(Deserialize)]
+        struct Raw {
+            #[serde(flatten)]
+            entry: RawGetEntriesItem,
+            audit_path: Vec<String>,
+        }
+        let raw: Raw = serde_json::from_slice(v).map_err(CTParseError::JsonError)?;
+        Ok(Self {
+            entry: GetEntriesItem::from_raw(raw.entry)?,
+            audit_path: decode_proof_nodes(&raw.audit_path)?,
+        })
+    }
+}
@@ -6,3 +6,184 @@ fn argon2021() {
     let data = include_str!("../../test_data/argon2021-get-entries?start=0&end=1.json");
     GetEntriesItem::parse(data).unwrap();
 }
+
+#[test]
+fn argon2021_extra_data() {
+    let data = include_str!("../../test_data/argon2021-get-entries?start=0&end=1.json");
+    for entry in GetEntriesItem::parse(data).unwrap() {
+        let is_precert = matches!(
+            entry.leaf_input.timestamped_entry.log_entry,
+            LogEntry::Precert { .. }
+        );
+        let certs = parse_extra_data(is_precert, &entry.extra_data).unwrap();
+        assert!(!certs.is_empty());
+        for cert in certs {
+            // DER SEQUENCE
+            assert_eq!(cert[0], 0x30);
+        }
+    }
+    assert!(parse_extra_data(false, &[0, 0, 5, 0, 0]).is_err());
+}
+
+/// Gives out a few bytes at a time, like a slow response body.
+struct Trickle<'a>(&'a [u8]);
+
+impl io::Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.0.len()).min(7);
+        buf[..len].copy_from_slice(&self.0[..len]);
+        self.0 = &self.0[len..];
+        Ok(len)
+    }
+}
+
+#[test]
+fn parse_reader() {
+    let data = include_str!("../../test_data/argon2021-get-entries?start=0&end=1.json");
+    assert_eq!(
+        GetEntriesItem::parse_reader(Trickle(data.as_bytes())).unwrap(),
+        GetEntriesItem::parse(data).unwrap(),
+    );
+    assert!(GetEntriesItem::parse(r#"{"entries": []}"#)
+        .unwrap()
+        .is_empty());
+    assert!(matches!(
+        GetEntriesItem::parse(r#"{"entries": [{"leaf_input": "!", "extra_data": ""}]}"#),
+        Err(CTParseError::Base64Error(_)),
+    ));
+    assert!(matches!(
+        GetEntriesItem::parse(r#"{"entries": [{"leaf_input": ""}]}"#),
+        Err(CTParseError::JsonError(_)),
+    ));
+    assert!(matches!(
+        GetEntriesItem::parse(r#"{"other": 1}"#),
+        Err(CTParseError::JsonError(_)),
+    ));
+    assert!(matches!(
+        GetEntriesItem::parse(r#"{"entries": []} trailing"#),
+        Err(CTParseError::JsonError(_)),
+    ));
+}
+
+#[test]
+fn merkle_leaf_hash() {
+    let data = include_str!("../../test_data/argon2021-get-entries?start=0&end=1.json");
+    let raw: serde_json::Value = serde_json::from_str(data).unwrap();
+    let entries = GetEntriesItem::parse(data).unwrap();
+    for (entry, raw) in entries.iter().zip(raw["entries"].as_array().unwrap()) {
+        let mut leaf = vec![0];
+        leaf.extend(base64::decode(raw["leaf_input"].as_str().unwrap()).unwrap());
+        assert_eq!(entry.leaf_input.leaf_hash(), belvi_hash::db_full(&leaf));
+    }
+    assert_ne!(
+        entries[0].leaf_input.leaf_hash(),
+        entries[1].leaf_input.leaf_hash()
+    );
+}
+
+#[test]
+fn leaf_round_trip() {
+    let data = include_str!("../../test_data/argon2021-get-entries?start=0&end=1.json");
+    let raw: serde_json::Value = serde_json::from_str(data).unwrap();
+    for raw in raw["entries"].as_array().unwrap() {
+        let leaf_input = base64::decode(raw["leaf_input"].as_str().unwrap()).unwrap();
+        let leaf = MerkleTreeLeaf::parse(&leaf_input).unwrap();
+        assert_eq!(leaf.to_bytes(), leaf_input);
+        assert_eq!(
+            leaf.timestamped_entry.to_bytes(),
+            leaf_input[2..],
+            "TimestampedEntry round trip"
+        );
+    }
+}
+
+#[test]
+fn leaf_extensions() {
+    let data = include_str!("../../test_data/argon2021-get-entries?start=0&end=1.json");
+    let raw: serde_json::Value = serde_json::from_str(data).unwrap();
+    for raw in raw["entries"].as_array().unwrap() {
+        let leaf_input = base64::decode(raw["leaf_input"].as_str().unwrap()).unwrap();
+        let entry = TimestampedEntry::parse(&leaf_input[2..]).unwrap();
+        assert_eq!(entry.extensions, CtExtensions(vec![]));
+        // the certificate is a whole DER SEQUENCE, without the extensions after it
+        let cert = entry.log_entry.inner_cert();
+        assert_eq!(cert[0], 0x30);
+        assert!(leaf_input.ends_with(&[&cert[..], &[0, 0]].concat()));
+        // the same as hashing everything after the certificate's length, as stored hashes were
+        let cert_start = leaf_input.len() - cert.len() - 2;
+        assert_eq!(entry.db_hash(), belvi_hash::db(&leaf_input[cert_start..]));
+
+        let mut with_extensions = entry.clone();
+        with_extensions.extensions = CtExtensions(vec![1, 2, 3]);
+        let bytes = with_extensions.to_bytes();
+        assert!(bytes.ends_with(&[0, 3, 1, 2, 3]));
+        assert_eq!(TimestampedEntry::parse(&bytes).unwrap(), with_extensions);
+        assert!(matches!(
+            TimestampedEntry::parse(&bytes[..bytes.len() - 1]),
+            Err(CTParseError::TimestampedEntryExtensionsWrongLength),
+        ));
+        assert!(matches!(
+            TimestampedEntry::parse(&leaf_input[2..leaf_input.len() - 10]),
+            Err(CTParseError::TimestampedEntryTooShort),
+        ));
+    }
+}
+
+#[test]
+fn empty_extra_data() {
+    let data = include_str!("../../test_data/argon2021-get-entries?start=0&end=1.json");
+    let raw: serde_json::Value = serde_json::from_str(data).unwrap();
+    let leaf_input = raw["entries"][0]["leaf_input"].as_str().unwrap();
+    let entries = GetEntriesItem::parse(&format!(
+        r#"{{"entries": [{{"leaf_input": "{leaf_input}", "extra_data": ""}}]}}"#
+    ))
+    .unwrap();
+    assert_eq!(entries.len(), 1);
+    assert!(entries[0].extra_data.is_empty());
+    // a missing field is still an error
+    assert!(matches!(
+        GetEntriesItem::parse(&format!(
+            r#"{{"entries": [{{"leaf_input": "{leaf_input}"}}]}}"#
+        )),
+        Err(CTParseError::JsonError(_)),
+    ));
+}
+
+#[test]
+fn proofs() {
+    let node = base64::encode([7; 32]);
+    let proof =
+        ConsistencyProof::parse(format!(r#"{{"consistency": ["{node}", "{node}"]}}"#).as_bytes())
+            .unwrap();
+    assert_eq!(proof, ConsistencyProof(vec![[7; 32], [7; 32]]));
+    assert_eq!(
+        ConsistencyProof::parse(br#"{"consistency": []}"#).unwrap(),
+        ConsistencyProof(vec![])
+    );
+
+    let proof =
+        ProofByHash::parse(format!(r#"{{"leaf_index": 12, "audit_path": ["{node}"]}}"#).as_bytes())
+            .unwrap();
+    assert_eq!(proof.leaf_index, 12);
+    assert_eq!(proof.audit_path, vec![[7; 32]]);
+    assert!(matches!(
+        ProofByHash::parse(br#"{"leaf_index": 12, "audit_path": ["AAAA"]}"#),
+        Err(CTParseError::ProofNodeWrongLength(3)),
+    ));
+    assert!(matches!(
+        ProofByHash::parse(br#"{"leaf_index": 12, "audit_path": ["!"]}"#),
+        Err(CTParseError::Base64Error(_)),
+    ));
+    assert!(matches!(
+        ProofByHash::parse(br#"{"audit_path": []}"#),
+        Err(CTParseError::JsonError(_)),
+    ));
+
+    let data = include_str!("../../test_data/argon2021-get-entries?start=0&end=1.json");
+    let raw: serde_json::Value = serde_json::from_str(data).unwrap();
+    let mut raw_entry = raw["entries"][0].clone();
+    raw_entry["audit_path"] = serde_json::json!([node]);
+    let proof = EntryAndProof::parse(raw_entry.to_string().as_bytes()).unwrap();
+    assert_eq!(proof.entry, GetEntriesItem::parse(data).unwrap()[0]);
+    assert_eq!(proof.audit_path, vec![[7; 32]]);
+}
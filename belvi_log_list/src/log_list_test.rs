@@ -6,3 +6,17 @@ fn parse_list() {
     let log_list = serde_json::from_str::<LogList>(include_str!("../log_list.json")).unwrap();
     assert_eq!(log_list.operators[0].name, "Google".to_string());
 }
+
+#[test]
+fn google_list_is_valid() {
+    LogList::google().validate().unwrap();
+}
+
+#[test]
+fn descriptions() {
+    let log_list = LogList::google();
+    let descriptions = log_list.descriptions();
+    assert_eq!(descriptions.len(), log_list.logs().count());
+    let argon = LogId("KXm+8J45OSHwVnOfY6V35b5XfZxgCvj5TV0mXCVdx4Q=".to_string());
+    assert_eq!(descriptions[&argon.num()], "Google 'Argon2022' log");
+}
@@ -4,7 +4,7 @@ use chrono::TimeZone;
 
 fn validities(log: &Log) -> [bool; 13] {
     fn jan1(year: i32) -> DateTime<Utc> {
-        chrono::Utc.ymd(year, 01, 01).and_hms(00, 00, 00)
+        chrono::Utc.ymd(year, 1, 1).and_hms(0, 0, 0)
     }
     [
         log.has_active_certs(jan1(2015)),
@@ -71,6 +71,7 @@ fn argon2021() {
         validities(&log),
         [true, true, true, true, true, true, true, false, false, false, false, false, false],
     );
+    assert_eq!(log.max_merge_delay(), Duration::days(1));
 }
 
 #[test]
@@ -113,6 +114,8 @@ fn aviator() {
         validities(&log),
         [true, true, true, true, true, false, false, false, false, false, false, false, false],
     );
+    assert!(log.has_readable_index(46466471));
+    assert!(!log.has_readable_index(46466472));
 }
 
 #[test]
@@ -141,3 +144,64 @@ fn nimbus2022() {
         [true, true, true, true, true, true, true, true, false, false, false, false, false],
     );
 }
+
+#[test]
+fn validate() {
+    let mut log = serde_json::from_str::<Log>(
+        r#"
+        {
+            "description": "Test log",
+            "log_id": "9lyUL9F3MCIUVBgIMJRWjuNNExkzv98MLyALzE7xZOM=",
+            "key": "",
+            "url": "https://example.com/",
+            "mmd": 86400,
+            "state": {
+                "usable": {
+                    "timestamp": "2018-06-15T02:30:13Z"
+                }
+            },
+            "temporal_interval": {
+                "start_inclusive": "2021-01-01T00:00:00Z",
+                "end_exclusive": "2022-01-01T00:00:00Z"
+            }
+        }
+    "#,
+    )
+    .unwrap();
+    log.validate().unwrap();
+    let interval = log.temporal_interval.as_ref().unwrap();
+    assert_eq!(
+        interval.start().unwrap(),
+        Utc.ymd(2021, 1, 1).and_hms(0, 0, 0)
+    );
+    assert_eq!(
+        interval.end().unwrap(),
+        Utc.ymd(2022, 1, 1).and_hms(0, 0, 0)
+    );
+
+    log.temporal_interval.as_mut().unwrap().end_exclusive = "2021-01-01T00:00:00Z".to_string();
+    assert!(matches!(
+        log.validate(),
+        Err(LogListError::EmptyInterval { .. })
+    ));
+    log.temporal_interval.as_mut().unwrap().end_exclusive = "next year".to_string();
+    assert!(matches!(
+        log.validate(),
+        Err(LogListError::InvalidTime { .. })
+    ));
+}
+
+#[test]
+fn proof_by_hash_url() {
+    let log = LogList::google().logs().next().unwrap().clone();
+    assert_eq!(
+        log.get_proof_by_hash_url(&[0xfb, 0xff, 0xfe], 10),
+        format!(
+            "{}ct/v1/get-proof-by-hash?hash=%2B%2F%2F%2B&tree_size=10",
+            log.url
+        )
+    );
+    assert!(log
+        .get_proof_by_hash_url(&[0; 32], 10)
+        .contains("?hash=AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA%3D&"));
+}
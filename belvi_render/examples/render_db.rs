@@ -31,7 +31,7 @@ async fn main() {
     let total = keys.len();
     for (idx, key) in keys.into_iter().enumerate() {
         let cert = conn.get_cert(&key[2..]).await.unwrap();
-        if let Err(_) = catch_unwind(|| check(cert)) {
+        if catch_unwind(|| check(cert)).is_err() {
             panic!("Failed with cert {}", hex::encode(&key[2..]));
         };
         if idx % 1000 == 0 {
@@ -1,11 +1,11 @@
 // SPDX-License-Identifier: Apache-2.0
-use super::{render_array, Render};
+use super::{render_array, Format, Render};
 
 macro_rules! render_vec_wrapper {
     ($t:path) => {
         impl Render for $t {
-            fn render(&self) -> String {
-                render_array(self.iter().map(Render::render))
+            fn render_as(&self, format: Format) -> String {
+                render_array(self.iter().map(|val| val.render_as(format)), format)
             }
         }
     };
@@ -15,7 +15,7 @@ render_vec_wrapper!(x509_certificate::rfc3280::RdnSequence);
 render_vec_wrapper!(x509_certificate::rfc3280::RelativeDistinguishedName);
 
 impl Render for x509_certificate::rfc3280::Name {
-    fn render(&self) -> String {
-        (**self).render()
+    fn render_as(&self, format: Format) -> String {
+        (**self).render_as(format)
     }
 }
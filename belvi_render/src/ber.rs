@@ -3,18 +3,24 @@
 use bcder::{decode::Constructed, decode::Content, Mode};
 use log::trace;
 
-use super::{html_escape::HtmlEscapable, render_array, Render};
+use super::{render_array, Format, Render};
 
-fn take_cons(cons: &mut Constructed<bytes::Bytes>) -> Result<String, bcder::decode::Error> {
+pub(crate) fn take_cons(
+    cons: &mut Constructed<bytes::Bytes>,
+    format: Format,
+) -> Result<String, bcder::decode::Error> {
     if let Ok(()) = cons.take_null() {
-        return Ok(r#"<span class="bvcert-null">NULL</span>"#.to_string());
+        return Ok(match format {
+            Format::Html => r#"<span class="bvcert-null">NULL</span>"#.to_string(),
+            Format::Text => "NULL".to_string(),
+        });
     }
 
     macro_rules! forward_to_render {
         ($($($thing:ident)::+),+,) => {
             $(
                 if let Ok(thing) = $($thing ::)+take_from(cons) {
-                    return Ok(thing.render());
+                    return Ok(thing.render_as(format));
                 }
             )+
         };
@@ -32,16 +38,20 @@ fn take_cons(cons: &mut Constructed<bytes::Bytes>) -> Result<String, bcder::deco
         x509_certificate::asn1time::UtcTime,
     ];
 
+    if let Some(string) = belvi_cert::take_legacy_string(cons)? {
+        return Ok(format.escape(string));
+    }
+
     if let Ok(thing) =
         x509_certificate::asn1time::GeneralizedTime::take_from_allow_fractional_z(cons)
     {
-        return Ok(thing.render());
+        return Ok(thing.render_as(format));
     }
 
     if let Ok(s) = cons.take_sequence(|subcons| {
         let mut table = Vec::new();
         loop {
-            match take_cons(subcons) {
+            match take_cons(subcons, format) {
                 Ok(val) => table.push(val),
                 Err(bcder::decode::Error::Malformed) => break,
                 Err(bcder::decode::Error::Unimplemented) => {
@@ -50,7 +60,7 @@ fn take_cons(cons: &mut Constructed<bytes::Bytes>) -> Result<String, bcder::deco
                 }
             }
         }
-        Ok(render_array(table.into_iter()))
+        Ok(render_array(table.into_iter(), format))
     }) {
         return Ok(s);
     }
@@ -61,8 +71,8 @@ fn take_cons(cons: &mut Constructed<bytes::Bytes>) -> Result<String, bcder::deco
                 Content::Primitive(prim) => {
                     let bytes = prim.take_all()?;
                     Ok(match String::from_utf8(bytes.to_vec()) {
-                        Ok(str) => str.html_escape(),
-                        Err(_) => bytes.render(),
+                        Ok(str) => format.escape(str),
+                        Err(_) => bytes.render_as(format),
                     })
                 }
                 Content::Constructed(_) => Err(bcder::decode::Error::Unimplemented), // TODO
@@ -77,23 +87,21 @@ fn take_cons(cons: &mut Constructed<bytes::Bytes>) -> Result<String, bcder::deco
     })
 }
 
-pub fn render_ber(bytes: bytes::Bytes) -> String {
+pub fn render_ber(bytes: bytes::Bytes, format: Format) -> String {
     let orig_bytes = bytes.clone();
     trace!("rendering ber {:x}", bytes);
-    if let Ok(text) = Constructed::decode(bytes, Mode::Ber, take_cons) {
+    if let Ok(text) = Constructed::decode(bytes, Mode::Ber, |cons| take_cons(cons, format)) {
         text
     } else {
-        format!("Unparsed DER: {}", orig_bytes.render())
+        format!("Unparsed DER: {}", orig_bytes.render_as(format))
     }
 }
 
 macro_rules! string_type {
     ($str:ident) => {
         impl Render for bcder::$str {
-            fn render(&self) -> String {
-                String::from_utf8(self.to_bytes().to_vec())
-                    .unwrap()
-                    .html_escape()
+            fn render_as(&self, format: Format) -> String {
+                format.escape(String::from_utf8(self.to_bytes().to_vec()).unwrap())
             }
         }
     };
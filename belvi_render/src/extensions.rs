@@ -1,34 +1,461 @@
 // SPDX-License-Identifier: Apache-2.0
-use super::{ber::render_ber, render_kv_table, Render};
+use super::{
+    ber::{render_ber, take_cons},
+    html_escape::HtmlEscapable,
+    oid::render_named_oid,
+    render_array, render_kv_table,
+    sct::take_sct_list,
+    Format, Render,
+};
 
+use bcder::{decode::Constructed, Ia5String, Mode, OctetString, Oid, Tag};
+use belvi_cert::SubjectAltName;
 use x509_certificate::rfc5280::{Extension, Extensions};
 
+/// 1.3.6.1.5.5.7.1.1
+const OID_AUTHORITY_INFO_ACCESS: &[u8] = &[43, 6, 1, 5, 5, 7, 1, 1];
+/// 2.5.29.17
+const OID_SUBJECT_ALT_NAME: &[u8] = &[85, 29, 17];
+/// 2.5.29.14
+const OID_SUBJECT_KEY_IDENTIFIER: &[u8] = &[85, 29, 14];
+/// 2.5.29.35
+const OID_AUTHORITY_KEY_IDENTIFIER: &[u8] = &[85, 29, 35];
+/// 2.5.29.32
+const OID_CERTIFICATE_POLICIES: &[u8] = &[85, 29, 32];
+/// 1.3.6.1.4.1.11129.2.4.2
+const OID_EMBEDDED_SCTS: &[u8] = &[43, 6, 1, 4, 1, 214, 121, 2, 4, 2];
+/// 1.3.6.1.4.1.11129.2.4.3
+const OID_CT_POISON: &[u8] = &[43, 6, 1, 4, 1, 214, 121, 2, 4, 3];
+/// 1.3.6.1.5.5.7.2.1
+const OID_QUALIFIER_CPS: &[u8] = &[43, 6, 1, 5, 5, 7, 2, 1];
+
+/// Names for common extensions, which read better as table keys than the names in the OID table.
+const EXTENSION_NAMES: &[(&[u8], &str)] = &[
+    (OID_SUBJECT_KEY_IDENTIFIER, "Subject Key Identifier"),
+    // 2.5.29.15
+    (&[85, 29, 15], "Key Usage"),
+    (OID_SUBJECT_ALT_NAME, "Subject Alternative Name"),
+    // 2.5.29.18
+    (&[85, 29, 18], "Issuer Alternative Name"),
+    // 2.5.29.19
+    (&[85, 29, 19], "Basic Constraints"),
+    // 2.5.29.30
+    (&[85, 29, 30], "Name Constraints"),
+    // 2.5.29.31
+    (&[85, 29, 31], "CRL Distribution Points"),
+    (OID_CERTIFICATE_POLICIES, "Certificate Policies"),
+    (OID_AUTHORITY_KEY_IDENTIFIER, "Authority Key Identifier"),
+    // 2.5.29.36
+    (&[85, 29, 36], "Policy Constraints"),
+    // 2.5.29.37
+    (&[85, 29, 37], "Extended Key Usage"),
+    (OID_AUTHORITY_INFO_ACCESS, "Authority Information Access"),
+    // 1.3.6.1.5.5.7.1.24
+    (&[43, 6, 1, 5, 5, 7, 1, 24], "TLS Feature"),
+    (OID_EMBEDDED_SCTS, "Embedded SCTs"),
+    (OID_CT_POISON, "Precertificate Poison"),
+];
+
+/// Renders an extension's OID with its name from [`EXTENSION_NAMES`], falling back to the OID table.
+fn render_extension_id(id: &Oid, format: Format) -> String {
+    match EXTENSION_NAMES.iter().find(|(oid, _)| *oid == id.as_ref()) {
+        Some((_, name)) => render_named_oid(id, Some(name), format),
+        None => id.render_as(format),
+    }
+}
+
+/// CA/Browser Forum policy OIDs that indicate how the subject was validated.
+const VALIDATION_POLICIES: &[(&[u8], &str)] = &[
+    // 2.23.140.1.1
+    (&[103, 129, 12, 1, 1], "Extended validation (EV)"),
+    // 2.23.140.1.2.1
+    (&[103, 129, 12, 1, 2, 1], "Domain validated (DV)"),
+    // 2.23.140.1.2.2
+    (&[103, 129, 12, 1, 2, 2], "Organization validated (OV)"),
+    // 2.23.140.1.2.3
+    (&[103, 129, 12, 1, 2, 3], "Individual validated (IV)"),
+];
+
 impl Render for Extensions {
-    fn render(&self) -> String {
+    fn render_as(&self, format: Format) -> String {
         let table = self.iter().map(|ext| {
-            let key = format!(
-                r#"<span class="bvcert-{}">{}{}</span>"#,
-                if ext.critical == Some(true) {
-                    "critical"
-                } else {
-                    "noncritical"
-                },
-                ext.id.render(),
-                if ext.critical == Some(true) {
-                    " (critical)"
-                } else {
-                    ""
+            let critical = ext.critical == Some(true);
+            let id = render_extension_id(&ext.id, format);
+            let key = match (format, critical) {
+                (Format::Html, true) => {
+                    format!(r#"<span class="bvcert-critical">{} (critical)</span>"#, id)
+                }
+                (Format::Html, false) => {
+                    format!(r#"<span class="bvcert-noncritical">{}</span>"#, id)
                 }
-            );
-            (key, ext.render())
+                (Format::Text, true) => format!("{} (critical)", id),
+                (Format::Text, false) => id,
+            };
+            (key, ext.render_as(format))
         });
-        render_kv_table(table)
+        render_kv_table(table, format)
     }
 }
 
 impl Render for Extension {
-    fn render(&self) -> String {
-        // TODO: recognize common extensions
-        render_ber(self.value.to_bytes())
+    fn render_as(&self, format: Format) -> String {
+        let bytes = self.value.to_bytes();
+        let take_value = match self.id.as_ref() {
+            OID_SUBJECT_ALT_NAME => take_subject_alt_names,
+            OID_AUTHORITY_INFO_ACCESS => take_authority_info_access,
+            OID_SUBJECT_KEY_IDENTIFIER => take_subject_key_identifier,
+            OID_AUTHORITY_KEY_IDENTIFIER => take_authority_key_identifier,
+            OID_CERTIFICATE_POLICIES => take_certificate_policies,
+            OID_EMBEDDED_SCTS => take_sct_list,
+            OID_CT_POISON => take_ct_poison,
+            _ => return render_ber(bytes, format),
+        };
+        Constructed::decode(bytes.clone(), Mode::Der, |cons| take_value(cons, format))
+            .unwrap_or_else(|_| render_ber(bytes, format))
+    }
+}
+
+/// Renders a URI as a link if it is HTTP(S), so other schemes (like `javascript:`) can't be used.
+fn render_uri(uri: &str, format: Format) -> String {
+    if format == Format::Text {
+        format.escape(uri)
+    } else if uri.starts_with("http://") || uri.starts_with("https://") {
+        format!(
+            r#"<a class="bvcert-uri" href="{uri}" rel="nofollow noreferrer">{uri}</a>"#,
+            uri = uri.html_escape()
+        )
+    } else {
+        format!(r#"<span class="bvcert-uri">{}</span>"#, uri.html_escape())
+    }
+}
+
+/// Takes a `GeneralName` that is a URI. Other types of names are rare in the extensions that use
+/// this, so they are left unimplemented.
+fn take_uri(
+    cons: &mut Constructed<bytes::Bytes>,
+    format: Format,
+) -> Result<String, bcder::decode::Error> {
+    cons.take_value_if(Tag::CTX_6, |content| {
+        let bytes = content.as_primitive()?.take_all()?;
+        let uri = std::str::from_utf8(&bytes).map_err(|_| bcder::decode::Error::Malformed)?;
+        Ok(render_uri(uri, format))
+    })
+}
+
+/// RFC 5280, section 4.2.1.6. Types of names that aren't supported are left out.
+fn take_subject_alt_names(
+    cons: &mut Constructed<bytes::Bytes>,
+    format: Format,
+) -> Result<String, bcder::decode::Error> {
+    let names = belvi_cert::take_subject_alt_names(cons)?;
+    Ok(render_kv_table(
+        names.iter().map(|name| {
+            let lossy = |bytes: &[u8]| format.escape(String::from_utf8_lossy(bytes));
+            match name {
+                SubjectAltName::Dns(name) => ("DNS".to_string(), lossy(name)),
+                SubjectAltName::Email(name) => ("Email".to_string(), lossy(name)),
+                SubjectAltName::Uri(uri) => (
+                    "URI".to_string(),
+                    render_uri(&String::from_utf8_lossy(uri), format),
+                ),
+                SubjectAltName::IpAddress(ip) => ("IP address".to_string(), format.escape(ip)),
+                SubjectAltName::OtherName { type_id, value } => {
+                    (type_id.render_as(format), lossy(value))
+                }
+                SubjectAltName::DirectoryName(name) => {
+                    ("Directory name".to_string(), format.escape(name))
+                }
+            }
+        }),
+        format,
+    ))
+}
+
+/// RFC 5280, section 4.2.2.1
+fn take_authority_info_access(
+    cons: &mut Constructed<bytes::Bytes>,
+    format: Format,
+) -> Result<String, bcder::decode::Error> {
+    cons.take_sequence(|cons| {
+        let mut table = Vec::new();
+        while let Some(row) = cons.take_opt_sequence(|cons| {
+            let method = Oid::take_from(cons)?;
+            let location = take_uri(cons, format)?;
+            Ok((method.render_as(format), location))
+        })? {
+            table.push(row);
+        }
+        Ok(render_kv_table(table.into_iter(), format))
+    })
+}
+
+/// Renders bytes as colon-separated hex, the way key identifiers are usually shown.
+fn render_colon_hex(bytes: &[u8], format: Format) -> String {
+    let hex: Vec<_> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    match format {
+        Format::Html => format!(r#"<code class="bvcert-key-id">{}</code>"#, hex.join(":")),
+        Format::Text => hex.join(":"),
+    }
+}
+
+/// RFC 5280, section 4.2.1.2
+fn take_subject_key_identifier(
+    cons: &mut Constructed<bytes::Bytes>,
+    format: Format,
+) -> Result<String, bcder::decode::Error> {
+    Ok(render_colon_hex(
+        &OctetString::take_from(cons)?.to_bytes(),
+        format,
+    ))
+}
+
+/// RFC 5280, section 4.2.1.1
+fn take_authority_key_identifier(
+    cons: &mut Constructed<bytes::Bytes>,
+    format: Format,
+) -> Result<String, bcder::decode::Error> {
+    cons.take_sequence(|cons| {
+        let mut table = Vec::new();
+        if let Some(key_id) = cons.take_opt_value_if(Tag::CTX_0, |content| {
+            Ok(render_colon_hex(
+                &content.as_primitive()?.take_all()?,
+                format,
+            ))
+        })? {
+            table.push(("Key identifier".to_string(), key_id));
+        }
+        if let Some(issuer) = cons.take_opt_constructed_if(Tag::CTX_1, |cons| {
+            let mut names = Vec::new();
+            while let Some(name) = cons.take_opt_value(|tag, content| {
+                let bytes = content.as_primitive()?.take_all()?;
+                Ok(if tag == Tag::CTX_6 {
+                    render_uri(&String::from_utf8_lossy(&bytes), format)
+                } else {
+                    bytes.render_as(format)
+                })
+            })? {
+                names.push(name);
+            }
+            Ok(render_array(names.into_iter(), format))
+        })? {
+            table.push(("Issuer".to_string(), issuer));
+        }
+        if let Some(serial) = cons.take_opt_value_if(Tag::CTX_2, |content| {
+            Ok(render_colon_hex(
+                &content.as_primitive()?.take_all()?,
+                format,
+            ))
+        })? {
+            table.push(("Issuer serial number".to_string(), serial));
+        }
+        Ok(render_kv_table(table.into_iter(), format))
+    })
+}
+
+/// RFC 5280, section 4.2.1.4
+fn take_certificate_policies(
+    cons: &mut Constructed<bytes::Bytes>,
+    format: Format,
+) -> Result<String, bcder::decode::Error> {
+    cons.take_sequence(|cons| {
+        let mut validation = Vec::new();
+        let mut table = Vec::new();
+        while let Some(row) = cons.take_opt_sequence(|cons| {
+            let policy = Oid::take_from(cons)?;
+            if let Some((_, level)) = VALIDATION_POLICIES
+                .iter()
+                .find(|(oid, _)| *oid == policy.as_ref())
+            {
+                validation.push(*level);
+            }
+            let qualifiers = cons.take_opt_sequence(|cons| {
+                let mut qualifiers = Vec::new();
+                while let Some(qualifier) =
+                    cons.take_opt_sequence(|cons| take_policy_qualifier(cons, format))?
+                {
+                    qualifiers.push(qualifier);
+                }
+                Ok(render_kv_table(qualifiers.into_iter(), format))
+            })?;
+            let qualifiers = qualifiers.unwrap_or_else(|| format.empty("(none)"));
+            Ok((policy.render_as(format), qualifiers))
+        })? {
+            table.push(row);
+        }
+        if !validation.is_empty() {
+            table.insert(0, ("Validation".to_string(), validation.join(", ")));
+        }
+        Ok(render_kv_table(table.into_iter(), format))
+    })
+}
+
+/// RFC 6962, section 3.1. The value is always NULL.
+fn take_ct_poison(
+    cons: &mut Constructed<bytes::Bytes>,
+    format: Format,
+) -> Result<String, bcder::decode::Error> {
+    cons.take_primitive_if(Tag::NULL, |prim| prim.skip_all())?;
+    let text = "This is a precertificate, which can't be used as a certificate. It was logged to get SCTs for the final certificate.";
+    Ok(match format {
+        Format::Html => format!(r#"<strong class="bvcert-poison">{}</strong>"#, text),
+        Format::Text => text.to_string(),
+    })
+}
+
+fn take_policy_qualifier(
+    cons: &mut Constructed<bytes::Bytes>,
+    format: Format,
+) -> Result<(String, String), bcder::decode::Error> {
+    let id = Oid::take_from(cons)?;
+    let value = if id.as_ref() == OID_QUALIFIER_CPS {
+        let uri = Ia5String::take_from(cons)?;
+        render_uri(&String::from_utf8_lossy(&uri.to_bytes()), format)
+    } else {
+        take_cons(cons, format)?
+    };
+    Ok((id.render_as(format), value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use x509_certificate::X509Certificate;
+
+    fn render_ext(cert: &[u8], oid: &[u8]) -> String {
+        let cert = X509Certificate::from_der(cert).unwrap();
+        let cert: &x509_certificate::rfc5280::Certificate = cert.as_ref();
+        cert.tbs_certificate
+            .extensions
+            .as_ref()
+            .unwrap()
+            .iter()
+            .find(|ext| ext.id.as_ref() == oid)
+            .unwrap()
+            .render()
+    }
+
+    #[test]
+    fn extension_names() {
+        let cert =
+            X509Certificate::from_der(include_bytes!("../../test_certs/policesf.der")).unwrap();
+        let cert: &x509_certificate::rfc5280::Certificate = cert.as_ref();
+        let rendered = cert.tbs_certificate.extensions.as_ref().unwrap().render();
+        assert!(
+            rendered.contains(
+                r#"<span class="bvcert-critical"><span class="bvcert-oid" data-oid="2.5.29.15" title="2.5.29.15">Key Usage</span> (critical)</span>"#
+            ),
+            "{}",
+            rendered
+        );
+        assert!(rendered.contains(
+            r#"<span class="bvcert-noncritical"><span class="bvcert-oid" data-oid="2.5.29.17" title="2.5.29.17">Subject Alternative Name</span></span>"#
+        ));
+    }
+
+    #[test]
+    fn subject_alt_names() {
+        let rendered = render_ext(
+            include_bytes!("../../test_certs/othername.der"),
+            OID_SUBJECT_ALT_NAME,
+        );
+        assert!(
+            rendered.contains(&"smartcard.example".html_escape()),
+            "{}",
+            rendered
+        );
+        assert!(rendered.contains(&"user@corp.example".html_escape()));
+        assert!(rendered.contains(r#"data-oid="1.3.6.1.4.1.311.20.2.3""#));
+        assert!(rendered.contains(&"CN=Test User, O=Example Corp".html_escape()));
+        let rendered = render_ext(
+            include_bytes!("../../test_certs/haplorrhini.der"),
+            OID_SUBJECT_ALT_NAME,
+        );
+        assert!(rendered.contains(&"2600:1901:0:631b::".html_escape()));
+    }
+
+    #[test]
+    fn authority_info_access() {
+        let rendered = render_ext(
+            include_bytes!("../../test_certs/policesf.der"),
+            OID_AUTHORITY_INFO_ACCESS,
+        );
+        assert!(rendered.contains(">ocsp</span>"), "{}", rendered);
+        assert!(rendered.contains(">caIssuers</span>"), "{}", rendered);
+        assert!(rendered.contains(&format!(
+            r#"href="{}""#,
+            "http://r3.o.lencr.org".html_escape()
+        )));
+        assert!(!rendered.contains("Unparsed"));
+    }
+
+    #[test]
+    fn certificate_policies() {
+        let rendered = render_ext(
+            include_bytes!("../../test_certs/policesf.der"),
+            OID_CERTIFICATE_POLICIES,
+        );
+        assert!(rendered.contains("Domain validated (DV)"), "{}", rendered);
+        assert!(rendered.contains(">domainValidated</span>"));
+        assert!(rendered.contains(">cps</span>"));
+        assert!(rendered.contains(&format!(
+            r#"href="{}""#,
+            "http://cps.letsencrypt.org".html_escape()
+        )));
+
+        let rendered = render_ext(
+            include_bytes!("../../test_certs/alphassl.der"),
+            OID_CERTIFICATE_POLICIES,
+        );
+        assert!(
+            rendered.contains("Extended validation (EV)"),
+            "{}",
+            rendered
+        );
+        assert!(!rendered.contains("Unparsed"));
+    }
+
+    #[test]
+    fn embedded_scts() {
+        let rendered = render_ext(
+            include_bytes!("../../test_certs/policesf.der"),
+            OID_EMBEDDED_SCTS,
+        );
+        assert!(!rendered.contains("Unparsed"), "{}", rendered);
+        assert!(!rendered.contains("Unknown log"), "{}", rendered);
+        // 6F:53:76:AC:...
+        assert!(rendered.contains(&format!(
+            r#"title="{}""#,
+            "b1N2rDHwMRnYmQCkURX/dxUcEdkCwQApBo2yCJo32RM=".html_escape()
+        )));
+        assert!(rendered.contains(r#"<time datetime="2022-07-11T13:07:39.820Z">"#));
+    }
+
+    #[test]
+    fn key_identifiers() {
+        let cert = include_bytes!("../../test_certs/policesf.der");
+        assert_eq!(
+            render_ext(cert, OID_SUBJECT_KEY_IDENTIFIER),
+            r#"<code class="bvcert-key-id">03:43:1E:C0:3E:DA:B4:05:DF:59:A4:CA:26:C8:DF:38:01:4E:54:28</code>"#
+        );
+        let rendered = render_ext(cert, OID_AUTHORITY_KEY_IDENTIFIER);
+        assert!(rendered.contains("Key identifier"), "{}", rendered);
+        assert!(rendered.contains(
+            r#"<code class="bvcert-key-id">14:2E:B3:17:B7:58:56:CB:AE:50:09:40:E6:1F:AF:9D:8B:14:C2:C6</code>"#
+        ));
+    }
+
+    #[test]
+    fn ct_poison() {
+        let rendered = render_ext(
+            include_bytes!("../../test_certs/poisoned.der"),
+            OID_CT_POISON,
+        );
+        assert!(rendered.contains("bvcert-poison"), "{}", rendered);
+    }
+
+    #[test]
+    fn uri_schemes() {
+        assert!(render_uri("https://example.com/", Format::Html).starts_with("<a "));
+        assert!(!render_uri("javascript:alert(1)", Format::Html).contains("href"));
     }
 }
@@ -1,6 +1,8 @@
 // SPDX-License-Identifier: Apache-2.0
 //! Rendering of various CT-related things.
 
+use html_escape::HtmlEscapable;
+use std::fmt;
 use x509_certificate::{certificate::X509Certificate, rfc5280::Certificate};
 
 mod arrays;
@@ -8,156 +10,300 @@ pub(crate) mod ber;
 mod extensions;
 pub mod html_escape;
 mod oid;
+mod sct;
 mod strings;
 mod time;
 
-/// Render a key-value table.
-fn render_kv_table(rows: impl Iterator<Item = (String, String)>) -> String {
-    let rows_html = rows
-        .map(|(k, v)| {
-            format!(
-                r#"<tr><th><span class="bvcert-kv-th-text">{}</span></th><td>{}</td></tr>"#,
-                k, v
-            )
-        })
-        .fold(String::new(), |a, b| a + &b);
-    if rows_html.is_empty() {
-        r#"<span class="bvcert-empty">(empty)</span>"#.to_string()
+/// What things are rendered as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// HTML, styled by `bvcert.css`.
+    Html,
+    /// Indented plain text, for pasting into places that don't support HTML. Key-value tables
+    /// become `key: value` lines, with nested tables indented under their key.
+    Text,
+}
+
+impl Format {
+    /// Escapes text for this format. In plain text, control characters are escaped so values
+    /// stay on one line.
+    fn escape(self, text: impl fmt::Display) -> String {
+        match self {
+            Self::Html => text.html_escape(),
+            Self::Text => text
+                .to_string()
+                .chars()
+                .map(|khar| {
+                    if khar.is_control() {
+                        khar.escape_default().to_string()
+                    } else {
+                        khar.to_string()
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Renders a placeholder for a missing value, like `(none)`.
+    fn empty(self, text: &str) -> String {
+        match self {
+            Self::Html => format!(r#"<span class="bvcert-empty">{}</span>"#, text),
+            Self::Text => text.to_string(),
+        }
+    }
+}
+
+/// Render a key-value table. As plain text, tables are whole lines, so a value that ends with a
+/// newline is a nested table.
+fn render_kv_table(rows: impl Iterator<Item = (String, String)>, format: Format) -> String {
+    let rendered = match format {
+        Format::Html => {
+            let rows_html = rows
+                .map(|(k, v)| {
+                    format!(
+                        r#"<tr><th><span class="bvcert-kv-th-text">{}</span></th><td>{}</td></tr>"#,
+                        k, v
+                    )
+                })
+                .fold(String::new(), |a, b| a + &b);
+            if rows_html.is_empty() {
+                rows_html
+            } else {
+                format!(r#"<table class="bvcert-kv-table">{}</table>"#, rows_html)
+            }
+        }
+        Format::Text => rows
+            .map(|(k, v)| {
+                if v.ends_with('\n') {
+                    let indented: String =
+                        v.lines().map(|line| format!("    {}\n", line)).collect();
+                    format!("{}:\n{}", k, indented)
+                } else {
+                    format!("{}: {}\n", k, v)
+                }
+            })
+            .collect(),
+    };
+    if rendered.is_empty() {
+        format.empty("(empty)")
     } else {
-        format!(r#"<table class="bvcert-kv-table">{}</table>"#, rows_html)
+        rendered
     }
 }
 
-fn render_array(rows: impl Iterator<Item = String>) -> String {
+fn render_array(rows: impl Iterator<Item = String>, format: Format) -> String {
     render_kv_table(
         rows.enumerate()
             .map(|(idx, val)| (format!("{}.", idx), val)),
+        format,
     )
 }
 
 pub trait Render {
-    fn render(&self) -> String;
+    /// Renders as HTML.
+    fn render(&self) -> String {
+        self.render_as(Format::Html)
+    }
+
+    fn render_as(&self, format: Format) -> String;
 }
 
 impl<T> Render for Option<T>
 where
     T: Render,
 {
-    fn render(&self) -> String {
+    fn render_as(&self, format: Format) -> String {
         match self {
-            Some(val) => val.render(),
-            None => r#"<span class="bvcert-empty">(none)</span>"#.to_string(),
+            Some(val) => val.render_as(format),
+            None => format.empty("(none)"),
         }
     }
 }
 
 impl Render for X509Certificate {
-    fn render(&self) -> String {
+    fn render_as(&self, format: Format) -> String {
         let cert: &Certificate = self.as_ref();
-        cert.render()
+        cert.render_as(format)
     }
 }
 
 impl Render for Certificate {
-    fn render(&self) -> String {
+    fn render_as(&self, format: Format) -> String {
         render_kv_table(
             [
                 (
                     "Signed certificate".to_string(),
-                    self.tbs_certificate.render(),
+                    self.tbs_certificate.render_as(format),
                 ),
                 (
                     "Signature algorithm".to_string(),
-                    self.signature_algorithm.render(),
+                    self.signature_algorithm.render_as(format),
                 ),
-                ("Signature".to_string(), self.signature.render()),
+                ("Signature".to_string(), self.signature.render_as(format)),
             ]
             .into_iter(),
+            format,
         )
     }
 }
 
 impl Render for x509_certificate::rfc5280::TbsCertificate {
-    fn render(&self) -> String {
+    fn render_as(&self, format: Format) -> String {
         let mut table = vec![
-            ("Version".to_string(), self.version.render()),
-            ("Serial number".to_string(), self.serial_number.render()),
-            ("Signature algorithm".to_string(), self.signature.render()),
-            ("Issuer".to_string(), self.issuer.render()),
-            ("Validity".to_string(), self.validity.render()),
-            ("Subject".to_string(), self.subject.render()),
+            ("Version".to_string(), self.version.render_as(format)),
+            (
+                "Serial number".to_string(),
+                self.serial_number.render_as(format),
+            ),
+            (
+                "Signature algorithm".to_string(),
+                self.signature.render_as(format),
+            ),
+            ("Issuer".to_string(), self.issuer.render_as(format)),
+            ("Validity".to_string(), self.validity.render_as(format)),
+            ("Subject".to_string(), self.subject.render_as(format)),
             (
                 "Subject public key".to_string(),
-                self.subject_public_key_info.render(),
+                self.subject_public_key_info.render_as(format),
             ),
         ];
         if let Some(val) = &self.issuer_unique_id {
-            table.push(("Issuer ID".to_string(), val.render()));
+            table.push(("Issuer ID".to_string(), val.render_as(format)));
         }
         if let Some(val) = &self.subject_unique_id {
-            table.push(("Subject ID".to_string(), val.render()));
+            table.push(("Subject ID".to_string(), val.render_as(format)));
         }
         if let Some(val) = &self.extensions {
-            table.push(("Extensions".to_string(), val.render()));
+            table.push(("Extensions".to_string(), val.render_as(format)));
         }
-        render_kv_table(table.into_iter())
+        render_kv_table(table.into_iter(), format)
     }
 }
 
 impl Render for x509_certificate::rfc3280::AttributeTypeAndValue {
-    fn render(&self) -> String {
-        render_kv_table(
-            [
-                ("Type".to_string(), self.typ.render()),
-                ("Value".to_string(), ber::render_ber((**self.value).clone())),
-            ]
-            .into_iter(),
-        )
-    }
-}
-
-impl Render for x509_certificate::rfc5280::Validity {
-    fn render(&self) -> String {
+    fn render_as(&self, format: Format) -> String {
         render_kv_table(
             [
-                ("Not before".to_string(), self.not_before.render()),
-                ("Not after".to_string(), self.not_after.render()),
+                ("Type".to_string(), self.typ.render_as(format)),
+                (
+                    "Value".to_string(),
+                    ber::render_ber((**self.value).clone(), format),
+                ),
             ]
             .into_iter(),
+            format,
         )
     }
 }
 
 impl Render for x509_certificate::rfc5280::Version {
-    fn render(&self) -> String {
+    fn render_as(&self, _format: Format) -> String {
         format!("{:?}", self) // V1/V2/V3
     }
 }
 
 impl Render for x509_certificate::rfc5280::AlgorithmIdentifier {
-    fn render(&self) -> String {
-        let mut table = vec![("Algorithm".to_string(), self.algorithm.render())];
+    fn render_as(&self, format: Format) -> String {
+        let mut table = vec![("Algorithm".to_string(), self.algorithm.render_as(format))];
         if let Some(params) = &self.parameters {
             table.push((
                 "Algorithm identifier".to_string(),
-                ber::render_ber((***params).clone()),
+                ber::render_ber((***params).clone(), format),
             ));
         }
-        render_kv_table(table.into_iter())
+        render_kv_table(table.into_iter(), format)
     }
 }
 
 impl Render for x509_certificate::rfc5280::SubjectPublicKeyInfo {
-    fn render(&self) -> String {
+    fn render_as(&self, format: Format) -> String {
         render_kv_table(
             [
-                ("Algorithm".to_string(), self.algorithm.render()),
+                ("Algorithm".to_string(), self.algorithm.render_as(format)),
                 (
                     "Subject public key".to_string(),
-                    self.subject_public_key.render(),
+                    self.subject_public_key.render_as(format),
                 ),
             ]
             .into_iter(),
+            format,
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn text_kv_tables() {
+        let inner = render_kv_table(
+            [
+                ("Algorithm".to_string(), "RSA (2048)".to_string()),
+                (
+                    "Empty".to_string(),
+                    None::<bytes::Bytes>.render_as(Format::Text),
+                ),
+            ]
+            .into_iter(),
+            Format::Text,
+        );
+        let text = render_kv_table(
+            [
+                ("Version".to_string(), "V3".to_string()),
+                ("Key".to_string(), inner),
+                (
+                    "Serial".to_string(),
+                    bytes::Bytes::from(vec![0xAB; 40]).render_as(Format::Text),
+                ),
+                (
+                    "Nothing".to_string(),
+                    render_kv_table(std::iter::empty(), Format::Text),
+                ),
+            ]
+            .into_iter(),
+            Format::Text,
+        );
+        assert_eq!(
+            text,
+            format!(
+                "Version: V3\nKey:\n    Algorithm: RSA (2048)\n    Empty: (none)\nSerial: {}\nNothing: (empty)\n",
+                "AB".repeat(40)
+            )
+        );
+        assert_eq!(Format::Text.escape("a\nb\u{1b}"), "a\\nb\\u{1b}");
+    }
+
+    #[test]
+    fn text_cert() {
+        let cert =
+            X509Certificate::from_der(include_bytes!("../../test_certs/policesf.der")).unwrap();
+        let text = cert.render_as(Format::Text);
+        assert!(
+            text.starts_with("Signed certificate:\n    Version: V3\n"),
+            "{}",
+            text
+        );
+        assert!(!text.contains('<'), "{}", text);
+        assert!(
+            text.contains("Subject Alternative Name (2.5.29.17):\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("Key Usage (2.5.29.15) (critical):"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("ocsp (1.3.6.1.5.5.7.48.1): http://r3.o.lencr.org"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("Timestamp: July 11, 2022, 13:07:39"),
+            "{}",
+            text
+        );
+    }
+}
@@ -3,59 +3,88 @@
 #![allow(clippy::mutable_key_type)]
 
 use bcder::oid::Oid;
+use log::warn;
 use std::collections::HashMap;
 
 mod parse;
 
-use super::{html_escape::HtmlEscapable, Render};
+use super::{html_escape::HtmlEscapable, Format, Render};
 
 lazy_static::lazy_static! {
-    static ref COMMON_OIDS: HashMap<Oid<bytes::Bytes>, String> = {
-        let mut hm = HashMap::new();
-        {
-            let oid_data = include_str!("oid/dumpasn1.txt");
-            let mut oid = None;
-            for line in oid_data.lines() {
-                if line.is_empty() || line.starts_with('#') { continue; }
-                let mut parts = line.split(" = ");
-                match parts.next().unwrap() {
-                    "OID" => oid = Some(parse::parse_oid(parts.next().unwrap())),
-                    "Description" => {
-                        let desc = parts.next().unwrap().to_string();
-                        hm.insert(oid.unwrap(), desc);
-                        oid = None;
-                    }
-                    "Comment" | "Warning" => {},
-                    p => panic!("invalid dumpasn1 data: prefix of {}", p),
+    static ref COMMON_OIDS: HashMap<Oid<bytes::Bytes>, String> =
+        load_oids(include_str!("oid/dumpasn1.txt"), include_str!("oid/oids.txt"));
+}
+
+/// Builds the OID name table. Invalid lines are logged and skipped, so a bad line in the data files
+/// can't cause a panic when rendering.
+fn load_oids(dumpasn1_data: &str, oids_data: &str) -> HashMap<Oid<bytes::Bytes>, String> {
+    let mut hm = HashMap::new();
+    let mut oid = None;
+    for line in dumpasn1_data.lines() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.splitn(2, " = ");
+        match (parts.next(), parts.next()) {
+            (Some("OID"), Some(val)) => match parse::parse_oid(val) {
+                Ok(parsed) => oid = Some(parsed),
+                Err(e) => {
+                    warn!("invalid OID {:?} in dumpasn1 data: {}", val, e);
+                    oid = None;
+                }
+            },
+            (Some("Description"), Some(desc)) => {
+                // if the OID was invalid, its description is skipped too
+                if let Some(oid) = oid.take() {
+                    hm.insert(oid, desc.to_string());
                 }
             }
+            (Some("Comment" | "Warning"), _) => {}
+            _ => warn!("invalid line in dumpasn1 data: {:?}", line),
         }
-        {
-            let oid_data = include_str!("oid/oids.txt");
-            for line in oid_data.lines() {
-                if line.is_empty() || line.starts_with('#') { continue; }
-                let mut parts = line.split('=');
-                hm.insert(parse::parse_oid(parts.next().unwrap()), parts.next().unwrap().to_string());
+    }
+    for line in oids_data.lines() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = line
+            .split_once('=')
+            .ok_or("no =")
+            .and_then(|(oid, name)| Ok((parse::parse_oid(oid)?, name)));
+        match parsed {
+            Ok((oid, name)) => {
+                hm.insert(oid, name.to_string());
             }
+            Err(e) => warn!("invalid line {:?} in OID data: {}", line, e),
         }
-        hm
-    };
+    }
+    hm
+}
+
+/// Renders an OID, showing `name` if it has one.
+pub(crate) fn render_named_oid(
+    oid: &Oid<bytes::Bytes>,
+    name: Option<&str>,
+    format: Format,
+) -> String {
+    match (format, name) {
+        (Format::Html, Some(name)) => format!(
+            r#"<span class="bvcert-oid" data-oid="{oid}" title="{oid}">{name}</span>"#,
+            oid = oid.html_escape(),
+            name = name,
+        ),
+        (Format::Html, None) => format!(
+            r#"<span class="bvcert-oid" data-oid="{oid}">{oid}</span>"#,
+            oid = oid.html_escape()
+        ),
+        (Format::Text, Some(name)) => format!("{} ({})", name, oid),
+        (Format::Text, None) => oid.to_string(),
+    }
 }
 
 impl Render for Oid<bytes::Bytes> {
-    fn render(&self) -> String {
-        if let Some(val) = COMMON_OIDS.get(self) {
-            format!(
-                r#"<span class="bvcert-oid" data-oid="{oid}" title="{oid}">{name}</span>"#,
-                oid = self.html_escape(),
-                name = val,
-            )
-        } else {
-            format!(
-                r#"<span class="bvcert-oid" data-oid="{oid}">{oid}</span>"#,
-                oid = self.html_escape()
-            )
-        }
+    fn render_as(&self, format: Format) -> String {
+        render_named_oid(self, COMMON_OIDS.get(self).map(String::as_str), format)
     }
 }
 
@@ -63,6 +92,17 @@ impl Render for Oid<bytes::Bytes> {
 mod test {
     use super::*;
 
+    #[test]
+    fn malformed_oid_data() {
+        let oids = load_oids(
+            "OID = 1 2 3\nDescription = good\nOID = 9 9\nDescription = bad\nnonsense\n",
+            "1 2 4=alsoGood\n1 x 5=bad\nno equals sign\n",
+        );
+        assert_eq!(oids.len(), 2);
+        assert_eq!(oids[&parse::parse_oid("1 2 3").unwrap()], "good");
+        assert_eq!(oids[&parse::parse_oid("1 2 4").unwrap()], "alsoGood");
+    }
+
     #[test]
     fn unknown_oid() {
         let oid = Oid(bytes::Bytes::from(&[192, 200, 50, 30][..]));
@@ -70,5 +110,6 @@ mod test {
             Render::render(&oid),
             "<span class=\"bvcert-oid\" data-oid=\"2.1057762.30\">2.1057762.30</span>".to_string()
         );
+        assert_eq!(oid.render_as(Format::Text), "2.1057762.30");
     }
 }
@@ -7,25 +7,25 @@ fn from_str(s: &str) -> Result<u32, &'static str> {
     u32::from_str(s).map_err(|_| "only integer components allowed")
 }
 
-pub fn parse_oid(arg: &str) -> Oid {
+pub fn parse_oid(arg: &str) -> Result<Oid, &'static str> {
     let mut components = arg.split(' ');
     let (first, second) = match (components.next(), components.next()) {
         (Some(first), Some(second)) => (first, second),
         _ => {
-            panic!("at least two components required");
+            return Err("at least two components required");
         }
     };
-    let first = from_str(first).unwrap();
+    let first = from_str(first)?;
     if first > 2 {
-        panic!("first component can only be 0, 1, or 2.")
+        return Err("first component can only be 0, 1, or 2.");
     }
-    let second = from_str(second).unwrap();
+    let second = from_str(second)?;
     if first < 2 && second >= 40 {
-        panic!("second component for 0. and 1. must be less than 40");
+        return Err("second component for 0. and 1. must be less than 40");
     }
     let mut res = vec![40 * first + second];
     for item in components {
-        res.push(from_str(item).unwrap());
+        res.push(from_str(item)?);
     }
 
     let mut parts: Vec<u8> = Vec::with_capacity(res.len());
@@ -46,5 +46,5 @@ pub fn parse_oid(arg: &str) -> Oid {
         }
         parts.push((item & 0x7F) as u8);
     }
-    Oid(bytes::Bytes::copy_from_slice(&parts[..]))
+    Ok(Oid(bytes::Bytes::copy_from_slice(&parts[..])))
 }
@@ -0,0 +1,105 @@
+// SPDX-License-Identifier: Apache-2.0
+//! Renders embedded SCT lists, as described in RFC 6962, section 3.3.
+use bcder::{decode::Constructed, OctetString};
+use belvi_log_list::LogList;
+use chrono::{TimeZone, Utc};
+
+use super::{html_escape::HtmlEscapable, render_array, render_kv_table, Format, Render};
+
+/// Reads the TLS encoding used for SCTs.
+struct TlsReader<'a>(&'a [u8]);
+
+impl<'a> TlsReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], bcder::decode::Error> {
+        if self.0.len() < len {
+            return Err(bcder::decode::Error::Malformed);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn take_uint(&mut self, len: usize) -> Result<u64, bcder::decode::Error> {
+        Ok(self
+            .take(len)?
+            .iter()
+            .fold(0, |acc, byte| (acc << 8) | u64::from(*byte)))
+    }
+
+    /// Takes a value prefixed by a 2-byte length.
+    fn take_vec(&mut self) -> Result<&'a [u8], bcder::decode::Error> {
+        let len = self.take_uint(2)? as usize;
+        self.take(len)
+    }
+}
+
+fn render_log(log_id: &[u8], format: Format) -> String {
+    let encoded = base64::encode(log_id);
+    match (
+        LogList::google_shared()
+            .logs()
+            .find(|log| log.log_id == encoded),
+        format,
+    ) {
+        (Some(log), Format::Html) => format!(
+            r#"<span class="bvcert-log" title="{}">{}</span>"#,
+            encoded.html_escape(),
+            log.description.html_escape()
+        ),
+        (Some(log), Format::Text) => format!("{} ({})", format.escape(&log.description), encoded),
+        (None, _) => format!("Unknown log {}", log_id.render_as(format)),
+    }
+}
+
+fn render_sct(sct: &[u8], format: Format) -> Result<String, bcder::decode::Error> {
+    let mut reader = TlsReader(sct);
+    let version = reader.take_uint(1)?;
+    if version != 0 {
+        // later versions can have a different structure
+        return Ok(format!("Unknown SCT version {}", version));
+    }
+    let log_id = reader.take(32)?;
+    let timestamp = reader.take_uint(8)?;
+    let extensions = reader.take_vec()?;
+    let _hash_algorithm = reader.take_uint(1)?;
+    let _signature_algorithm = reader.take_uint(1)?;
+    let signature = reader.take_vec()?;
+    if !reader.0.is_empty() {
+        return Err(bcder::decode::Error::Malformed);
+    }
+    let timestamp = Utc
+        .timestamp_millis_opt(
+            timestamp
+                .try_into()
+                .map_err(|_| bcder::decode::Error::Malformed)?,
+        )
+        .single()
+        .ok_or(bcder::decode::Error::Malformed)?;
+    let mut table = vec![
+        ("Log".to_string(), render_log(log_id, format)),
+        ("Timestamp".to_string(), timestamp.render_as(format)),
+    ];
+    if !extensions.is_empty() {
+        table.push(("Extensions".to_string(), extensions.render_as(format)));
+    }
+    table.push(("Signature".to_string(), signature.render_as(format)));
+    Ok(render_kv_table(table.into_iter(), format))
+}
+
+/// Takes the value of the embedded SCT list extension.
+pub fn take_sct_list(
+    cons: &mut Constructed<bytes::Bytes>,
+    format: Format,
+) -> Result<String, bcder::decode::Error> {
+    let list = OctetString::take_from(cons)?.to_bytes();
+    let mut reader = TlsReader(&list);
+    let mut scts = TlsReader(reader.take_vec()?);
+    if !reader.0.is_empty() {
+        return Err(bcder::decode::Error::Malformed);
+    }
+    let mut rendered = Vec::new();
+    while !scts.0.is_empty() {
+        rendered.push(render_sct(scts.take_vec()?, format)?);
+    }
+    Ok(render_array(rendered.into_iter(), format))
+}
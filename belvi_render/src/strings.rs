@@ -1,19 +1,22 @@
 // SPDX-License-Identifier: Apache-2.0
 use bcder::OctetString;
 
-use super::Render;
+use super::{Format, Render};
 
 impl Render for OctetString {
-    fn render(&self) -> String {
-        self.to_bytes().render()
+    fn render_as(&self, format: Format) -> String {
+        self.to_bytes().render_as(format)
     }
 }
 
 const LEN_LIMIT: usize = 30;
 
 impl Render for bytes::Bytes {
-    fn render(&self) -> String {
-        if self.len() > LEN_LIMIT {
+    fn render_as(&self, format: Format) -> String {
+        if format == Format::Text {
+            // plain text has nowhere to keep the full value, so all of it is shown
+            format!("{:X}", self)
+        } else if self.len() > LEN_LIMIT {
             format!(
                 r#"<code class="bvcert-bytes" data-full="{:X}">{:X}…</code>"#,
                 self,
@@ -26,15 +29,27 @@ impl Render for bytes::Bytes {
 }
 
 impl Render for &[u8] {
-    fn render(&self) -> String {
-        bytes::Bytes::copy_from_slice(self).render()
+    fn render_as(&self, format: Format) -> String {
+        bytes::Bytes::copy_from_slice(self).render_as(format)
     }
 }
 
 impl Render for bcder::BitString {
-    fn render(&self) -> String {
+    fn render_as(&self, format: Format) -> String {
         if self.unused() == 0 {
-            self.octet_bytes().render()
+            self.octet_bytes().render_as(format)
+        } else if self.octet_len() > LEN_LIMIT {
+            // writing out every bit would be enormous, so show the bytes instead
+            let bytes = self.octet_bytes();
+            match format {
+                Format::Html => format!(
+                    r#"<code class="bvcert-bytes" data-full="{:X}" title="last {} bits unused">{:X}…</code>"#,
+                    bytes,
+                    self.unused(),
+                    bytes.slice(0..LEN_LIMIT)
+                ),
+                Format::Text => format!("{:X} (last {} bits unused)", bytes, self.unused()),
+            }
         } else {
             let mut bits_string = self
                 .octet_bytes()
@@ -42,14 +57,17 @@ impl Render for bcder::BitString {
                 .map(|byte| format!("{:0>8b}", byte))
                 .fold(String::new(), |a, b| a + &b + " ");
             bits_string.truncate(bits_string.len() - 1 - self.unused() as usize);
-            format!(r#"<code class="bvcert-bytes">{}</code>"#, bits_string)
+            match format {
+                Format::Html => format!(r#"<code class="bvcert-bytes">{}</code>"#, bits_string),
+                Format::Text => bits_string,
+            }
         }
     }
 }
 
 impl Render for bcder::Integer {
-    fn render(&self) -> String {
-        self.as_slice().render()
+    fn render_as(&self, format: Format) -> String {
+        self.as_slice().render_as(format)
     }
 }
 
@@ -83,4 +101,22 @@ mod test {
             "<code class=\"bvcert-bytes\">6D6167696321</code>"
         );
     }
+
+    #[test]
+    fn long_bits() {
+        let bits = bcder::BitString::new(3, bytes::Bytes::from(vec![0xAB; 512]));
+        let rendered = bits.render();
+        assert_eq!(
+            rendered,
+            format!(
+                r#"<code class="bvcert-bytes" data-full="{}" title="last 3 bits unused">{}…</code>"#,
+                "AB".repeat(512),
+                "AB".repeat(LEN_LIMIT)
+            )
+        );
+        assert_eq!(
+            bits.render_as(Format::Text),
+            format!("{} (last 3 bits unused)", "AB".repeat(512))
+        );
+    }
 }
@@ -1,35 +1,120 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use super::{html_escape::HtmlEscapable, Render};
+use super::{html_escape::HtmlEscapable, render_kv_table, Format, Render};
 use x509_certificate::asn1time::Time;
 
 impl Render for x509_certificate::asn1time::UtcTime {
-    fn render(&self) -> String {
-        (**self).render() // get inner chrono::DateTime
+    fn render_as(&self, format: Format) -> String {
+        (**self).render_as(format) // get inner chrono::DateTime
     }
 }
 
 impl Render for x509_certificate::asn1time::GeneralizedTime {
-    fn render(&self) -> String {
-        self.to_string()
+    fn render_as(&self, format: Format) -> String {
+        chrono::DateTime::<chrono::Utc>::from(self.clone()).render_as(format)
     }
 }
 
 impl Render for chrono::DateTime<chrono::Utc> {
-    fn render(&self) -> String {
-        format!(
-            r#"<time datetime="{}">{}</time>"#,
-            self.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
-            self.format("%B %e, %Y, %k:%M:%S").html_escape()
+    fn render_as(&self, format: Format) -> String {
+        let text = self.format("%B %e, %Y, %k:%M:%S");
+        match format {
+            Format::Html => format!(
+                r#"<time datetime="{}">{}</time>"#,
+                self.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                text.html_escape()
+            ),
+            Format::Text => text.to_string(),
+        }
+    }
+}
+
+/// Certs that expire in less than this many days are shown as expiring soon.
+const EXPIRING_SOON_DAYS: i64 = 30;
+
+fn time_to_utc(time: &Time) -> chrono::DateTime<chrono::Utc> {
+    match time {
+        Time::UtcTime(t) => **t,
+        Time::GeneralTime(t) => t.clone().into(),
+    }
+}
+
+/// Formats a duration roughly, like "12 days" or "1 hour".
+fn format_duration(duration: chrono::Duration) -> String {
+    let (count, unit) = if duration.num_days() > 0 {
+        (duration.num_days(), "day")
+    } else if duration.num_hours() > 0 {
+        (duration.num_hours(), "hour")
+    } else {
+        (duration.num_minutes(), "minute")
+    };
+    format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" })
+}
+
+/// Renders a badge saying how long a cert is valid for, as of `now`.
+#[must_use]
+pub fn render_remaining_validity(
+    not_before: chrono::DateTime<chrono::Utc>,
+    not_after: chrono::DateTime<chrono::Utc>,
+    now: chrono::DateTime<chrono::Utc>,
+    format: Format,
+) -> String {
+    let (class, text) = if now > not_after {
+        (
+            "expired",
+            format!("expired {} ago", format_duration(now - not_after)),
+        )
+    } else if now < not_before {
+        (
+            "soon",
+            format!("not valid for {}", format_duration(not_before - now)),
+        )
+    } else {
+        let remaining = not_after - now;
+        let class = if remaining.num_days() < EXPIRING_SOON_DAYS {
+            "soon"
+        } else {
+            "ok"
+        };
+        (class, format!("expires in {}", format_duration(remaining)))
+    };
+    match format {
+        Format::Html => format!(
+            r#"<span class="bvcert-validity bvcert-validity-{}">{}</span>"#,
+            class,
+            text.html_escape()
+        ),
+        Format::Text => text,
+    }
+}
+
+impl Render for x509_certificate::rfc5280::Validity {
+    fn render_as(&self, format: Format) -> String {
+        render_kv_table(
+            [
+                ("Not before".to_string(), self.not_before.render_as(format)),
+                ("Not after".to_string(), self.not_after.render_as(format)),
+                (
+                    "Remaining".to_string(),
+                    render_remaining_validity(
+                        time_to_utc(&self.not_before),
+                        time_to_utc(&self.not_after),
+                        chrono::Utc::now(),
+                        format,
+                    ),
+                ),
+            ]
+            .into_iter(),
+            format,
         )
     }
 }
 
 impl Render for Time {
-    fn render(&self) -> String {
+    fn render_as(&self, format: Format) -> String {
         match self {
-            Time::UtcTime(t) => t.render(),
-            Time::GeneralTime(t) => t.render(),
+            Time::UtcTime(t) => t.render_as(format),
+            Time::GeneralTime(t) => t.render_as(format),
         }
     }
 }
@@ -42,11 +127,61 @@ mod test {
 
     #[test]
     fn simple_date() {
-        let date = chrono::Utc.ymd(2022, 01, 01).and_hms(00, 00, 00);
+        let date = chrono::Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
         assert_eq!(
             date.render(),
             "<time datetime=\"2022-01-01T00:00:00.000Z\">January  1&#x2C; 2022&#x2C;  0&#x3A;00&#x3A;00</time>"
                 .to_string()
         );
+        assert_eq!(date.render_as(Format::Text), "January  1, 2022,  0:00:00");
+    }
+
+    #[test]
+    fn generalized_time() {
+        let time = bcder::decode::Constructed::decode(
+            &b"\x18\x0f20500101120000Z"[..],
+            bcder::Mode::Der,
+            x509_certificate::asn1time::GeneralizedTime::take_from_allow_fractional_z,
+        )
+        .unwrap();
+        let utc_time = chrono::Utc.ymd(2050, 1, 1).and_hms(12, 0, 0);
+        assert_eq!(time.render(), utc_time.render());
+        assert_eq!(Time::GeneralTime(time).render(), utc_time.render());
+    }
+
+    #[test]
+    fn remaining_validity() {
+        let not_before = chrono::Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let not_after = chrono::Utc.ymd(2022, 4, 1).and_hms(0, 0, 0);
+        let badge = |now| render_remaining_validity(not_before, not_after, now, Format::Html);
+        assert_eq!(
+            badge(chrono::Utc.ymd(2022, 1, 2).and_hms(0, 0, 0)),
+            r#"<span class="bvcert-validity bvcert-validity-ok">expires in 89 days</span>"#
+        );
+        assert_eq!(
+            badge(chrono::Utc.ymd(2022, 3, 20).and_hms(0, 0, 0)),
+            r#"<span class="bvcert-validity bvcert-validity-soon">expires in 12 days</span>"#
+        );
+        assert_eq!(
+            badge(chrono::Utc.ymd(2022, 3, 31).and_hms(23, 0, 0)),
+            r#"<span class="bvcert-validity bvcert-validity-soon">expires in 1 hour</span>"#
+        );
+        assert_eq!(
+            badge(chrono::Utc.ymd(2022, 4, 4).and_hms(1, 0, 0)),
+            r#"<span class="bvcert-validity bvcert-validity-expired">expired 3 days ago</span>"#
+        );
+        assert_eq!(
+            badge(chrono::Utc.ymd(2021, 12, 31).and_hms(12, 0, 0)),
+            r#"<span class="bvcert-validity bvcert-validity-soon">not valid for 12 hours</span>"#
+        );
+        assert_eq!(
+            render_remaining_validity(
+                not_before,
+                not_after,
+                chrono::Utc.ymd(2022, 1, 2).and_hms(0, 0, 0),
+                Format::Text
+            ),
+            "expires in 89 days"
+        );
     }
 }